@@ -1,9 +1,9 @@
-use super::{session, Session, Store};
-use crate::{empty_body, qs_params, MarketSession};
+use super::{Session, Store};
+use crate::{empty_body, epoch, qs_params, MarketSession};
 use crate::{Product, SortOrder};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
 use http::Method;
-use session::CallbackProvider;
 use std::sync::Arc;
 use strum::EnumString;
 
@@ -19,68 +19,64 @@ where
     Self { session }
   }
 
-  pub async fn list(&self, callbacks: impl CallbackProvider) -> Result<Vec<Account>> {
+  pub async fn list(&self) -> Result<Vec<Account>> {
     let resp: AccountListResponse = self
       .session
-      .send(Method::GET, "/v1/accounts/list", empty_body(), callbacks)
+      .send(Method::GET, "/v1/accounts/list", empty_body())
       .await?;
     debug!("balance json: {}", serde_json::to_string_pretty(&resp)?);
     Ok(resp.response.accounts.account)
   }
 
-  pub async fn balance(
-    &self,
-    account_id_key: &str,
-    balance_request: BalanceRequest<'_>,
-    callbacks: impl CallbackProvider,
-  ) -> Result<BalanceResponse> {
+  pub async fn balance(&self, account_id_key: &str, balance_request: BalanceRequest<'_>) -> Result<BalanceResponse> {
     let balance: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/accounts/{}/balance", account_id_key),
         qs_params(&balance_request)?,
-        callbacks,
       )
       .await?;
     debug!("balance json: {}", serde_json::to_string_pretty(&balance)?);
     Ok(serde_json::from_value(balance.get("BalanceResponse").unwrap().clone())?)
   }
 
-  pub async fn portfolio(
-    &self,
-    account_id_key: &str,
-    params: PortfolioRequest,
-    callbacks: impl CallbackProvider,
-  ) -> Result<PortfolioResponse> {
+  pub async fn portfolio(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    params.validate()?;
     let portfolio: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/accounts/{}/portfolio", account_id_key),
         qs_params(&params)?,
-        callbacks,
       )
       .await?;
     debug!("portfolio json: {}", serde_json::to_string_pretty(&portfolio)?);
-    Ok(serde_json::from_value(
-      portfolio.get("PortfolioResponse").unwrap().clone(),
-    )?)
+    let response: PortfolioResponse = serde_json::from_value(portfolio.get("PortfolioResponse").unwrap().clone())?;
+    let view = params.view.unwrap_or_default();
+    for position in response.account_portfolio.iter().flat_map(|p| &p.position) {
+      if let Some(kind) = position
+        .view
+        .as_ref()
+        .map(PositionView::kind)
+        .filter(|kind| *kind != view)
+      {
+        warn!(
+          "position {} has the {:?} view instead of the requested {:?}",
+          position.position_id, kind, view
+        );
+      }
+    }
+    Ok(response)
   }
 
-  pub async fn position_lots(
-    &self,
-    account_id_key: &str,
-    position_id: &str,
-    callbacks: impl CallbackProvider,
-  ) -> Result<PositionLotsResponse> {
+  pub async fn position_lots(&self, account_id_key: &str, position_id: &str) -> Result<PositionLotsResponse> {
     let portfolio: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/accounts/{}/portfolio/{}", account_id_key, position_id),
         empty_body(),
-        callbacks,
       )
       .await?;
     debug!("position lots json: {}", serde_json::to_string_pretty(&portfolio)?);
@@ -94,6 +90,8 @@ where
 #[serde(rename_all = "camelCase", default)]
 pub struct PortfolioRequest {
   pub count: Option<usize>,
+  /// The page to list, starting at 1, see [`PortfolioResponse::next_page`].
+  pub page_number: Option<usize>,
   pub sort_by: Option<PortfolioColumn>,
   pub sort_order: Option<SortOrder>,
   pub market_session: Option<MarketSession>,
@@ -102,6 +100,17 @@ pub struct PortfolioRequest {
   pub view: Option<PortfolioView>,
 }
 
+impl PortfolioRequest {
+  /// Fails when the column to sort by isn't in the view, which is the quick view when there is none.
+  pub fn validate(&self) -> Result<()> {
+    let view = self.view.unwrap_or_default();
+    match self.sort_by {
+      Some(column) if !view.has_column(column) => Err(anyhow!("can't sort the {:?} view by {:?}", view, column)),
+      _ => Ok(()),
+    }
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase", default)]
 pub struct BalanceRequest<'a> {
@@ -122,7 +131,7 @@ impl<'a> Default for BalanceRequest<'a> {
   }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum AccountType {
   #[serde(rename = "AMMCHK")]
@@ -295,9 +304,122 @@ pub enum AccountType {
   Varcd,
   #[serde(rename = "VARIRACD")]
   Variracd,
+  /// A type this crate doesn't know about yet, with the value E*Trade sent so it serializes back
+  /// unchanged.
+  #[serde(untagged)]
+  #[strum(default)]
+  Unknown(String),
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+impl Default for AccountType {
+  fn default() -> Self {
+    AccountType::Unknown(String::new())
+  }
+}
+
+impl AccountType {
+  /// Individual retirement accounts, retirement plans and the certificates of deposit held in an IRA.
+  pub fn is_retirement(&self) -> bool {
+    use AccountType::*;
+    matches!(
+      self,
+      Benfira
+        | Benfrothira
+        | BenfEstateIra
+        | BenfMinorIra
+        | BenfRothEstateIra
+        | BenfRothMinorIra
+        | BenfRothTrustIra
+        | BenfTrustIra
+        | Contributory
+        | ConversionRothIra
+        | IndividualK
+        | IraRollover
+        | Ira
+        | Iracd
+        | MoneyPurchase
+        | Prefiracd
+        | ProfitSharing
+        | Rothira
+        | RothIndividualK
+        | RothIraMinors
+        | Sarsepira
+        | Sepira
+        | SimpleIra
+        | TrdIraMinors
+        | Variracd
+    )
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum AccountMode {
+  #[serde(rename = "CASH")]
+  Cash,
+  #[serde(rename = "MARGIN")]
+  Margin,
+  #[serde(rename = "IRA")]
+  Ira,
+  #[serde(rename = "CHECKING")]
+  Checking,
+  #[serde(rename = "SAVINGS")]
+  Savings,
+  #[serde(rename = "CD")]
+  Cd,
+  /// A mode this crate doesn't know about yet, with the value E*Trade sent so it serializes back
+  /// unchanged.
+  #[serde(untagged)]
+  #[strum(default)]
+  Unknown(String),
+}
+
+impl Default for AccountMode {
+  fn default() -> Self {
+    AccountMode::Unknown(String::new())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum AccountStatus {
+  #[serde(rename = "ACTIVE")]
+  Active,
+  #[serde(rename = "CLOSED")]
+  Closed,
+  /// A status this crate doesn't know about yet, with the value E*Trade sent so it serializes back
+  /// unchanged.
+  #[serde(untagged)]
+  #[strum(default)]
+  Unknown(String),
+}
+
+impl Default for AccountStatus {
+  fn default() -> Self {
+    AccountStatus::Unknown(String::new())
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum InstitutionType {
+  #[serde(rename = "BROKERAGE")]
+  Brokerage,
+  /// An institution this crate doesn't know about yet, with the value E*Trade sent so it serializes back
+  /// unchanged.
+  #[serde(untagged)]
+  #[strum(default)]
+  Unknown(String),
+}
+
+impl Default for InstitutionType {
+  fn default() -> Self {
+    InstitutionType::Unknown(String::new())
+  }
+}
+
+/// The view of a portfolio request, it decides the [`PositionView`] of the positions and the columns to sort by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum PortfolioView {
   #[serde(rename = "PERFORMANCE")]
@@ -306,13 +428,34 @@ pub enum PortfolioView {
   Fundamental,
   #[serde(rename = "OPTIONSWATCH")]
   Optionswatch,
+  #[default]
   #[serde(rename = "QUICK")]
   Quick,
   #[serde(rename = "COMPLETE")]
   Complete,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+impl PortfolioView {
+  /// Whether the positions of the view have the column, the columns of the position itself are in every view and
+  /// the complete view has them all.
+  pub fn has_column(&self, column: PortfolioColumn) -> bool {
+    use PortfolioColumn::*;
+    let quote = matches!(column, LastTrade | LastTradeTime);
+    let change = matches!(column, PriceChange | PriceChangePct);
+    let in_view = match self {
+      PortfolioView::Complete => true,
+      PortfolioView::Performance => quote || change,
+      PortfolioView::Fundamental => {
+        quote || change || matches!(column, PeRatio | Eps | Dividend | DivYield | Marketcap | Week52Range)
+      }
+      PortfolioView::Optionswatch => quote || matches!(column, BaseSymbolPrice | Premium | Bi | Ask),
+      PortfolioView::Quick => quote || change || matches!(column, Volume),
+    };
+    in_view || column.is_position_column()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum PortfolioColumn {
   #[serde(rename = "SYMBOL")]
@@ -485,6 +628,43 @@ pub enum PortfolioColumn {
   ExpandCollapseFlag,
 }
 
+impl PortfolioColumn {
+  /// Whether the column is a field of the position rather than of its view.
+  pub fn is_position_column(&self) -> bool {
+    use PortfolioColumn::*;
+    matches!(
+      self,
+      Symbol
+        | TypeName
+        | ExchangeName
+        | Currency
+        | Quantity
+        | LongOrShort
+        | DateAcquired
+        | Pricepaid
+        | TotalGain
+        | TotalGainPct
+        | MarketValue
+        | OptionType
+        | StrikePrice
+        | Expiration
+        | DaysGain
+        | Commission
+        | TotalCost
+        | DaysGainPct
+        | PctOfPortfolio
+        | SymbolDesc
+        | OtherFees
+        | HeldAs
+        | CostPershare
+        | TypeCode
+        | DisplaySymbol
+        | AdjNonadjFlag
+        | ExpandCollapseFlag
+    )
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 struct AccountListResponse {
   #[serde(rename = "AccountListResponse")]
@@ -510,13 +690,33 @@ pub struct Account {
   pub inst_no: Option<i32>,
   pub account_id: String,
   pub account_id_key: String,
-  pub account_mode: String,
+  pub account_mode: AccountMode,
   pub account_desc: String,
   pub account_name: String,
-  pub account_type: String,
-  pub institution_type: String,
-  pub account_status: String,
-  pub closed_date: i64,
+  pub account_type: AccountType,
+  pub institution_type: InstitutionType,
+  pub account_status: AccountStatus,
+  /// Sent as epoch seconds, with 0 for accounts that are open.
+  #[serde(with = "epoch::seconds")]
+  pub closed_date: Option<DateTime<Utc>>,
+}
+
+impl Account {
+  pub fn is_retirement(&self) -> bool {
+    self.account_mode == AccountMode::Ira || self.account_type.is_retirement()
+  }
+
+  pub fn is_margin(&self) -> bool {
+    self.account_mode == AccountMode::Margin
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.account_status == AccountStatus::Closed || self.closed_date.is_some()
+  }
+
+  pub fn is_brokerage(&self) -> bool {
+    self.institution_type == InstitutionType::Brokerage
+  }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -666,6 +866,15 @@ pub struct PortfolioResponse {
   pub account_portfolio: Vec<AccountPortfolio>,
 }
 
+impl PortfolioResponse {
+  /// The number of the page after this one, `None` for the last page.
+  pub fn next_page(&self) -> Option<usize> {
+    let portfolio = self.account_portfolio.last()?;
+    let next: usize = portfolio.next_page_no.parse().ok()?;
+    Some(next).filter(|n| *n > 1 && *n <= portfolio.total_no_of_pages.max(0) as usize)
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct PositionLotsResponse {
@@ -704,7 +913,9 @@ pub struct PortfolioPosition {
   pub product: Product,
   pub osi_key: String,
   pub symbol_description: String,
-  pub date_acquired: i64,
+  /// Sent as epoch milliseconds.
+  #[serde(with = "epoch::millis")]
+  pub date_acquired: Option<DateTime<Utc>>,
   pub price_paid: f64,
   pub price: f64,
   pub commissions: f64,
@@ -730,22 +941,106 @@ pub struct PortfolioPosition {
   #[serde(rename = "dateTimeUTC")]
   pub date_time_utc: i64,
   pub adj_prev_close: f64,
-  #[serde(rename = "Performance", skip_serializing_if = "Option::is_none")]
-  pub performance: Option<PerformanceView>,
-  #[serde(rename = "Fundamental", skip_serializing_if = "Option::is_none")]
-  pub fundamental: Option<FundamentalView>,
-  #[serde(rename = "OptionsWatch", skip_serializing_if = "Option::is_none")]
-  pub options_watch: Option<OptionsWatchView>,
-  #[serde(rename = "Quick", skip_serializing_if = "Option::is_none")]
-  pub quick: Option<QuickView>,
-  #[serde(rename = "Complete", skip_serializing_if = "Option::is_none")]
-  pub complete: Option<CompleteView>,
+  /// The view of the request, `None` when E*Trade didn't send one.
+  #[serde(flatten, deserialize_with = "deserialize_view")]
+  pub view: Option<PositionView>,
   pub lots_details: String,
   pub quote_details: String,
   #[serde(skip_serializing_if = "Vec::is_empty")]
   pub position_lot: Vec<PositionLot>,
 }
 
+/// The view fields a position can have. A flattened `Option<PositionView>` would turn a view that fails to
+/// deserialize into `None`, reading the fields one by one reports the error.
+#[derive(Deserialize)]
+struct PositionViews {
+  #[serde(rename = "Performance")]
+  performance: Option<PerformanceView>,
+  #[serde(rename = "Fundamental")]
+  fundamental: Option<FundamentalView>,
+  #[serde(rename = "OptionsWatch")]
+  options_watch: Option<OptionsWatchView>,
+  #[serde(rename = "Quick")]
+  quick: Option<QuickView>,
+  #[serde(rename = "Complete")]
+  complete: Option<Box<CompleteView>>,
+}
+
+fn deserialize_view<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<PositionView>, D::Error> {
+  let views = <PositionViews as serde::Deserialize>::deserialize(deserializer)?;
+  let mut views = [
+    views.performance.map(PositionView::Performance),
+    views.fundamental.map(PositionView::Fundamental),
+    views.options_watch.map(PositionView::OptionsWatch),
+    views.quick.map(PositionView::Quick),
+    views.complete.map(PositionView::Complete),
+  ]
+  .into_iter()
+  .flatten();
+  let view = views.next();
+  match views.next() {
+    Some(other) => Err(serde::de::Error::custom(format!(
+      "a position has both the {:?} and the {:?} view",
+      view.map(|v| v.kind()),
+      other.kind()
+    ))),
+    None => Ok(view),
+  }
+}
+
+/// The quote data of a position, as chosen by the [`PortfolioView`] of the request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum PositionView {
+  Performance(PerformanceView),
+  Fundamental(FundamentalView),
+  OptionsWatch(OptionsWatchView),
+  Quick(QuickView),
+  Complete(Box<CompleteView>),
+}
+
+impl PositionView {
+  pub fn kind(&self) -> PortfolioView {
+    match self {
+      PositionView::Performance(_) => PortfolioView::Performance,
+      PositionView::Fundamental(_) => PortfolioView::Fundamental,
+      PositionView::OptionsWatch(_) => PortfolioView::Optionswatch,
+      PositionView::Quick(_) => PortfolioView::Quick,
+      PositionView::Complete(_) => PortfolioView::Complete,
+    }
+  }
+
+  /// The last trade, which every view has.
+  pub fn last_trade(&self) -> f64 {
+    match self {
+      PositionView::Performance(v) => v.last_trade,
+      PositionView::Fundamental(v) => v.last_trade,
+      PositionView::OptionsWatch(v) => v.last_trade,
+      PositionView::Quick(v) => v.last_trade,
+      PositionView::Complete(v) => v.last_trade,
+    }
+  }
+
+  pub fn last_trade_time(&self) -> Option<DateTime<Utc>> {
+    match self {
+      PositionView::Performance(v) => v.last_trade_time,
+      PositionView::Fundamental(v) => v.last_trade_time,
+      PositionView::OptionsWatch(v) => v.last_trade_time,
+      PositionView::Quick(v) => v.last_trade_time,
+      PositionView::Complete(v) => v.last_trade_time,
+    }
+  }
+
+  pub fn quote_status(&self) -> Option<QuoteStatus> {
+    match self {
+      PositionView::Performance(v) => v.quote_status,
+      PositionView::Fundamental(v) => v.quote_status,
+      PositionView::OptionsWatch(v) => v.quote_status,
+      PositionView::Quick(v) => v.quote_status,
+      PositionView::Complete(v) => v.quote_status,
+    }
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct PerformanceView {
@@ -758,7 +1053,9 @@ pub struct PerformanceView {
   pub market_value: f64,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub quote_status: Option<QuoteStatus>,
-  pub last_trade_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub last_trade_time: Option<DateTime<Utc>>,
 }
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
@@ -781,7 +1078,9 @@ pub enum QuoteStatus {
 #[serde(rename_all = "camelCase", default)]
 pub struct FundamentalView {
   pub last_trade: f64,
-  pub last_trade_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub last_trade_time: Option<DateTime<Utc>>,
   pub change: f64,
   pub change_pct: f64,
   pub pe_ratio: f64,
@@ -797,7 +1096,9 @@ pub struct FundamentalView {
 #[serde(rename_all = "camelCase", default)]
 pub struct OptionsWatchView {
   pub last_trade: f64,
-  pub last_trade_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub last_trade_time: Option<DateTime<Utc>>,
   pub base_symbol_and_price: String,
   pub premium: f64,
   pub bid: f64,
@@ -810,7 +1111,9 @@ pub struct OptionsWatchView {
 #[serde(rename_all = "camelCase", default)]
 pub struct QuickView {
   pub last_trade: f64,
-  pub last_trade_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub last_trade_time: Option<DateTime<Utc>>,
   pub change: f64,
   pub change_pct: f64,
   pub volume: i64,
@@ -831,7 +1134,9 @@ pub struct CompleteView {
   pub prev_close: f64,
   pub adj_prev_close: f64,
   pub last_trade: f64,
-  pub last_trade_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub last_trade_time: Option<DateTime<Utc>>,
   pub adj_last_trade: f64,
   pub symbol_description: String,
   pub perform_1_month: f64,
@@ -920,3 +1225,127 @@ pub struct PositionLot {
   pub premium_adj: f64,
   pub short_type: i32,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    Account, AccountMode, AccountStatus, AccountType, InstitutionType, PortfolioColumn, PortfolioPosition,
+    PortfolioRequest, PortfolioView, PositionView, QuoteStatus,
+  };
+
+  #[test]
+  fn deserializes_typed_accounts() {
+    let account: Account = serde_json::from_str(
+      r#"{
+        "accountId": "82",
+        "accountIdKey": "key",
+        "accountMode": "IRA",
+        "accountType": "ROTHIRA",
+        "institutionType": "BROKERAGE",
+        "accountStatus": "CLOSED",
+        "closedDate": 1700000000
+      }"#,
+    )
+    .unwrap();
+    assert_eq!(account.account_mode, AccountMode::Ira);
+    assert_eq!(account.account_type, AccountType::Rothira);
+    assert_eq!(account.institution_type, InstitutionType::Brokerage);
+    assert_eq!(account.account_status, AccountStatus::Closed);
+    assert_eq!(account.closed_date.unwrap().timestamp(), 1_700_000_000);
+    assert!(account.is_retirement());
+    assert!(account.is_closed());
+    assert!(!account.is_margin());
+    assert!(account.is_brokerage());
+    assert_eq!(serde_json::to_value(&account).unwrap()["closedDate"], 1_700_000_000);
+  }
+
+  #[test]
+  fn tolerates_unknown_values() {
+    let account: Account = serde_json::from_str(
+      r#"{
+        "accountMode": "MARGIN",
+        "accountType": "SOMETHING_NEW",
+        "institutionType": "BANK",
+        "accountStatus": "ACTIVE",
+        "closedDate": 0
+      }"#,
+    )
+    .unwrap();
+    assert_eq!(account.account_type, AccountType::Unknown("SOMETHING_NEW".to_string()));
+    assert_eq!(account.institution_type, InstitutionType::Unknown("BANK".to_string()));
+    let json = serde_json::to_value(&account).unwrap();
+    assert_eq!(json["accountType"], "SOMETHING_NEW");
+    assert_eq!(json["institutionType"], "BANK");
+    assert_eq!(json["accountMode"], "MARGIN");
+    assert!(account.closed_date.is_none());
+    assert!(account.is_margin());
+    assert!(!account.is_closed());
+    assert!(!account.is_retirement());
+    assert!(!account.is_brokerage());
+  }
+
+  #[test]
+  fn deserializes_the_view_of_positions() {
+    let position: PortfolioPosition = serde_json::from_str(
+      r#"{
+        "positionId": 1,
+        "dateAcquired": 1700000000000,
+        "quantity": 10,
+        "Quick": {
+          "lastTrade": 151.5,
+          "lastTradeTime": 1700000100,
+          "change": 1.5,
+          "volume": 1000,
+          "quoteStatus": "REALTIME"
+        }
+      }"#,
+    )
+    .unwrap();
+    assert_eq!(position.date_acquired.unwrap().timestamp(), 1_700_000_000);
+    let view = position.view.as_ref().unwrap();
+    assert_eq!(view.kind(), PortfolioView::Quick);
+    assert_eq!(view.last_trade(), 151.5);
+    assert_eq!(view.last_trade_time().unwrap().timestamp(), 1_700_000_100);
+    assert!(matches!(view.quote_status(), Some(QuoteStatus::Realtime)));
+    assert!(matches!(view, PositionView::Quick(q) if q.volume == 1000));
+
+    let json = serde_json::to_value(&position).unwrap();
+    assert_eq!(json["Quick"]["lastTradeTime"], 1_700_000_100);
+    assert_eq!(json["dateAcquired"], 1_700_000_000_000i64);
+    let none: PortfolioPosition = serde_json::from_str(r#"{"positionId": 2, "dateAcquired": 0}"#).unwrap();
+    assert!(none.view.is_none() && none.date_acquired.is_none());
+  }
+
+  #[test]
+  fn fails_on_a_malformed_view() {
+    let err = serde_json::from_str::<PortfolioPosition>(
+      r#"{"positionId": 3, "Quick": {"lastTrade": 151.5, "lastTradeTime": "yesterday"}}"#,
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("invalid type"), "{}", err);
+    assert!(serde_json::from_str::<PortfolioPosition>(r#"{"positionId": 4, "Quick": {}, "Complete": {}}"#).is_err());
+  }
+
+  #[test]
+  fn checks_the_sort_column_against_the_view() {
+    let request = |view, column| PortfolioRequest {
+      view,
+      sort_by: Some(column),
+      ..Default::default()
+    };
+    assert!(request(None, PortfolioColumn::Volume).validate().is_ok());
+    assert!(request(None, PortfolioColumn::PeRatio).validate().is_err());
+    assert!(request(Some(PortfolioView::Fundamental), PortfolioColumn::PeRatio)
+      .validate()
+      .is_ok());
+    assert!(request(Some(PortfolioView::Performance), PortfolioColumn::MarketValue)
+      .validate()
+      .is_ok());
+    assert!(request(Some(PortfolioView::Optionswatch), PortfolioColumn::Delta)
+      .validate()
+      .is_err());
+    assert!(request(Some(PortfolioView::Complete), PortfolioColumn::Delta)
+      .validate()
+      .is_ok());
+  }
+}
@@ -1,9 +1,14 @@
-use crate::{empty_body, qs_params, session::CallbackProvider, Session, SortOrder, Store};
+use crate::clients::AlertsClient;
+use crate::{empty_body, epoch, qs_params, Session, SortOrder, Store};
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use http::Method;
 use std::sync::Arc;
 use strum::EnumString;
 
+/// The most alerts E*Trade lists in one request.
+pub const MAX_ALERTS: usize = 300;
+
 pub struct Api<T: Store> {
   session: Arc<Session<T>>,
 }
@@ -16,28 +21,22 @@ where
     Self { session }
   }
 
-  pub async fn list(&self, params: ListAlertsRequest, callbacks: impl CallbackProvider) -> Result<AlertsResponse> {
+  pub async fn list(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
     let alerts: serde_json::Value = self
       .session
-      .send(Method::GET, "/v1/users/alerts", qs_params(&params)?, callbacks)
+      .send(Method::GET, "/v1/users/alerts", qs_params(&params)?)
       .await?;
     debug!("alerts json: {}", serde_json::to_string_pretty(&alerts)?);
     Ok(serde_json::from_value(alerts.get("AlertsResponse").unwrap().clone())?)
   }
 
-  pub async fn details(
-    &self,
-    alert_id: &str,
-    html: bool,
-    callbacks: impl CallbackProvider,
-  ) -> Result<AlertDetailsResponse> {
+  pub async fn details(&self, alert_id: &str, html: bool) -> Result<AlertDetailsResponse> {
     let alerts: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/users/alerts/{}", alert_id),
         if html { Some(vec![("htmlTags", true)]) } else { None },
-        callbacks,
       )
       .await?;
     debug!("alert json: {}", serde_json::to_string_pretty(&alerts)?);
@@ -46,21 +45,49 @@ where
     )?)
   }
 
-  pub async fn delete(&self, alert_id: &str, callbacks: impl CallbackProvider) -> Result<DeleteAlertsResponse> {
+  /// Deletes the alert, or the alerts of a comma separated list of ids. The ids that couldn't be deleted are in
+  /// `failed_alerts`.
+  pub async fn delete(&self, alert_id: &str) -> Result<DeleteAlertsResponse> {
     let alerts: serde_json::Value = self
       .session
-      .send(
-        Method::DELETE,
-        format!("/v1/users/alerts/{}", alert_id),
-        empty_body(),
-        callbacks,
-      )
+      .send(Method::DELETE, format!("/v1/users/alerts/{}", alert_id), empty_body())
       .await?;
     debug!("alert json: {}", serde_json::to_string_pretty(&alerts)?);
     Ok(serde_json::from_value(alerts.get("AlertsResponse").unwrap().clone())?)
   }
 }
 
+/// The helpers of [`AlertsClient`], so they don't need the trait in scope.
+impl<T> Api<T>
+where
+  T: Store + Send + Sync,
+{
+  /// See [`AlertsClient::list_all`].
+  pub async fn list_all(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    AlertsClient::list_all(self, params).await
+  }
+
+  /// See [`AlertsClient::delete_many`].
+  pub async fn delete_many(&self, alert_ids: &[i64]) -> Result<DeleteAlertsResponse> {
+    AlertsClient::delete_many(self, alert_ids).await
+  }
+
+  /// See [`AlertsClient::mark_read`].
+  pub async fn mark_read(&self, alert_ids: &[i64]) -> Result<()> {
+    AlertsClient::mark_read(self, alert_ids).await
+  }
+
+  /// See [`AlertsClient::next_alert`].
+  pub async fn next_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    AlertsClient::next_alert(self, alert, html).await
+  }
+
+  /// See [`AlertsClient::prev_alert`].
+  pub async fn prev_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    AlertsClient::prev_alert(self, alert, html).await
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct ListAlertsRequest {
@@ -82,18 +109,90 @@ pub struct AlertsResponse {
 #[serde(rename_all = "camelCase", default)]
 pub struct AlertDetailsResponse {
   pub id: i64,
-  pub create_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub create_time: Option<DateTime<Utc>>,
   pub subject: String,
   pub msg_text: String,
-  #[serde(skip_serializing_if = "Option::is_none")]
-  pub read_time: Option<i64>,
-  #[serde(skip_serializing_if = "Option::is_none")]
-  pub delete_time: Option<i64>,
+  #[serde(with = "epoch::seconds", skip_serializing_if = "Option::is_none")]
+  pub read_time: Option<DateTime<Utc>>,
+  #[serde(with = "epoch::seconds", skip_serializing_if = "Option::is_none")]
+  pub delete_time: Option<DateTime<Utc>>,
   pub symbol: Option<String>,
+  /// The url of the next alert, empty for the last one.
   pub next: String,
+  /// The url of the previous alert, empty for the first one.
   pub prev: String,
 }
 
+impl AlertDetailsResponse {
+  /// The message without its html tags and entities, with a line per paragraph.
+  pub fn text(&self) -> String {
+    strip_html(&self.msg_text)
+  }
+
+  /// The id of the next alert, from the `next` url.
+  pub fn next_id(&self) -> Option<i64> {
+    link_id(&self.next)
+  }
+
+  /// The id of the previous alert, from the `prev` url.
+  pub fn prev_id(&self) -> Option<i64> {
+    link_id(&self.prev)
+  }
+}
+
+/// The id at the end of the path of an alert url such as `https://api.etrade.com/v1/user/alerts/6774?htmlTags=false`.
+fn link_id(url: &str) -> Option<i64> {
+  url
+    .split('?')
+    .next()?
+    .trim_end_matches('/')
+    .rsplit('/')
+    .next()?
+    .parse()
+    .ok()
+}
+
+fn strip_html(html: &str) -> String {
+  let mut text = String::new();
+  let mut rest = html;
+  while let Some(start) = rest.find('<') {
+    text.push_str(&rest[..start]);
+    let Some(end) = rest[start..].find('>') else {
+      rest = &rest[start..];
+      break;
+    };
+    let tag = rest[start + 1..start + end]
+      .trim_start_matches('/')
+      .to_ascii_lowercase();
+    let name = tag
+      .split(|c: char| c.is_whitespace() || c == '/')
+      .next()
+      .unwrap_or_default();
+    if matches!(name, "br" | "p" | "div" | "li" | "tr") {
+      text.push('\n');
+    }
+    rest = &rest[start + end + 1..];
+  }
+  text.push_str(rest);
+
+  let text = text
+    .replace("&nbsp;", " ")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&apos;", "'")
+    .replace("&amp;", "&");
+  text
+    .lines()
+    .map(|l| l.split_whitespace().collect::<Vec<_>>().join(" "))
+    .filter(|l| !l.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct DeleteAlertsResponse {
@@ -111,7 +210,9 @@ pub struct FailedAlerts {
 #[serde(rename_all = "camelCase", default)]
 pub struct Alert {
   pub id: i64,
-  pub create_time: i64,
+  /// Sent as epoch seconds.
+  #[serde(with = "epoch::seconds")]
+  pub create_time: Option<DateTime<Utc>>,
   pub subject: String,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub status: Option<Status>,
@@ -125,7 +226,7 @@ pub enum Category {
   #[serde(rename = "ACCOUNT")]
   Account,
 }
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum Status {
   #[serde(rename = "READ")]
@@ -135,3 +236,38 @@ pub enum Status {
   #[serde(rename = "DELETED")]
   Deleted,
 }
+
+#[cfg(test)]
+mod tests {
+  use chrono::{TimeZone, Utc};
+  use serde_json::json;
+
+  use super::AlertDetailsResponse;
+
+  #[test]
+  fn reads_alert_details() {
+    let alert: AlertDetailsResponse = serde_json::from_value(json!({
+      "id": 6773,
+      "createTime": 1719936000,
+      "subject": "Order executed",
+      "msgText": "<p>Your order to buy <b>10</b> AAPL&nbsp;@ $150 was executed.</p><p>Q&amp;A: call us.<br/>Thanks</p>",
+      "readTime": null,
+      "deleteTime": 0,
+      "next": "https://api.etrade.com/v1/user/alerts/6774?htmlTags=false",
+      "prev": ""
+    }))
+    .unwrap();
+
+    assert_eq!(
+      alert.create_time,
+      Some(Utc.with_ymd_and_hms(2024, 7, 2, 16, 0, 0).unwrap())
+    );
+    assert!(alert.read_time.is_none() && alert.delete_time.is_none());
+    assert_eq!(
+      alert.text(),
+      "Your order to buy 10 AAPL @ $150 was executed.\nQ&A: call us.\nThanks"
+    );
+    assert_eq!(alert.next_id(), Some(6774));
+    assert_eq!(alert.prev_id(), None);
+  }
+}
@@ -4,27 +4,44 @@ use accounts::BalanceRequest;
 use anyhow::{anyhow, Result};
 use bat::{Input, PrettyPrinter};
 use etrade::orders::{ListOrdersRequest, OrderStatus, TransactionType};
-use etrade::KeychainStore;
 use etrade::{self, SortOrder};
 use etrade::{accounts, MarketSession, SecurityType};
 use serde::Serialize;
-use std::sync::Arc;
 use structopt::StructOpt;
 use tokio::io::{self, *};
 
+mod store;
+
 #[tokio::main]
 async fn main() -> Result<()> {
   pretty_env_logger::init();
 
+  let opts = Opts::from_args();
+  let cmd = match opts.cmd {
+    Cmd::Store {
+      cmd: StoreCmd::Migrate { from, to },
+    } => {
+      let copied = store::migrate(&from, &to).await?;
+      println!("copied {} secrets", copied);
+      return Ok(());
+    }
+    Cmd::Session(cmd) => cmd,
+  };
+
   let mode: etrade::Mode = etrade::Mode::Live;
-  let session = Arc::new(etrade::Session::new(mode, KeychainStore));
-  let accounts = etrade::accounts::Api::new(session.clone());
-  let orders = etrade::orders::Api::new(session.clone());
-  let _transactions = etrade::transactions::Api::new(session.clone());
-  let oob = etrade::OOB;
+  let store = match &opts.store {
+    Some(backend) => backend.open().await?,
+    None => store::Backend::default_backend()?.open().await?,
+  };
+  let client = etrade::Client::builder(mode, store.clone())
+    .profile(&opts.profile)
+    .build();
+  let session = client.session();
+  let accounts = client.accounts();
+  let orders = client.orders();
 
-  match Cmd::from_args() {
-    Cmd::Init => {
+  match cmd {
+    SessionCmd::Init => {
       let msg1 = "Consumer key:\n";
       io::stderr().write_all(msg1.as_bytes()).await?;
 
@@ -40,13 +57,28 @@ async fn main() -> Result<()> {
       session
         .initialize(consumer_token.trim().to_string(), consumer_secret.trim().to_string())
         .await?;
-      println!("updated the {} consumer token and key", mode);
+      println!(
+        "updated the {} consumer token and key for profile {}",
+        mode,
+        session.profile()
+      );
+    }
+    SessionCmd::Profiles { cmd: ProfilesCmd::List } => {
+      for profile in session.profiles().await? {
+        println!("{}", profile);
+      }
+    }
+    SessionCmd::Profiles {
+      cmd: ProfilesCmd::Remove { name },
+    } => {
+      etrade::Session::new(mode, store).with_profile(&name).remove().await?;
+      println!("removed the {} profile {}", mode, name);
     }
-    Cmd::Accounts { cmd: AccountCmd::List } => {
-      let account_list = accounts.list(oob).await?;
+    SessionCmd::Accounts { cmd: AccountCmd::List } => {
+      let account_list = accounts.list().await?;
       pretty_print(&account_list)?;
     }
-    Cmd::Accounts {
+    SessionCmd::Accounts {
       cmd: AccountCmd::Balance { account_id, real_time },
     } => {
       let balance = accounts
@@ -56,16 +88,16 @@ async fn main() -> Result<()> {
             real_time_nav: if real_time { Some(real_time) } else { None },
             ..Default::default()
           },
-          oob,
         )
         .await?;
       pretty_print(&balance)?;
     }
-    Cmd::Accounts {
+    SessionCmd::Accounts {
       cmd:
         AccountCmd::Portfolio {
           account_id,
           count,
+          page_number,
           sort_by,
           sort_order,
           market_session,
@@ -79,6 +111,7 @@ async fn main() -> Result<()> {
           &account_id,
           accounts::PortfolioRequest {
             count,
+            page_number,
             sort_by,
             sort_order: Some(sort_order),
             market_session: Some(market_session),
@@ -86,12 +119,11 @@ async fn main() -> Result<()> {
             lots_required,
             view: Some(view),
           },
-          oob,
         )
         .await?;
       pretty_print(&portfolio)?;
     }
-    Cmd::Orders {
+    SessionCmd::Orders {
       cmd:
         OrdersCmd::List {
           account_id,
@@ -120,7 +152,6 @@ async fn main() -> Result<()> {
             transaction_type,
             market_session,
           },
-          oob,
         )
         .await?;
       pretty_print(&results)?;
@@ -150,9 +181,40 @@ fn pretty_print<T: Serialize>(data: &T) -> Result<()> {
 #[derive(Debug, StructOpt)]
 /// Exposes the E*Trade API methods for the CLI.
 ///
-/// This command mostly serves to manage the oauth1 tokens via the keychain.
+/// This command mostly serves to manage the oauth1 tokens via the keychain or another store.
+struct Opts {
+  #[structopt(long, global = true, default_value = etrade::DEFAULT_PROFILE)]
+  /// The profile whose consumer key and tokens are used
+  profile: String,
+  #[structopt(long, global = true)]
+  /// The store holding the consumer keys and tokens: memory, keychain, file:<path> or sqlite:<path>, defaults to the
+  /// keychain
+  store: Option<store::Backend>,
+  #[structopt(subcommand)]
+  cmd: Cmd,
+}
+
+#[derive(Debug, StructOpt)]
 enum Cmd {
+  /// Manage the stores holding the consumer keys and tokens
+  Store {
+    #[structopt(subcommand)]
+    cmd: StoreCmd,
+  },
+  #[structopt(flatten)]
+  Session(SessionCmd),
+}
+
+/// The commands that run with the store of `--store` and a session of the profile.
+#[derive(Debug, StructOpt)]
+enum SessionCmd {
+  /// Store the consumer key and secret for the profile
   Init,
+  /// List and remove profiles
+  Profiles {
+    #[structopt(subcommand)]
+    cmd: ProfilesCmd,
+  },
   /// List accounts, balances, transactions and portfolios
   Accounts {
     #[structopt(subcommand)]
@@ -165,6 +227,33 @@ enum Cmd {
   },
 }
 
+#[derive(Debug, StructOpt)]
+enum ProfilesCmd {
+  /// List the initialized profiles
+  List,
+  /// Remove the consumer key and tokens of a profile
+  Remove {
+    /// The name of the profile to remove
+    name: String,
+  },
+}
+
+#[derive(Debug, StructOpt)]
+enum StoreCmd {
+  /// Copy every secret from one store into another
+  ///
+  /// A store is one of keychain, file:<path> or sqlite:<path>. The passphrase of encrypted stores is read from
+  /// ETRADECTL_PASSPHRASE, or prompted for when it isn't set.
+  Migrate {
+    #[structopt(long)]
+    /// The store to copy from
+    from: store::Backend,
+    #[structopt(long)]
+    /// The store to copy into
+    to: store::Backend,
+  },
+}
+
 #[derive(Debug, StructOpt)]
 enum OrdersCmd {
   /// List the orders
@@ -231,6 +320,10 @@ enum AccountCmd {
     /// you receive a response with an empty marker, indicating that there are no more items.
     count: Option<usize>,
 
+    #[structopt(long)]
+    /// The page to return, starting at 1. Each response has the number of the next page.
+    page_number: Option<usize>,
+
     #[structopt(long)]
     /// The sort by query. Sorting done based on the column specified in the query paramater.
     sort_by: Option<accounts::PortfolioColumn>,
@@ -0,0 +1,78 @@
+use std::{path::PathBuf, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use etrade::{DynStore, FileStore, Memstore};
+use secstr::SecUtf8;
+use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt};
+
+const PASSPHRASE_VAR: &str = "ETRADECTL_PASSPHRASE";
+
+/// A store backend: `memory`, `keychain`, `file:<path>` or `sqlite:<path>`.
+#[derive(Debug, Clone)]
+pub enum Backend {
+  Memory,
+  #[cfg(feature = "keychain")]
+  Keychain,
+  File(PathBuf),
+  #[cfg(feature = "sqlite")]
+  Sqlite(PathBuf),
+}
+
+impl Backend {
+  /// The keychain when the binary is built with it.
+  pub fn default_backend() -> Result<Self> {
+    #[cfg(feature = "keychain")]
+    return Ok(Backend::Keychain);
+    #[cfg(not(feature = "keychain"))]
+    return Err(anyhow!(
+      "etradectl is built without keychain support, choose a store with --store"
+    ));
+  }
+
+  pub async fn open(&self) -> Result<Arc<dyn DynStore>> {
+    Ok(match self {
+      Backend::Memory => Arc::new(Memstore::new()),
+      #[cfg(feature = "keychain")]
+      Backend::Keychain => Arc::new(etrade::KeychainStore::new().await?),
+      Backend::File(path) => Arc::new(FileStore::with_passphrase(path, passphrase(path).await?).await?),
+      #[cfg(feature = "sqlite")]
+      Backend::Sqlite(path) => Arc::new(etrade::SqliteStore::open(path, passphrase(path).await?).await?),
+    })
+  }
+}
+
+impl FromStr for Backend {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    match s.split_once(':') {
+      None if s == "memory" => Ok(Backend::Memory),
+      #[cfg(feature = "keychain")]
+      None if s == "keychain" => Ok(Backend::Keychain),
+      Some(("file", path)) if !path.is_empty() => Ok(Backend::File(path.into())),
+      #[cfg(feature = "sqlite")]
+      Some(("sqlite", path)) if !path.is_empty() => Ok(Backend::Sqlite(path.into())),
+      _ => Err(anyhow!(
+        "unknown store {}, expected memory, keychain, file:<path> or sqlite:<path>",
+        s
+      )),
+    }
+  }
+}
+
+/// Copies every secret of the `from` store into the `to` store.
+pub async fn migrate(from: &Backend, to: &Backend) -> Result<usize> {
+  etrade::migrate(&from.open().await?, &to.open().await?).await
+}
+
+/// Reads the passphrase from `ETRADECTL_PASSPHRASE` or prompts for it.
+async fn passphrase(path: &std::path::Path) -> Result<SecUtf8> {
+  if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+    return Ok(passphrase.into());
+  }
+  let msg = format!("Passphrase for {}:\n", path.display());
+  io::stderr().write_all(msg.as_bytes()).await?;
+  let mut passphrase = String::new();
+  io::BufReader::new(io::stdin()).read_line(&mut passphrase).await?;
+  Ok(passphrase.trim_end_matches(['\r', '\n']).to_string().into())
+}
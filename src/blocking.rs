@@ -0,0 +1,320 @@
+//! Synchronous versions of the apis, enabled with the `blocking` feature.
+//!
+//! A [`Session`] wraps an async [`crate::Session`] together with a runtime of its own, and hands out [`Accounts`],
+//! [`Orders`], [`Options`], [`Alerts`] and [`Transactions`], which block the calling thread until the request
+//! completes. They must not be used from within an async runtime, calling them from an async task panics.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::accounts::{
+  self, Account, BalanceRequest, BalanceResponse, PortfolioRequest, PortfolioResponse, PositionLotsResponse,
+};
+use crate::alerts::{self, AlertDetailsResponse, AlertsResponse, DeleteAlertsResponse, ListAlertsRequest};
+use crate::clients::{AccountsClient, AlertsClient};
+use crate::options::{
+  self, GetOptionChainsRequest, GetOptionExpireDatesRequest, GetQuotesRequest, LookupResponse, OptionChainResponse,
+  OptionExpireDateResponse, QuoteResponse,
+};
+use crate::orders::{
+  self, CancelOrderRequest, CancelOrderResponse, ListOrdersRequest, OrdersResponse, PlaceOrderRequest,
+  PlaceOrderResponse, PreviewOrderRequest, PreviewOrderResponse,
+};
+use crate::transactions::{self, ListTransactionsRequest, TransactionDetailsResponse, TransactionListResponse};
+use crate::Store;
+
+/// A session that runs its requests on an internal single threaded runtime.
+pub struct Session<T: Store> {
+  inner: Arc<crate::Session<T>>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Session<T>
+where
+  T: Store + Send + Sync,
+{
+  /// Takes over a configured async session, fails when the runtime can't be started.
+  pub fn new(session: crate::Session<T>) -> Result<Self> {
+    let runtime = Builder::new_current_thread().enable_all().build()?;
+    Ok(Self {
+      inner: Arc::new(session),
+      runtime: Arc::new(runtime),
+    })
+  }
+
+  /// The async session, to share with code that does have a runtime.
+  pub fn inner(&self) -> &Arc<crate::Session<T>> {
+    &self.inner
+  }
+
+  pub fn initialize(&self, key: String, secret: String) -> Result<()> {
+    self.runtime.block_on(self.inner.initialize(key, secret))
+  }
+
+  pub fn profiles(&self) -> Result<Vec<String>> {
+    self.runtime.block_on(self.inner.profiles())
+  }
+
+  pub fn remove(&self) -> Result<()> {
+    self.runtime.block_on(self.inner.remove())
+  }
+
+  pub fn invalidate(&self) -> Result<()> {
+    self.runtime.block_on(self.inner.invalidate())
+  }
+
+  pub fn accounts(&self) -> Accounts<T> {
+    Accounts {
+      api: accounts::Api::new(self.inner.clone()),
+      runtime: self.runtime.clone(),
+    }
+  }
+
+  pub fn orders(&self) -> Orders<T> {
+    Orders {
+      api: orders::Api::new(self.inner.clone()),
+      runtime: self.runtime.clone(),
+    }
+  }
+
+  pub fn options(&self) -> Options<T> {
+    Options {
+      api: options::Api::new(self.inner.clone()),
+      runtime: self.runtime.clone(),
+    }
+  }
+
+  pub fn alerts(&self) -> Alerts<T> {
+    Alerts {
+      api: alerts::Api::new(self.inner.clone()),
+      runtime: self.runtime.clone(),
+    }
+  }
+
+  pub fn transactions(&self) -> Transactions<T> {
+    Transactions {
+      api: transactions::Api::new(self.inner.clone()),
+      runtime: self.runtime.clone(),
+    }
+  }
+}
+
+/// The blocking version of [`accounts::Api`].
+pub struct Accounts<T: Store> {
+  api: accounts::Api<T>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Accounts<T>
+where
+  T: Store + Send + Sync,
+{
+  pub fn list(&self) -> Result<Vec<Account>> {
+    self.runtime.block_on(self.api.list())
+  }
+
+  pub fn balance(&self, account_id_key: &str, balance_request: BalanceRequest<'_>) -> Result<BalanceResponse> {
+    self.runtime.block_on(self.api.balance(account_id_key, balance_request))
+  }
+
+  pub fn portfolio(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    self.runtime.block_on(self.api.portfolio(account_id_key, params))
+  }
+
+  /// See [`AccountsClient::portfolio_all`].
+  pub fn portfolio_all(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    self
+      .runtime
+      .block_on(AccountsClient::portfolio_all(&self.api, account_id_key, params))
+  }
+
+  pub fn position_lots(&self, account_id_key: &str, position_id: &str) -> Result<PositionLotsResponse> {
+    self
+      .runtime
+      .block_on(self.api.position_lots(account_id_key, position_id))
+  }
+}
+
+/// The blocking version of [`orders::Api`].
+pub struct Orders<T: Store> {
+  api: orders::Api<T>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Orders<T>
+where
+  T: Store + Send + Sync,
+{
+  pub fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
+    self.runtime.block_on(self.api.list(account_id_key, params))
+  }
+
+  pub fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
+    self.runtime.block_on(self.api.preview(account_id_key, params))
+  }
+
+  pub fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
+    self.runtime.block_on(self.api.place(account_id_key, params))
+  }
+
+  pub fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
+    self.runtime.block_on(self.api.cancel(account_id_key, params))
+  }
+
+  pub fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse> {
+    self
+      .runtime
+      .block_on(self.api.change_preview(account_id_key, order_id, params))
+  }
+
+  pub fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse> {
+    self
+      .runtime
+      .block_on(self.api.change_order(account_id_key, order_id, params))
+  }
+}
+
+/// The blocking version of [`options::Api`].
+pub struct Options<T: Store> {
+  api: options::Api<T>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Options<T>
+where
+  T: Store + Send + Sync,
+{
+  pub fn quotes(&self, symbols: &str, params: GetQuotesRequest) -> Result<QuoteResponse> {
+    self.runtime.block_on(self.api.quotes(symbols, params))
+  }
+
+  pub fn product(&self, search: &str) -> Result<LookupResponse> {
+    self.runtime.block_on(self.api.product(search))
+  }
+
+  pub fn chains(&self, params: &GetOptionChainsRequest<'_>) -> Result<OptionChainResponse> {
+    self.runtime.block_on(self.api.chains(params))
+  }
+
+  pub fn expire_dates(&self, params: &GetOptionExpireDatesRequest<'_>) -> Result<OptionExpireDateResponse> {
+    self.runtime.block_on(self.api.expire_dates(params))
+  }
+}
+
+/// The blocking version of [`alerts::Api`].
+pub struct Alerts<T: Store> {
+  api: alerts::Api<T>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Alerts<T>
+where
+  T: Store + Send + Sync,
+{
+  pub fn list(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    self.runtime.block_on(self.api.list(params))
+  }
+
+  pub fn details(&self, alert_id: &str, html: bool) -> Result<AlertDetailsResponse> {
+    self.runtime.block_on(self.api.details(alert_id, html))
+  }
+
+  pub fn delete(&self, alert_id: &str) -> Result<DeleteAlertsResponse> {
+    self.runtime.block_on(self.api.delete(alert_id))
+  }
+
+  /// See [`AlertsClient::list_all`].
+  pub fn list_all(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    self.runtime.block_on(AlertsClient::list_all(&self.api, params))
+  }
+
+  /// See [`AlertsClient::delete_many`].
+  pub fn delete_many(&self, alert_ids: &[i64]) -> Result<DeleteAlertsResponse> {
+    self.runtime.block_on(AlertsClient::delete_many(&self.api, alert_ids))
+  }
+
+  /// See [`AlertsClient::mark_read`].
+  pub fn mark_read(&self, alert_ids: &[i64]) -> Result<()> {
+    self.runtime.block_on(AlertsClient::mark_read(&self.api, alert_ids))
+  }
+
+  /// See [`AlertsClient::next_alert`].
+  pub fn next_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    self.runtime.block_on(AlertsClient::next_alert(&self.api, alert, html))
+  }
+
+  /// See [`AlertsClient::prev_alert`].
+  pub fn prev_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    self.runtime.block_on(AlertsClient::prev_alert(&self.api, alert, html))
+  }
+}
+
+/// The blocking version of [`transactions::Api`].
+pub struct Transactions<T: Store> {
+  api: transactions::Api<T>,
+  runtime: Arc<Runtime>,
+}
+
+impl<T> Transactions<T>
+where
+  T: Store + Send + Sync,
+{
+  pub fn list(&self, account_id_key: &str, params: ListTransactionsRequest<'_>) -> Result<TransactionListResponse> {
+    self.runtime.block_on(self.api.list(account_id_key, params))
+  }
+
+  pub fn details(&self, account_id_key: &str, tranid: &str, store_id: &str) -> Result<TransactionDetailsResponse> {
+    self
+      .runtime
+      .block_on(self.api.details(account_id_key, tranid, store_id))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Session;
+  use crate::accounts::BalanceRequest;
+  use crate::options::GetQuotesRequest;
+  use crate::orders::ListOrdersRequest;
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::Memstore;
+
+  #[test]
+  fn calls_the_apis_without_a_runtime() {
+    // the fake server needs a runtime of its own to serve the requests
+    let server_runtime = tokio::runtime::Runtime::new().unwrap();
+    let server = server_runtime.block_on(FakeServer::start(Fixtures::sample())).unwrap();
+    let session = server_runtime.block_on(server.session(Memstore::new())).unwrap();
+    let session = Session::new(session).unwrap();
+
+    let accounts = session.accounts();
+    let account = accounts.list().unwrap().remove(0);
+    let balance = accounts
+      .balance(&account.account_id_key, BalanceRequest::default())
+      .unwrap();
+    assert_eq!(balance.computed_balance.cash_balance, 10_000.0);
+
+    let orders = session
+      .orders()
+      .list(&account.account_id_key, ListOrdersRequest::default())
+      .unwrap();
+    assert!(orders.order.is_empty());
+
+    let quotes = session.options().quotes("AAPL", GetQuotesRequest::default()).unwrap();
+    assert_eq!(quotes.quote_data[0].product.as_ref().unwrap().symbol, "AAPL");
+
+    assert_eq!(session.profiles().unwrap(), vec!["default"]);
+  }
+}
@@ -0,0 +1,149 @@
+//! The NYSE trading calendar: weekends and the full day holidays of the exchange.
+//!
+//! The holidays follow the NYSE rules: a holiday on a Sunday is observed the Monday after and one on a Saturday the
+//! Friday before, except New Year's Day, which isn't observed on the last day of the previous year. Unscheduled
+//! closures and early closes are not known.
+
+use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
+
+/// The current date in New York.
+pub fn today() -> NaiveDate {
+  Utc::now().with_timezone(&chrono_tz::US::Eastern).date_naive()
+}
+
+/// The full day holidays of the year, in order.
+pub fn holidays(year: i32) -> Vec<NaiveDate> {
+  let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+  let nth = |month, weekday, n| NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap();
+
+  let mut holidays = vec![];
+  let new_year = date(1, 1);
+  match new_year.weekday() {
+    Weekday::Sat => {}
+    Weekday::Sun => holidays.push(date(1, 2)),
+    _ => holidays.push(new_year),
+  }
+  holidays.push(nth(1, Weekday::Mon, 3));
+  holidays.push(nth(2, Weekday::Mon, 3));
+  holidays.push(easter(year) - Duration::days(2));
+  let mut memorial = date(5, 31);
+  while memorial.weekday() != Weekday::Mon {
+    memorial = memorial.pred_opt().unwrap();
+  }
+  holidays.push(memorial);
+  if year >= 2022 {
+    holidays.push(observed(date(6, 19)));
+  }
+  holidays.push(observed(date(7, 4)));
+  holidays.push(nth(9, Weekday::Mon, 1));
+  holidays.push(nth(11, Weekday::Thu, 4));
+  holidays.push(observed(date(12, 25)));
+  holidays
+}
+
+pub fn is_holiday(date: NaiveDate) -> bool {
+  holidays(date.year()).contains(&date)
+}
+
+pub fn is_trading_day(date: NaiveDate) -> bool {
+  !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !is_holiday(date)
+}
+
+/// The last trading day strictly before the date.
+pub fn previous_trading_day(date: NaiveDate) -> NaiveDate {
+  let mut day = date.pred_opt().unwrap();
+  while !is_trading_day(day) {
+    day = day.pred_opt().unwrap();
+  }
+  day
+}
+
+/// The `count` trading days up to and including the date, oldest first. A date that isn't a trading day is left out.
+pub fn trading_days_until(date: NaiveDate, count: usize) -> Vec<NaiveDate> {
+  let mut days = vec![];
+  let mut day = date;
+  if !is_trading_day(day) {
+    day = previous_trading_day(day);
+  }
+  while days.len() < count {
+    days.push(day);
+    day = previous_trading_day(day);
+  }
+  days.reverse();
+  days
+}
+
+fn observed(date: NaiveDate) -> NaiveDate {
+  match date.weekday() {
+    Weekday::Sat => date - Duration::days(1),
+    Weekday::Sun => date + Duration::days(1),
+    _ => date,
+  }
+}
+
+/// Easter Sunday of the Gregorian calendar, with the anonymous Gregorian algorithm.
+fn easter(year: i32) -> NaiveDate {
+  let a = year % 19;
+  let b = year / 100;
+  let c = year % 100;
+  let d = b / 4;
+  let e = b % 4;
+  let f = (b + 8) / 25;
+  let g = (b - f + 1) / 3;
+  let h = (19 * a + b - d - g + 15) % 30;
+  let i = c / 4;
+  let k = c % 4;
+  let l = (32 + 2 * e + 2 * i - h - k) % 7;
+  let m = (a + 11 * h + 22 * l) / 451;
+  let month = (h + l - 7 * m + 114) / 31;
+  let day = (h + l - 7 * m + 114) % 31 + 1;
+  NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+
+  use super::{holidays, is_trading_day, trading_days_until};
+
+  fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+  }
+
+  #[test]
+  fn knows_the_nyse_holidays() {
+    assert_eq!(
+      holidays(2022),
+      vec![
+        date(2022, 1, 17),
+        date(2022, 2, 21),
+        date(2022, 4, 15),
+        date(2022, 5, 30),
+        date(2022, 6, 20),
+        date(2022, 7, 4),
+        date(2022, 9, 5),
+        date(2022, 11, 24),
+        date(2022, 12, 26),
+      ]
+    );
+    assert_eq!(holidays(2024)[3], date(2024, 3, 29));
+    assert_eq!(holidays(2023)[0], date(2023, 1, 2));
+  }
+
+  #[test]
+  fn skips_weekends_and_holidays() {
+    assert!(!is_trading_day(date(2024, 7, 4)));
+    assert!(!is_trading_day(date(2024, 7, 6)));
+    assert!(is_trading_day(date(2024, 7, 5)));
+    assert_eq!(
+      trading_days_until(date(2024, 7, 9), 5),
+      vec![
+        date(2024, 7, 2),
+        date(2024, 7, 3),
+        date(2024, 7, 5),
+        date(2024, 7, 8),
+        date(2024, 7, 9)
+      ]
+    );
+  }
+}
@@ -0,0 +1,651 @@
+//! Records the HTTP traffic of a session to a file and replays it in tests, without network.
+//!
+//! [`Recorder`] wraps a [`Transport`] and keeps every request and response for a cassette, which it writes on
+//! [`Recorder::finish`] or when it is dropped. [`Replayer`] serves the recorded responses to a session. Cassettes are
+//! YAML files, or JSON when the file name ends with `.json`.
+//!
+//! Nothing that identifies the user is recorded: the request headers are left out, so the oauth signature and
+//! access token never end up in a cassette, the tokens in the responses of the oauth endpoints are replaced and
+//! account ids are swapped for placeholders, the same one everywhere the id appears. In JSON bodies the account id
+//! fields are replaced by structure, `ACCOUNT-1` for a string and `1` for a number, in urls the path segments and
+//! query values that are an account id. The ids found that way are then replaced wherever else they appear as a
+//! word, in the other string values and in the bodies that aren't JSON.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use etrade::cassette::Replayer;
+//! use etrade::{Memstore, Mode, Session};
+//!
+//! let session = Session::new(Mode::Sandbox, Memstore::new())
+//!   .with_transport(Replayer::from_file("tests/cassettes/accounts.yaml")?);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+  collections::BTreeMap,
+  path::{Path, PathBuf},
+  sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use http::{header::CONTENT_TYPE, Request, Response};
+use hyper::Body;
+
+use crate::transport::Transport;
+
+const ACCOUNT_ID_FIELDS: &[&str] = &["accountId", "accountIdKey"];
+const TOKEN_FIELDS: &[&str] = &["oauth_token", "oauth_token_secret"];
+const REDACTED: &str = "REDACTED";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Cassette {
+  pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    let content = std::fs::read(path).with_context(|| format!("failed to read the cassette {}", path.display()))?;
+    let cassette = if is_json(path) {
+      serde_json::from_slice(&content)?
+    } else {
+      serde_yaml::from_slice(&content)?
+    };
+    Ok(cassette)
+  }
+
+  pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, self.to_bytes(path)?)
+      .with_context(|| format!("failed to write the cassette {}", path.display()))
+  }
+
+  fn to_bytes(&self, path: &Path) -> Result<Vec<u8>> {
+    if is_json(path) {
+      Ok(serde_json::to_vec_pretty(self)?)
+    } else {
+      Ok(serde_yaml::to_string(self)?.into_bytes())
+    }
+  }
+}
+
+fn is_json(path: &Path) -> bool {
+  path.extension().map(|ext| ext == "json").unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Interaction {
+  pub request: RecordedRequest,
+  pub response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecordedRequest {
+  pub method: String,
+  pub path: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub query: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub body: Option<String>,
+}
+
+impl RecordedRequest {
+  async fn from_request(req: Request<Body>) -> Result<(Self, Request<Body>)> {
+    let (parts, body) = req.into_parts();
+    let bytes = hyper::body::to_bytes(body).await?;
+    let recorded = Self {
+      method: parts.method.to_string(),
+      path: parts.uri.path().to_string(),
+      query: parts.uri.query().map(str::to_string),
+      body: if bytes.is_empty() {
+        None
+      } else {
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+      },
+    };
+    Ok((recorded, Request::from_parts(parts, bytes.into())))
+  }
+
+  /// Compares the method, the path, the query parameters whatever their order and the body, as JSON when it parses.
+  fn matches(&self, other: &RecordedRequest) -> bool {
+    self.method.eq_ignore_ascii_case(&other.method)
+      && self.path == other.path
+      && query_params(&self.query) == query_params(&other.query)
+      && same_body(&self.body, &other.body)
+  }
+}
+
+fn query_params(query: &Option<String>) -> Vec<(String, String)> {
+  let mut params: Vec<(String, String)> = query
+    .as_deref()
+    .map(|q| serde_urlencoded::from_str(q).unwrap_or_default())
+    .unwrap_or_default();
+  params.sort();
+  params
+}
+
+fn same_body(a: &Option<String>, b: &Option<String>) -> bool {
+  match (a, b) {
+    (Some(a), Some(b)) => match (
+      serde_json::from_str::<serde_json::Value>(a),
+      serde_json::from_str::<serde_json::Value>(b),
+    ) {
+      (Ok(a), Ok(b)) => a == b,
+      _ => a == b,
+    },
+    (a, b) => a == b,
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecordedResponse {
+  pub status: u16,
+  pub headers: BTreeMap<String, String>,
+  pub body: String,
+}
+
+impl RecordedResponse {
+  fn to_response(&self) -> Result<Response<Body>> {
+    let mut builder = Response::builder().status(self.status);
+    for (name, value) in &self.headers {
+      builder = builder.header(name, value);
+    }
+    Ok(builder.body(self.body.clone().into())?)
+  }
+}
+
+/// Replaces account ids with placeholders, handing out the same placeholder for the same id.
+#[derive(Debug, Default)]
+struct Redactor {
+  accounts: Vec<String>,
+}
+
+impl Redactor {
+  /// The number of the placeholder of the id, starting at 1.
+  fn placeholder(&mut self, id: &str) -> usize {
+    match self.accounts.iter().position(|real| real == id) {
+      Some(i) => i + 1,
+      None => {
+        self.accounts.push(id.to_string());
+        self.accounts.len()
+      }
+    }
+  }
+
+  /// The placeholder of a known id, for the path segments and query values.
+  fn known(&self, text: &str) -> Option<String> {
+    let i = self.accounts.iter().position(|real| real == text)?;
+    Some(format!("ACCOUNT-{}", i + 1))
+  }
+
+  /// Replaces the values of the account id fields of a JSON document, keeping their type.
+  fn redact_json(&mut self, value: &mut serde_json::Value) {
+    match value {
+      serde_json::Value::Object(map) => {
+        for (key, value) in map.iter_mut() {
+          let is_id = ACCOUNT_ID_FIELDS.contains(&key.as_str());
+          match value {
+            serde_json::Value::String(id) if is_id => *id = format!("ACCOUNT-{}", self.placeholder(id)),
+            serde_json::Value::Number(id) if is_id => {
+              *value = serde_json::Value::from(self.placeholder(&id.to_string()));
+            }
+            value => self.redact_json(value),
+          }
+        }
+      }
+      serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.redact_json(v)),
+      _ => {}
+    }
+  }
+
+  /// Replaces the known ids in the string values left by [`Redactor::redact_json`].
+  fn redact_strings(&self, value: &mut serde_json::Value) {
+    match value {
+      serde_json::Value::String(text) => *text = self.redact_text(text),
+      serde_json::Value::Object(map) => map.values_mut().for_each(|v| self.redact_strings(v)),
+      serde_json::Value::Array(values) => values.iter_mut().for_each(|v| self.redact_strings(v)),
+      _ => {}
+    }
+  }
+
+  /// Replaces the known ids that appear as whole words in a text, so `83405188` is replaced but not the
+  /// `834051881` of an order id.
+  fn redact_text(&self, text: &str) -> String {
+    let mut ids: Vec<(usize, &String)> = self.accounts.iter().enumerate().collect();
+    ids.sort_by_key(|(_, id)| std::cmp::Reverse(id.len()));
+    let mut text = text.to_string();
+    for (i, id) in ids {
+      if id.is_empty() || !text.contains(id.as_str()) {
+        continue;
+      }
+      let placeholder = format!("ACCOUNT-{}", i + 1);
+      let mut redacted = String::with_capacity(text.len());
+      let mut last = 0;
+      for (start, _) in text.match_indices(id.as_str()) {
+        let end = start + id.len();
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        if before.is_some_and(char::is_alphanumeric) || after.is_some_and(char::is_alphanumeric) {
+          continue;
+        }
+        redacted.push_str(&text[last..start]);
+        redacted.push_str(&placeholder);
+        last = end;
+      }
+      redacted.push_str(&text[last..]);
+      text = redacted;
+    }
+    text
+  }
+
+  fn redact_body(&mut self, body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+      Ok(mut json) if json.is_object() || json.is_array() => {
+        self.redact_json(&mut json);
+        self.redact_strings(&mut json);
+        serde_json::to_string(&json).unwrap_or_else(|_| body.to_string())
+      }
+      _ => self.redact_text(body),
+    }
+  }
+
+  fn redact_query(&self, query: &str) -> String {
+    match serde_urlencoded::from_str::<Vec<(String, String)>>(query) {
+      Ok(params) if params.iter().any(|(_, v)| self.known(v).is_some()) => {
+        let params: Vec<(String, String)> = params
+          .into_iter()
+          .map(|(k, v)| {
+            let v = self.known(&v).unwrap_or(v);
+            (k, v)
+          })
+          .collect();
+        serde_urlencoded::to_string(params).unwrap_or_else(|_| query.to_string())
+      }
+      _ => query.to_string(),
+    }
+  }
+
+  fn redact(&mut self, interaction: &mut Interaction) {
+    // the segment after `accounts` in `/v1/accounts/{accountIdKey}/balance` is an account id key
+    let segments: Vec<&str> = interaction.request.path.split('/').collect();
+    for pair in segments.windows(2) {
+      if pair[0] == "accounts" && pair[1] != "list" && !pair[1].is_empty() {
+        self.placeholder(pair[1]);
+      }
+    }
+    let response = &mut interaction.response;
+    response.body = self.redact_body(&redact_tokens(&response.body));
+
+    let request = &mut interaction.request;
+    request.body = request.body.as_deref().map(|b| self.redact_body(b));
+    request.path = request
+      .path
+      .split('/')
+      .map(|segment| self.known(segment).unwrap_or_else(|| segment.to_string()))
+      .collect::<Vec<_>>()
+      .join("/");
+    request.query = request.query.as_deref().map(|q| self.redact_query(q));
+  }
+}
+
+/// Replaces the tokens in the url encoded responses of the oauth endpoints.
+fn redact_tokens(body: &str) -> String {
+  match serde_urlencoded::from_str::<Vec<(String, String)>>(body) {
+    Ok(params) if params.iter().any(|(k, _)| TOKEN_FIELDS.contains(&k.as_str())) => {
+      let params: Vec<(String, String)> = params
+        .into_iter()
+        .map(|(k, v)| {
+          if TOKEN_FIELDS.contains(&k.as_str()) {
+            (k, REDACTED.to_string())
+          } else {
+            (k, v)
+          }
+        })
+        .collect();
+      serde_urlencoded::to_string(params).unwrap_or_else(|_| REDACTED.to_string())
+    }
+    _ => body.to_string(),
+  }
+}
+
+/// Sends the requests with another transport and records every interaction for the cassette.
+///
+/// The cassette is written by [`Recorder::finish`], or when the recorder is dropped with interactions that weren't
+/// written yet. A failure to write never fails a request that was already sent, share the recorder through an `Arc`
+/// to call `finish` and see the error.
+pub struct Recorder<T> {
+  inner: T,
+  path: PathBuf,
+  /// The cassette, its redactor and whether it has interactions that weren't written.
+  state: Mutex<(Cassette, Redactor, bool)>,
+}
+
+impl<T> Recorder<T> {
+  /// Records into a new cassette at `path`, replacing an existing file when it is written.
+  pub fn new(inner: T, path: impl Into<PathBuf>) -> Self {
+    Self {
+      inner,
+      path: path.into(),
+      state: Mutex::new((Cassette::default(), Redactor::default(), false)),
+    }
+  }
+
+  pub fn cassette(&self) -> Cassette {
+    self.state.lock().unwrap().0.clone()
+  }
+
+  /// Writes the cassette with the interactions recorded so far.
+  pub fn finish(&self) -> Result<()> {
+    let mut state = self.state.lock().unwrap();
+    state.0.save(&self.path)?;
+    state.2 = false;
+    Ok(())
+  }
+}
+
+impl<T> Drop for Recorder<T> {
+  fn drop(&mut self) {
+    let unsaved = self.state.get_mut().map(|state| state.2).unwrap_or(false);
+    if unsaved {
+      if let Err(e) = self.finish() {
+        warn!("failed to save the recorded interactions: {:#}", e);
+      }
+    }
+  }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for Recorder<T> {
+  async fn send(&self, req: Request<Body>) -> Result<Response<Body>> {
+    let (request, req) = RecordedRequest::from_request(req).await?;
+    let resp = self.inner.send(req).await?;
+
+    let (parts, body) = resp.into_parts();
+    let bytes = hyper::body::to_bytes(body).await?;
+    let headers = parts
+      .headers
+      .iter()
+      .filter(|(name, _)| *name == CONTENT_TYPE)
+      .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+      .collect();
+    let mut interaction = Interaction {
+      request,
+      response: RecordedResponse {
+        status: parts.status.as_u16(),
+        headers,
+        body: String::from_utf8_lossy(&bytes).into_owned(),
+      },
+    };
+
+    let mut state = self.state.lock().unwrap();
+    let (cassette, redactor, unsaved) = &mut *state;
+    redactor.redact(&mut interaction);
+    cassette.interactions.push(interaction);
+    *unsaved = true;
+    drop(state);
+
+    Ok(Response::from_parts(parts, bytes.into()))
+  }
+}
+
+/// Serves the responses of a cassette, failing every request that wasn't recorded.
+///
+/// Each recorded interaction answers one request, in the order they were recorded when the same request was made
+/// several times.
+#[derive(Debug)]
+pub struct Replayer {
+  interactions: Mutex<Vec<(Interaction, bool)>>,
+}
+
+impl Replayer {
+  pub fn new(cassette: Cassette) -> Self {
+    Self {
+      interactions: Mutex::new(cassette.interactions.into_iter().map(|i| (i, false)).collect()),
+    }
+  }
+
+  pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+    Ok(Self::new(Cassette::load(path)?))
+  }
+
+  /// The number of recorded interactions that weren't replayed yet.
+  pub fn remaining(&self) -> usize {
+    self
+      .interactions
+      .lock()
+      .unwrap()
+      .iter()
+      .filter(|(_, used)| !used)
+      .count()
+  }
+}
+
+#[async_trait]
+impl Transport for Replayer {
+  async fn send(&self, req: Request<Body>) -> Result<Response<Body>> {
+    let (request, _) = RecordedRequest::from_request(req).await?;
+    let mut interactions = self.interactions.lock().unwrap();
+    let (interaction, used) = interactions
+      .iter_mut()
+      .find(|(i, used)| !used && i.request.matches(&request))
+      .ok_or_else(|| {
+        anyhow!(
+          "no recorded interaction left for {} {}{}{}",
+          request.method,
+          request.path,
+          request.query.as_deref().map(|q| format!("?{}", q)).unwrap_or_default(),
+          request
+            .body
+            .as_deref()
+            .map(|b| format!(" with body {}", b))
+            .unwrap_or_default(),
+        )
+      })?;
+    *used = true;
+    interaction.response.to_response()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use anyhow::Result;
+  use async_trait::async_trait;
+  use http::{Method, Request, Response};
+  use hyper::Body;
+
+  use super::{Cassette, Recorder, Replayer};
+  use crate::{accounts, transport::Transport, Memstore, Mode, Session, Store};
+
+  /// Plays the E*Trade API for two accounts.
+  struct Fake;
+
+  #[async_trait]
+  impl Transport for Fake {
+    async fn send(&self, req: Request<Body>) -> Result<Response<Body>> {
+      let body = match req.uri().path() {
+        "/v1/accounts/list" => serde_json::json!({
+          "AccountListResponse": {"Accounts": {"Account": [
+            {"accountId": "83405188", "accountIdKey": "dBZOKt9xDrtRSAOl4MSiiA", "accountDesc": "Brokerage"},
+            {"accountId": "83405553", "accountIdKey": "YDHjz6wnsGRbsUc7fJ0ivQ", "accountDesc": "IRA"}
+          ]}}
+        }),
+        "/v1/accounts/dBZOKt9xDrtRSAOl4MSiiA/balance" => serde_json::json!({
+          "BalanceResponse": {"accountId": "83405188", "accountDescription": "Brokerage", "totalAccountValue": 1200.5}
+        }),
+        path => return Err(anyhow::anyhow!("unexpected request {}", path)),
+      };
+      Ok(
+        Response::builder()
+          .header("content-type", "application/json")
+          .header("set-cookie", "session=secret")
+          .body(serde_json::to_vec(&body)?.into())?,
+      )
+    }
+  }
+
+  async fn session(transport: impl Transport + 'static) -> Arc<Session<Memstore>> {
+    let store = Memstore::new();
+    store
+      .put("etradesandbox", "access_token_key", "the-token")
+      .await
+      .unwrap();
+    store
+      .put("etradesandbox", "access_token_secret", "the-secret")
+      .await
+      .unwrap();
+    let session = Session::new(Mode::Sandbox, store)
+      .non_interactive()
+      .with_transport(transport);
+    session.initialize("key".into(), "secret".into()).await.unwrap();
+    Arc::new(session)
+  }
+
+  #[tokio::test]
+  async fn records_and_replays_redacted_interactions() {
+    crate::tests::init();
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["accounts.yaml", "accounts.json"] {
+      let path = dir.path().join(name);
+      let recorder = Arc::new(Recorder::new(Fake, &path));
+      let recording = accounts::Api::new(session(recorder.clone()).await);
+      let list = recording.list().await.unwrap();
+      assert_eq!(list[0].account_id_key, "dBZOKt9xDrtRSAOl4MSiiA");
+      recording
+        .balance("dBZOKt9xDrtRSAOl4MSiiA", Default::default())
+        .await
+        .unwrap();
+      assert!(!path.exists(), "the cassette is written at the end");
+      // the yaml cassette is written when the recorder is dropped along with the session
+      if name.ends_with(".json") {
+        recorder.finish().unwrap();
+      } else {
+        drop((recording, recorder));
+      }
+
+      let content = std::fs::read_to_string(&path).unwrap();
+      for secret in [
+        "83405188",
+        "dBZOKt9xDrtRSAOl4MSiiA",
+        "YDHjz6wnsGRbsUc7fJ0ivQ",
+        "the-token",
+        "OAuth",
+        "session=secret",
+      ] {
+        assert!(!content.contains(secret), "{} leaked into {}", secret, name);
+      }
+      let cassette = Cassette::load(&path).unwrap();
+      assert_eq!(cassette.interactions.len(), 2);
+      assert_eq!(cassette.interactions[1].request.path, "/v1/accounts/ACCOUNT-2/balance");
+
+      let replayer = Arc::new(Replayer::from_file(&path).unwrap());
+      let replaying = accounts::Api::new(session(replayer.clone()).await);
+      let list = replaying.list().await.unwrap();
+      let key = &list[0].account_id_key;
+      assert_eq!(key, "ACCOUNT-2");
+      let balance = replaying.balance(key, Default::default()).await.unwrap();
+      assert_eq!(balance.account_id, "ACCOUNT-1");
+      assert_eq!(replayer.remaining(), 0);
+
+      let err = replaying.balance(key, Default::default()).await.unwrap_err();
+      assert!(err
+        .to_string()
+        .contains("no recorded interaction left for GET /v1/accounts/ACCOUNT-2/balance"));
+    }
+  }
+
+  #[tokio::test]
+  async fn records_without_failing_the_requests() {
+    let dir = tempfile::tempdir().unwrap();
+    let recorder = Arc::new(Recorder::new(Fake, dir.path().join("missing").join("accounts.yaml")));
+    let request = Request::post("https://api.etrade.com/v1/accounts/list")
+      .body(Body::from(vec![0xff, 0xfe]))
+      .unwrap();
+    recorder.send(request).await.unwrap();
+    assert_eq!(
+      recorder.cassette().interactions[0].request.body.as_deref(),
+      Some("\u{fffd}\u{fffd}")
+    );
+    assert!(recorder.finish().is_err());
+  }
+
+  #[test]
+  fn redacts_account_ids_by_structure() {
+    let mut redactor = super::Redactor::default();
+    let mut interaction = super::Interaction {
+      request: super::RecordedRequest {
+        method: Method::GET.to_string(),
+        path: "/v1/accounts/dBZOKt9xDrtRSAOl4MSiiA/orders".into(),
+        query: Some("symbol=AAPL&note=dBZOKt9xDrtRSAOl4MSiiA".into()),
+        ..Default::default()
+      },
+      response: super::RecordedResponse {
+        status: 200,
+        body: serde_json::json!({
+          "OrdersResponse": {
+            "accountId": 83405188,
+            "Order": [{
+              "orderId": 834051881,
+              "accountIdKey": "dBZOKt9xDrtRSAOl4MSiiA",
+              "orderDescription": "83405188",
+              "note": "order 834051881"
+            }]
+          }
+        })
+        .to_string(),
+        ..Default::default()
+      },
+    };
+    redactor.redact(&mut interaction);
+
+    assert_eq!(interaction.request.path, "/v1/accounts/ACCOUNT-1/orders");
+    assert_eq!(interaction.request.query.as_deref(), Some("symbol=AAPL&note=ACCOUNT-1"));
+    let body: serde_json::Value = serde_json::from_str(&interaction.response.body).unwrap();
+    assert_eq!(
+      body,
+      serde_json::json!({
+        "OrdersResponse": {
+          "accountId": 2,
+          "Order": [{
+            "orderId": 834051881,
+            "accountIdKey": "ACCOUNT-1",
+            "orderDescription": "ACCOUNT-2",
+            "note": "order 834051881"
+          }]
+        }
+      })
+    );
+
+    let body = redactor.redact_body("account 83405188, key dBZOKt9xDrtRSAOl4MSiiA");
+    assert_eq!(body, "account ACCOUNT-2, key ACCOUNT-1");
+  }
+
+  #[tokio::test]
+  async fn redacts_oauth_tokens() {
+    let mut redactor = super::Redactor::default();
+    let mut interaction = super::Interaction {
+      request: super::RecordedRequest {
+        method: Method::GET.to_string(),
+        path: "/oauth/request_token".into(),
+        ..Default::default()
+      },
+      response: super::RecordedResponse {
+        status: 200,
+        body: "oauth_token=abc&oauth_token_secret=def&oauth_callback_confirmed=true".into(),
+        ..Default::default()
+      },
+    };
+    redactor.redact(&mut interaction);
+    assert_eq!(
+      interaction.response.body,
+      "oauth_token=REDACTED&oauth_token_secret=REDACTED&oauth_callback_confirmed=true"
+    );
+  }
+}
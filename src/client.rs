@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use crate::middleware::Middleware;
+use crate::transport::Transport;
+use crate::{accounts, alerts, options, orders, transactions};
+use crate::{CallbackProvider, Mode, Session, Store};
+
+/// Owns a [`Session`] and hands out the apis that share it.
+pub struct Client<T: Store> {
+  session: Arc<Session<T>>,
+}
+
+impl<T> Client<T>
+where
+  T: Store,
+{
+  /// Starts configuring a client, by default it talks to E*Trade and prompts for the verifier code with
+  /// [`OOB`](crate::OOB), like [`Session::new`].
+  pub fn builder(mode: Mode, store: T) -> ClientBuilder<T> {
+    ClientBuilder {
+      session: Session::new(mode, store),
+    }
+  }
+
+  pub fn new(session: Arc<Session<T>>) -> Self {
+    Self { session }
+  }
+
+  pub fn session(&self) -> &Arc<Session<T>> {
+    &self.session
+  }
+
+  pub fn accounts(&self) -> accounts::Api<T> {
+    accounts::Api::new(self.session.clone())
+  }
+
+  pub fn orders(&self) -> orders::Api<T> {
+    orders::Api::new(self.session.clone())
+  }
+
+  pub fn market(&self) -> options::Api<T> {
+    options::Api::new(self.session.clone())
+  }
+
+  pub fn alerts(&self) -> alerts::Api<T> {
+    alerts::Api::new(self.session.clone())
+  }
+
+  pub fn transactions(&self) -> transactions::Api<T> {
+    transactions::Api::new(self.session.clone())
+  }
+}
+
+impl<T> Clone for Client<T>
+where
+  T: Store,
+{
+  fn clone(&self) -> Self {
+    Self {
+      session: self.session.clone(),
+    }
+  }
+}
+
+/// Configures the session of a [`Client`], the methods match the `with_*` methods of [`Session`].
+pub struct ClientBuilder<T: Store> {
+  session: Session<T>,
+}
+
+impl<T> ClientBuilder<T>
+where
+  T: Store,
+{
+  /// See [`Session::with_profile`].
+  pub fn profile(mut self, profile: impl Into<String>) -> Self {
+    self.session = self.session.with_profile(profile);
+    self
+  }
+
+  /// See [`Session::with_callbacks`].
+  pub fn callbacks(mut self, callbacks: impl CallbackProvider + 'static) -> Self {
+    self.session = self.session.with_callbacks(callbacks);
+    self
+  }
+
+  /// See [`Session::non_interactive`].
+  pub fn non_interactive(mut self) -> Self {
+    self.session = self.session.non_interactive();
+    self
+  }
+
+  /// See [`Session::with_transport`].
+  pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+    self.session = self.session.with_transport(transport);
+    self
+  }
+
+  /// See [`Session::with_middleware`].
+  pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+    self.session = self.session.with_middleware(middleware);
+    self
+  }
+
+  /// See [`Session::with_base_url`].
+  pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+    self.session = self.session.with_base_url(base_url);
+    self
+  }
+
+  pub fn build(self) -> Client<T> {
+    Client::new(Arc::new(self.session))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Client;
+  use crate::accounts::BalanceRequest;
+  use crate::alerts::ListAlertsRequest;
+  use crate::options::GetQuotesRequest;
+  use crate::orders::ListOrdersRequest;
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::transactions::ListTransactionsRequest;
+  use crate::{Memstore, Mode};
+
+  #[tokio::test]
+  async fn builds_a_client_sharing_one_session() {
+    let fixtures = Fixtures::sample();
+    let (key, secret) = (fixtures.consumer_key.clone(), fixtures.consumer_secret.clone());
+    let server = FakeServer::start(fixtures).await.unwrap();
+    let client = Client::builder(Mode::Sandbox, Memstore::new())
+      .profile("alice")
+      .base_url(server.url())
+      .callbacks(server.authorizer())
+      .build();
+    client.session().initialize(key, secret).await.unwrap();
+    assert_eq!(client.session().profile(), "alice");
+
+    let account = client.accounts().list().await.unwrap().remove(0);
+    let key = &account.account_id_key;
+    let balance = client.accounts().balance(key, BalanceRequest::default()).await.unwrap();
+    assert_eq!(balance.computed_balance.cash_balance, 10_000.0);
+    assert!(client
+      .orders()
+      .list(key, ListOrdersRequest::default())
+      .await
+      .unwrap()
+      .order
+      .is_empty());
+    let quotes = client
+      .market()
+      .quotes("AAPL", GetQuotesRequest::default())
+      .await
+      .unwrap();
+    assert_eq!(quotes.quote_data.len(), 1);
+    client.alerts().list(ListAlertsRequest::default()).await.unwrap();
+    client
+      .transactions()
+      .list(key, ListTransactionsRequest::default())
+      .await
+      .unwrap();
+  }
+}
@@ -0,0 +1,432 @@
+//! Traits over the apis, so code can depend on them instead of the `Api` structs and swap in the
+//! [`PaperBroker`](crate::paper::PaperBroker) or the mocks of [`crate::testing`].
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::accounts::{
+  self, Account, BalanceRequest, BalanceResponse, PortfolioRequest, PortfolioResponse, PositionLotsResponse,
+};
+use crate::alerts::{
+  self, AlertDetailsResponse, AlertsResponse, DeleteAlertsResponse, FailedAlerts, ListAlertsRequest, MAX_ALERTS,
+};
+use crate::options::{
+  self, GetOptionChainsRequest, GetOptionExpireDatesRequest, GetQuotesRequest, LookupResponse, OptionChainResponse,
+  OptionExpireDateResponse, QuoteResponse,
+};
+use crate::orders::{
+  self, CancelOrderRequest, CancelOrderResponse, ListOrdersRequest, OrdersResponse, PlaceOrderRequest,
+  PlaceOrderResponse, PreviewOrderRequest, PreviewOrderResponse,
+};
+use crate::paper::{PaperBroker, QuoteSource};
+use crate::transactions::{self, ListTransactionsRequest, TransactionDetailsResponse, TransactionListResponse};
+use crate::Store;
+
+#[async_trait]
+pub trait AccountsClient: Send + Sync {
+  async fn list(&self) -> Result<Vec<Account>>;
+  async fn balance(&self, account_id_key: &str, params: BalanceRequest<'_>) -> Result<BalanceResponse>;
+  async fn portfolio(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse>;
+  async fn position_lots(&self, account_id_key: &str, position_id: &str) -> Result<PositionLotsResponse>;
+
+  /// Follows the page numbers to list every position of the portfolio, each page adds its account portfolio to the
+  /// response and the totals are the ones of the first page.
+  async fn portfolio_all(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    let mut response = self.portfolio(account_id_key, params.clone()).await?;
+    let mut next = response.next_page();
+    while let Some(page_number) = next {
+      let page = self
+        .portfolio(
+          account_id_key,
+          PortfolioRequest {
+            page_number: Some(page_number),
+            ..params.clone()
+          },
+        )
+        .await?;
+      next = page.next_page().filter(|n| *n > page_number);
+      response.account_portfolio.extend(page.account_portfolio);
+    }
+    Ok(response)
+  }
+}
+
+#[async_trait]
+pub trait OrdersClient: Send + Sync {
+  async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse>;
+  async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse>;
+  async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse>;
+  async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse>;
+  async fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse>;
+  async fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse>;
+}
+
+#[async_trait]
+pub trait MarketClient: Send + Sync {
+  async fn quotes(&self, symbols: &str, params: GetQuotesRequest) -> Result<QuoteResponse>;
+  async fn product(&self, search: &str) -> Result<LookupResponse>;
+  async fn chains(&self, params: &GetOptionChainsRequest<'_>) -> Result<OptionChainResponse>;
+  async fn expire_dates(&self, params: &GetOptionExpireDatesRequest<'_>) -> Result<OptionExpireDateResponse>;
+}
+
+#[async_trait]
+pub trait AlertsClient: Send + Sync {
+  async fn list(&self, params: ListAlertsRequest) -> Result<AlertsResponse>;
+  async fn details(&self, alert_id: &str, html: bool) -> Result<AlertDetailsResponse>;
+  async fn delete(&self, alert_id: &str) -> Result<DeleteAlertsResponse>;
+
+  /// Lists the alerts again with a larger `count` when E*Trade has more than it returned. E*Trade lists at most
+  /// [`MAX_ALERTS`] and has no way to ask for the next ones: past that the response holds only the first
+  /// [`MAX_ALERTS`], `total_alerts` keeps the full count and a warning is logged.
+  async fn list_all(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    let mut response = self.list(params.clone()).await?;
+    if response.total_alerts as usize > MAX_ALERTS {
+      warn!(
+        "listing {} of {} alerts, E*Trade lists at most {}",
+        MAX_ALERTS, response.total_alerts, MAX_ALERTS
+      );
+    }
+    let total = (response.total_alerts.max(0) as usize).min(MAX_ALERTS);
+    if response.alerts.len() < total {
+      response = self
+        .list(ListAlertsRequest {
+          count: Some(total),
+          ..params
+        })
+        .await?;
+    }
+    Ok(response)
+  }
+
+  /// Deletes the alerts in one request, the ids that couldn't be deleted are in `failed_alerts`.
+  async fn delete_many(&self, alert_ids: &[i64]) -> Result<DeleteAlertsResponse> {
+    if alert_ids.is_empty() {
+      return Ok(DeleteAlertsResponse {
+        result: "SUCCESS".to_string(),
+        failed_alerts: FailedAlerts::default(),
+      });
+    }
+    let ids = alert_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",");
+    self.delete(&ids).await
+  }
+
+  /// Marks the alerts as read, E*Trade does that when their details are fetched.
+  async fn mark_read(&self, alert_ids: &[i64]) -> Result<()> {
+    for id in alert_ids {
+      self.details(&id.to_string(), false).await?;
+    }
+    Ok(())
+  }
+
+  /// The alert after this one, `None` for the last one.
+  async fn next_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    match alert.next_id() {
+      Some(id) => Ok(Some(self.details(&id.to_string(), html).await?)),
+      None => Ok(None),
+    }
+  }
+
+  /// The alert before this one, `None` for the first one.
+  async fn prev_alert(&self, alert: &AlertDetailsResponse, html: bool) -> Result<Option<AlertDetailsResponse>> {
+    match alert.prev_id() {
+      Some(id) => Ok(Some(self.details(&id.to_string(), html).await?)),
+      None => Ok(None),
+    }
+  }
+}
+
+#[async_trait]
+pub trait TransactionsClient: Send + Sync {
+  async fn list(&self, account_id_key: &str, params: ListTransactionsRequest<'_>) -> Result<TransactionListResponse>;
+  async fn details(&self, account_id_key: &str, tranid: &str, store_id: &str) -> Result<TransactionDetailsResponse>;
+
+  /// Follows the page markers to list every transaction, while E*Trade says there are more.
+  async fn list_all(
+    &self,
+    account_id_key: &str,
+    params: ListTransactionsRequest<'_>,
+  ) -> Result<Vec<TransactionDetailsResponse>> {
+    let mut transactions = vec![];
+    let mut marker: Option<String> = None;
+    loop {
+      let page = self
+        .list(
+          account_id_key,
+          ListTransactionsRequest {
+            marker: marker.as_deref().or(params.marker),
+            ..params.clone()
+          },
+        )
+        .await?;
+      transactions.extend(page.transaction);
+      if !page.more_transactions || page.page_marker.is_empty() || marker.as_deref() == Some(page.page_marker.as_str())
+      {
+        break;
+      }
+      marker = Some(page.page_marker);
+    }
+    Ok(transactions)
+  }
+}
+
+#[async_trait]
+impl<T> AccountsClient for accounts::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn list(&self) -> Result<Vec<Account>> {
+    accounts::Api::list(self).await
+  }
+
+  async fn balance(&self, account_id_key: &str, params: BalanceRequest<'_>) -> Result<BalanceResponse> {
+    accounts::Api::balance(self, account_id_key, params).await
+  }
+
+  async fn portfolio(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    accounts::Api::portfolio(self, account_id_key, params).await
+  }
+
+  async fn position_lots(&self, account_id_key: &str, position_id: &str) -> Result<PositionLotsResponse> {
+    accounts::Api::position_lots(self, account_id_key, position_id).await
+  }
+}
+
+#[async_trait]
+impl<T> OrdersClient for orders::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
+    orders::Api::list(self, account_id_key, params).await
+  }
+
+  async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
+    orders::Api::preview(self, account_id_key, params).await
+  }
+
+  async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
+    orders::Api::place(self, account_id_key, params).await
+  }
+
+  async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
+    orders::Api::cancel(self, account_id_key, params).await
+  }
+
+  async fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse> {
+    orders::Api::change_preview(self, account_id_key, order_id, params).await
+  }
+
+  async fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse> {
+    orders::Api::change_order(self, account_id_key, order_id, params).await
+  }
+}
+
+#[async_trait]
+impl<Q> OrdersClient for PaperBroker<Q>
+where
+  Q: QuoteSource,
+{
+  async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
+    PaperBroker::list(self, account_id_key, params).await
+  }
+
+  async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
+    PaperBroker::preview(self, account_id_key, params).await
+  }
+
+  async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
+    PaperBroker::place(self, account_id_key, params).await
+  }
+
+  async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
+    PaperBroker::cancel(self, account_id_key, params).await
+  }
+
+  async fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse> {
+    PaperBroker::change_preview(self, account_id_key, order_id, params).await
+  }
+
+  async fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse> {
+    PaperBroker::change_order(self, account_id_key, order_id, params).await
+  }
+}
+
+#[async_trait]
+impl<T> MarketClient for options::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn quotes(&self, symbols: &str, params: GetQuotesRequest) -> Result<QuoteResponse> {
+    options::Api::quotes(self, symbols, params).await
+  }
+
+  async fn product(&self, search: &str) -> Result<LookupResponse> {
+    options::Api::product(self, search).await
+  }
+
+  async fn chains(&self, params: &GetOptionChainsRequest<'_>) -> Result<OptionChainResponse> {
+    options::Api::chains(self, params).await
+  }
+
+  async fn expire_dates(&self, params: &GetOptionExpireDatesRequest<'_>) -> Result<OptionExpireDateResponse> {
+    options::Api::expire_dates(self, params).await
+  }
+}
+
+#[async_trait]
+impl<T> AlertsClient for alerts::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn list(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    alerts::Api::list(self, params).await
+  }
+
+  async fn details(&self, alert_id: &str, html: bool) -> Result<AlertDetailsResponse> {
+    alerts::Api::details(self, alert_id, html).await
+  }
+
+  async fn delete(&self, alert_id: &str) -> Result<DeleteAlertsResponse> {
+    alerts::Api::delete(self, alert_id).await
+  }
+}
+
+#[async_trait]
+impl<T> TransactionsClient for transactions::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn list(&self, account_id_key: &str, params: ListTransactionsRequest<'_>) -> Result<TransactionListResponse> {
+    transactions::Api::list(self, account_id_key, params).await
+  }
+
+  async fn details(&self, account_id_key: &str, tranid: &str, store_id: &str) -> Result<TransactionDetailsResponse> {
+    transactions::Api::details(self, account_id_key, tranid, store_id).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::{AccountsClient, AlertsClient, OrdersClient};
+  use crate::accounts::{self, BalanceRequest, PortfolioRequest};
+  use crate::alerts::{Alert, AlertsResponse, ListAlertsRequest, MAX_ALERTS};
+  use crate::orders::{self, ListOrdersRequest};
+  use crate::paper::{PaperBroker, ReplayQuotes};
+  use crate::testing::{FakeServer, Fixtures, MockAccounts, MockAlerts};
+  use crate::Memstore;
+
+  async fn cash(accounts: &dyn AccountsClient) -> f64 {
+    let account = accounts.list().await.unwrap().remove(0);
+    let balance = accounts
+      .balance(&account.account_id_key, BalanceRequest::default())
+      .await
+      .unwrap();
+    balance.computed_balance.cash_balance
+  }
+
+  async fn order_count(orders: &dyn OrdersClient, account_id_key: &str) -> usize {
+    orders
+      .list(account_id_key, ListOrdersRequest::default())
+      .await
+      .unwrap()
+      .order
+      .len()
+  }
+
+  #[tokio::test]
+  async fn apis_and_the_paper_broker_implement_the_clients() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let session = Arc::new(server.session(Memstore::new()).await.unwrap());
+    assert_eq!(cash(&accounts::Api::new(session.clone())).await, 10_000.0);
+
+    let live: Box<dyn OrdersClient> = Box::new(orders::Api::new(session));
+    let paper: Box<dyn OrdersClient> = Box::new(PaperBroker::new("paper", 0.0, ReplayQuotes::new()));
+    assert_eq!(order_count(live.as_ref(), "fake-account-key").await, 0);
+    assert_eq!(order_count(paper.as_ref(), "paper").await, 0);
+  }
+
+  #[tokio::test]
+  async fn follows_the_portfolio_pages() {
+    let fixtures = Fixtures::sample();
+    let key = fixtures.accounts[0].account_id_key.clone();
+    let mut first = fixtures.portfolios[&key].clone();
+    first.account_portfolio[0].total_no_of_pages = 2;
+    first.account_portfolio[0].next_page_no = "2".to_string();
+    let mut second = fixtures.portfolios[&key].clone();
+    second.account_portfolio[0].total_no_of_pages = 2;
+    second.account_portfolio[0].position[0].product.symbol = "MSFT".to_string();
+    let accounts = MockAccounts::new().on_portfolio(Ok(first)).on_portfolio(Ok(second));
+
+    let params = PortfolioRequest {
+      count: Some(1),
+      ..Default::default()
+    };
+    let portfolio = accounts.portfolio_all(&key, params).await.unwrap();
+    let symbols: Vec<&str> = portfolio
+      .account_portfolio
+      .iter()
+      .flat_map(|p| &p.position)
+      .map(|p| p.product.symbol.as_str())
+      .collect();
+    assert_eq!(symbols, ["AAPL", "MSFT"]);
+    let calls = accounts.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[1].args["params"]["pageNumber"], 2);
+    assert_eq!(calls[1].args["params"]["count"], 1);
+  }
+
+  #[tokio::test]
+  async fn lists_at_most_the_alerts_etrade_allows() {
+    let response = |count: usize| AlertsResponse {
+      total_alerts: 450,
+      alerts: (0..count as i64)
+        .map(|id| Alert {
+          id,
+          ..Default::default()
+        })
+        .collect(),
+    };
+    let alerts = MockAlerts::new()
+      .on_list(Ok(response(25)))
+      .on_list(Ok(response(MAX_ALERTS)));
+
+    let listed = alerts.list_all(ListAlertsRequest::default()).await.unwrap();
+    assert_eq!(listed.alerts.len(), MAX_ALERTS);
+    assert_eq!(listed.total_alerts, 450);
+    let calls = alerts.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[1].args["params"]["count"], MAX_ALERTS);
+  }
+}
@@ -0,0 +1,239 @@
+use std::sync::Once;
+
+use anyhow::{anyhow, Result};
+use libsodium_sys as sodium;
+use secstr::SecVec;
+
+pub(crate) const KEY_LEN: usize = sodium::crypto_secretbox_KEYBYTES as usize;
+pub(crate) const SALT_LEN: usize = sodium::crypto_pwhash_SALTBYTES as usize;
+const NONCE_LEN: usize = sodium::crypto_secretbox_NONCEBYTES as usize;
+const MAC_LEN: usize = sodium::crypto_secretbox_MACBYTES as usize;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    if unsafe { sodium::sodium_init() } < 0 {
+      panic!("failed to initialize libsodium");
+    }
+  });
+}
+
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+  init();
+  let mut buf = [0u8; N];
+  unsafe { sodium::randombytes_buf(buf.as_mut_ptr() as *mut _, N) };
+  buf
+}
+
+/// Overwrites the buffer with zeroes in a way the compiler won't optimize away.
+pub(crate) fn wipe(buf: &mut [u8]) {
+  init();
+  unsafe { sodium::sodium_memzero(buf.as_mut_ptr() as *mut _, buf.len()) };
+}
+
+/// A symmetric key for libsodium's secretbox, wiped from memory when dropped.
+pub(crate) struct SecretKey([u8; KEY_LEN]);
+
+impl SecretKey {
+  /// Generates a random key.
+  pub fn random() -> Self {
+    Self(random_bytes())
+  }
+
+  /// Derives the key from a passphrase with argon2id.
+  pub fn from_passphrase(passphrase: &[u8], salt: &[u8; SALT_LEN]) -> Result<Self> {
+    init();
+    let mut key = [0u8; KEY_LEN];
+    let rc = unsafe {
+      sodium::crypto_pwhash(
+        key.as_mut_ptr(),
+        KEY_LEN as _,
+        passphrase.as_ptr() as *const _,
+        passphrase.len() as _,
+        salt.as_ptr(),
+        sodium::crypto_pwhash_opslimit_interactive() as _,
+        sodium::crypto_pwhash_memlimit_interactive(),
+        sodium::crypto_pwhash_ALG_ARGON2ID13 as _,
+      )
+    };
+    if rc != 0 {
+      return Err(anyhow!("failed to derive a key from the passphrase"));
+    }
+    Ok(Self(key))
+  }
+
+  /// Derives the key by hashing the content of a key file.
+  pub fn from_key_material(material: &[u8]) -> Result<Self> {
+    init();
+    if material.len() < KEY_LEN {
+      return Err(anyhow!("key material needs at least {} bytes", KEY_LEN));
+    }
+    let mut key = [0u8; KEY_LEN];
+    let rc = unsafe {
+      sodium::crypto_generichash(
+        key.as_mut_ptr(),
+        KEY_LEN,
+        material.as_ptr(),
+        material.len() as _,
+        std::ptr::null(),
+        0,
+      )
+    };
+    if rc != 0 {
+      return Err(anyhow!("failed to derive a key from the key material"));
+    }
+    Ok(Self(key))
+  }
+
+  /// Encrypts the plaintext, the result is the random nonce followed by the ciphertext.
+  pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+    let nonce: [u8; NONCE_LEN] = random_bytes();
+    let mut sealed = vec![0u8; NONCE_LEN + MAC_LEN + plaintext.len()];
+    sealed[..NONCE_LEN].copy_from_slice(&nonce);
+    unsafe {
+      sodium::crypto_secretbox_easy(
+        sealed[NONCE_LEN..].as_mut_ptr(),
+        plaintext.as_ptr(),
+        plaintext.len() as _,
+        nonce.as_ptr(),
+        self.0.as_ptr(),
+      )
+    };
+    sealed
+  }
+
+  /// Decrypts the output of [`SecretKey::seal`].
+  pub fn open(&self, sealed: &[u8]) -> Result<SecVec<u8>> {
+    if sealed.len() < NONCE_LEN + MAC_LEN {
+      return Err(anyhow!("encrypted data is truncated"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let mut plaintext = SecVec::new(vec![0u8; ciphertext.len() - MAC_LEN]);
+    let rc = unsafe {
+      sodium::crypto_secretbox_open_easy(
+        plaintext.unsecure_mut().as_mut_ptr(),
+        ciphertext.as_ptr(),
+        ciphertext.len() as _,
+        nonce.as_ptr(),
+        self.0.as_ptr(),
+      )
+    };
+    if rc != 0 {
+      return Err(anyhow!(
+        "failed to decrypt, the key is wrong or the data was tampered with"
+      ));
+    }
+    Ok(plaintext)
+  }
+}
+
+impl Drop for SecretKey {
+  fn drop(&mut self) {
+    wipe(&mut self.0);
+  }
+}
+
+/// The output of [`SecretKey::seal`], wiped from memory when dropped.
+pub(crate) struct Sealed(Vec<u8>);
+
+impl Sealed {
+  pub fn new(key: &SecretKey, plaintext: &[u8]) -> Self {
+    Self(key.seal(plaintext))
+  }
+
+  pub fn open(&self, key: &SecretKey) -> Result<SecVec<u8>> {
+    key.open(&self.0)
+  }
+
+  fn wipe(&mut self) {
+    wipe(&mut self.0);
+    #[cfg(test)]
+    tests::WIPED.with(|wiped| wiped.set(wiped.get() + 1));
+  }
+}
+
+impl AsRef<[u8]> for Sealed {
+  fn as_ref(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl std::fmt::Debug for Sealed {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("Sealed(..)")
+  }
+}
+
+impl Drop for Sealed {
+  fn drop(&mut self) {
+    self.wipe();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::Cell;
+
+  use super::{random_bytes, Sealed, SecretKey, KEY_LEN, SALT_LEN};
+  use crate::{Memstore, Store};
+
+  thread_local! {
+    /// The number of [`Sealed`] values wiped on this thread.
+    pub(super) static WIPED: Cell<usize> = const { Cell::new(0) };
+  }
+
+  fn wiped() -> usize {
+    WIPED.with(|wiped| wiped.replace(0))
+  }
+
+  fn random_key() -> SecretKey {
+    SecretKey::from_key_material(&random_bytes::<KEY_LEN>()).unwrap()
+  }
+
+  #[test]
+  fn wipes_sealed_values() {
+    let key = random_key();
+    let mut sealed = Sealed::new(&key, b"secret");
+    assert!(sealed.as_ref().iter().any(|b| *b != 0));
+    sealed.wipe();
+    assert!(!sealed.as_ref().is_empty() && sealed.as_ref().iter().all(|b| *b == 0));
+    assert!(sealed.open(&key).is_err());
+  }
+
+  #[tokio::test(flavor = "current_thread")]
+  async fn memstore_wipes_replaced_and_deleted_values() {
+    let store = Memstore::new();
+    store.put("ns", "key", "first").await.unwrap();
+    wiped();
+
+    store.put("ns", "key", "second").await.unwrap();
+    assert_eq!(wiped(), 1, "the replaced value is wiped");
+    assert_eq!(store.get("ns", "key").await.unwrap().unwrap().unsecure(), "second");
+    assert_eq!(wiped(), 0, "reading keeps the value");
+
+    store.del("ns", "key").await.unwrap();
+    assert_eq!(wiped(), 1, "the deleted value is wiped");
+    assert!(store.get("ns", "key").await.unwrap().is_none());
+  }
+
+  #[test]
+  fn seals_and_opens() {
+    let key = random_key();
+    let sealed = key.seal(b"hello");
+    assert_ne!(&sealed[sealed.len() - 5..], b"hello");
+    assert_eq!(key.open(&sealed).unwrap().unsecure(), b"hello");
+    assert!(random_key().open(&sealed).is_err());
+  }
+
+  #[test]
+  fn derives_keys_from_passphrases() {
+    let salt = [7u8; SALT_LEN];
+    let sealed = SecretKey::from_passphrase(b"correct horse", &salt)
+      .unwrap()
+      .seal(b"hello");
+    let same = SecretKey::from_passphrase(b"correct horse", &salt).unwrap();
+    assert_eq!(same.open(&sealed).unwrap().unsecure(), b"hello");
+    let other = SecretKey::from_passphrase(b"battery staple", &salt).unwrap();
+    assert!(other.open(&sealed).is_err());
+  }
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use secstr::SecUtf8;
+
+use crate::Store;
+
+/// The object safe version of [`Store`], so the store can be picked at runtime.
+///
+/// Every [`Store`] implements it, and `Arc<dyn DynStore>` implements [`Store`] so it can back a
+/// [`Session`](crate::Session):
+///
+/// ```
+/// use std::sync::Arc;
+/// use etrade::{DynStore, Memstore, Mode, Session};
+///
+/// let store: Arc<dyn DynStore> = Arc::new(Memstore::new());
+/// let session = Session::new(Mode::Sandbox, store);
+/// ```
+#[async_trait]
+pub trait DynStore: Send + Sync {
+  async fn put(&self, namespace: &str, key: &str, value: SecUtf8) -> Result<()>;
+  async fn del(&self, namespace: &str, key: &str) -> Result<()>;
+  async fn get(&self, namespace: &str, key: &str) -> Result<Option<SecUtf8>>;
+  async fn list(&self, namespace: &str) -> Result<Vec<String>>;
+  async fn namespaces(&self) -> Result<Vec<String>>;
+}
+
+#[async_trait]
+impl<T> DynStore for T
+where
+  T: Store + Send + Sync,
+{
+  async fn put(&self, namespace: &str, key: &str, value: SecUtf8) -> Result<()> {
+    Store::put(self, namespace, key, value).await
+  }
+
+  async fn del(&self, namespace: &str, key: &str) -> Result<()> {
+    Store::del(self, namespace, key).await
+  }
+
+  async fn get(&self, namespace: &str, key: &str) -> Result<Option<SecUtf8>> {
+    Store::get(self, namespace, key).await
+  }
+
+  async fn list(&self, namespace: &str) -> Result<Vec<String>> {
+    Store::list(self, namespace).await
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    Store::namespaces(self).await
+  }
+}
+
+#[async_trait]
+impl Store for Arc<dyn DynStore> {
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    DynStore::put(self.as_ref(), &namespace.into(), &key.into(), value.into()).await
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    DynStore::del(self.as_ref(), namespace.as_ref(), key.as_ref()).await
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    DynStore::get(self.as_ref(), namespace.as_ref(), key.as_ref()).await
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    DynStore::list(self.as_ref(), namespace.as_ref()).await
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    DynStore::namespaces(self.as_ref()).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::DynStore;
+  use crate::{accounts, Memstore, Mode, Session};
+
+  #[tokio::test]
+  async fn test_dyn_store() {
+    let store: Arc<dyn DynStore> = Arc::new(Memstore::new());
+    crate::testing::verify_store(store.clone()).await;
+    crate::testing::verify_listing_store(store).await;
+  }
+
+  #[tokio::test]
+  async fn sessions_use_stores_picked_at_runtime() {
+    let store: Arc<dyn DynStore> = Arc::new(Memstore::new());
+    let session = Arc::new(Session::new(Mode::Sandbox, store));
+    session.initialize("key".into(), "secret".into()).await.unwrap();
+    assert_eq!(session.profiles().await.unwrap(), vec!["default"]);
+    let _accounts = accounts::Api::new(session);
+  }
+}
@@ -0,0 +1,72 @@
+//! Serde helpers for the epoch timestamps of E*Trade, used with `#[serde(with = "epoch::seconds")]` on
+//! `Option<DateTime<Utc>>` fields. A missing date is sent as 0 or null.
+
+/// Maps the epoch seconds of E*Trade to a date, where 0 or null means there is no date.
+pub(crate) mod seconds {
+  use chrono::{DateTime, TimeZone, Utc};
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(date.map(|d| d.timestamp()).unwrap_or_default())
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    match Option::<i64>::deserialize(deserializer)? {
+      None | Some(0) => Ok(None),
+      Some(secs) => Utc
+        .timestamp_opt(secs, 0)
+        .single()
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid epoch seconds: {}", secs))),
+    }
+  }
+}
+
+/// Maps the epoch milliseconds of E*Trade to a date, where 0 or null means there is no date.
+pub(crate) mod millis {
+  use chrono::{DateTime, TimeZone, Utc};
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(date.map(|d| d.timestamp_millis()).unwrap_or_default())
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+    match Option::<i64>::deserialize(deserializer)? {
+      None | Some(0) => Ok(None),
+      Some(millis) => Utc
+        .timestamp_millis_opt(millis)
+        .single()
+        .map(Some)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid epoch milliseconds: {}", millis))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{DateTime, Utc};
+  use serde_json::json;
+
+  #[derive(Debug, Deserialize, Serialize)]
+  struct Dates {
+    #[serde(with = "super::seconds")]
+    seconds: Option<DateTime<Utc>>,
+    #[serde(with = "super::millis")]
+    millis: Option<DateTime<Utc>>,
+  }
+
+  #[test]
+  fn maps_zero_and_null_to_no_date() {
+    let dates: Dates = serde_json::from_value(json!({"seconds": null, "millis": 0})).unwrap();
+    assert!(dates.seconds.is_none() && dates.millis.is_none());
+    assert_eq!(
+      serde_json::to_value(&dates).unwrap(),
+      json!({"seconds": 0, "millis": 0})
+    );
+
+    let dates: Dates = serde_json::from_value(json!({"seconds": 1700000000, "millis": 1700000000123i64})).unwrap();
+    assert_eq!(dates.seconds.unwrap().timestamp(), 1_700_000_000);
+    assert_eq!(dates.millis.unwrap().timestamp_millis(), 1_700_000_000_123);
+  }
+}
@@ -0,0 +1,427 @@
+use std::{
+  collections::BTreeMap,
+  fs::{self, File, OpenOptions},
+  io::{ErrorKind, Read, Write},
+  path::{Path, PathBuf},
+  sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use fs2::FileExt;
+use secstr::{SecUtf8, SecVec};
+
+use crate::crypto::{random_bytes, SecretKey, SALT_LEN};
+use crate::Store;
+
+const MAGIC: &[u8; 8] = b"ETRADEFS";
+const VERSION: u8 = 1;
+const KDF_KEYFILE: u8 = 0;
+const KDF_PASSPHRASE: u8 = 1;
+const HEADER_LEN: usize = MAGIC.len() + 2 + SALT_LEN;
+
+type Data = BTreeMap<String, BTreeMap<String, SecUtf8>>;
+
+/// A store that persists the secrets in a single file, encrypted with a key derived from a passphrase or a key file.
+///
+/// Every operation reads the file from disk while holding a lock on a `<path>.lock` sidecar file, so several
+/// processes can share the same store. Writes go to a temporary file that is renamed over the store.
+/// On unix the store is created with `0600` permissions and refused when it is readable by others.
+#[derive(Clone)]
+pub struct FileStore {
+  inner: Arc<Inner>,
+}
+
+struct Inner {
+  path: PathBuf,
+  kdf: u8,
+  salt: [u8; SALT_LEN],
+  key: SecretKey,
+}
+
+impl std::fmt::Debug for FileStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("FileStore").field("path", &self.inner.path).finish()
+  }
+}
+
+impl FileStore {
+  /// Opens or creates the store at `path`, encrypted with a key derived from the passphrase.
+  pub async fn with_passphrase(path: impl Into<PathBuf>, passphrase: SecUtf8) -> Result<Self> {
+    let path = path.into();
+    tokio::task::spawn_blocking(move || {
+      Self::open(path, KDF_PASSPHRASE, |salt| {
+        SecretKey::from_passphrase(passphrase.unsecure().as_bytes(), salt)
+      })
+    })
+    .await?
+  }
+
+  /// Opens or creates the store at `path`, encrypted with a key derived from the content of `keyfile`.
+  pub async fn with_keyfile(path: impl Into<PathBuf>, keyfile: impl AsRef<Path>) -> Result<Self> {
+    let path = path.into();
+    let material = SecVec::new(
+      fs::read(keyfile.as_ref()).with_context(|| format!("failed to read key file {}", keyfile.as_ref().display()))?,
+    );
+    tokio::task::spawn_blocking(move || {
+      Self::open(path, KDF_KEYFILE, |_| SecretKey::from_key_material(material.unsecure()))
+    })
+    .await?
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.inner.path
+  }
+
+  fn open(path: PathBuf, kdf: u8, derive: impl FnOnce(&[u8; SALT_LEN]) -> Result<SecretKey>) -> Result<Self> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+      create_private_dir(dir)?;
+    }
+    let lock = lock_file(&path)?;
+    lock.lock_exclusive()?;
+
+    let store = match read_file(&path)? {
+      Some(bytes) => {
+        let (file_kdf, salt, _) = parse_header(&bytes)?;
+        if file_kdf != kdf {
+          return Err(anyhow!(
+            "{} is encrypted with a {}",
+            path.display(),
+            if file_kdf == KDF_PASSPHRASE {
+              "passphrase"
+            } else {
+              "key file"
+            }
+          ));
+        }
+        let store = Self::from_parts(path, kdf, salt, derive(&salt)?);
+        store.inner.load()?;
+        store
+      }
+      None => {
+        let salt = random_bytes();
+        let store = Self::from_parts(path, kdf, salt, derive(&salt)?);
+        store.inner.save(&Data::new())?;
+        store
+      }
+    };
+    lock.unlock()?;
+    Ok(store)
+  }
+
+  fn from_parts(path: PathBuf, kdf: u8, salt: [u8; SALT_LEN], key: SecretKey) -> Self {
+    Self {
+      inner: Arc::new(Inner { path, kdf, salt, key }),
+    }
+  }
+
+  async fn read<R, F>(&self, f: F) -> Result<R>
+  where
+    R: Send + 'static,
+    F: FnOnce(&Data) -> R + Send + 'static,
+  {
+    let inner = self.inner.clone();
+    tokio::task::spawn_blocking(move || {
+      let lock = lock_file(&inner.path)?;
+      lock.lock_shared()?;
+      let result = inner.load().map(|data| f(&data));
+      lock.unlock()?;
+      result
+    })
+    .await?
+  }
+
+  async fn update<F>(&self, f: F) -> Result<()>
+  where
+    F: FnOnce(&mut Data) + Send + 'static,
+  {
+    let inner = self.inner.clone();
+    tokio::task::spawn_blocking(move || {
+      let lock = lock_file(&inner.path)?;
+      lock.lock_exclusive()?;
+      let result = inner.load().and_then(|mut data| {
+        f(&mut data);
+        inner.save(&data)
+      });
+      lock.unlock()?;
+      result
+    })
+    .await?
+  }
+}
+
+impl Inner {
+  fn load(&self) -> Result<Data> {
+    let bytes = read_file(&self.path)?.ok_or_else(|| anyhow!("{} was removed", self.path.display()))?;
+    let (_, _, sealed) = parse_header(&bytes)?;
+    let plaintext = self
+      .key
+      .open(sealed)
+      .with_context(|| format!("failed to decrypt {}", self.path.display()))?;
+    Ok(serde_json::from_slice(plaintext.unsecure())?)
+  }
+
+  fn save(&self, data: &Data) -> Result<()> {
+    let plaintext = SecVec::new(serde_json::to_vec(data)?);
+    let mut bytes = Vec::with_capacity(HEADER_LEN + plaintext.unsecure().len() + 64);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(self.kdf);
+    bytes.extend_from_slice(&self.salt);
+    bytes.extend_from_slice(&self.key.seal(plaintext.unsecure()));
+
+    let tmp = sibling(&self.path, &format!("tmp.{}", std::process::id()));
+    let mut file = create_private_file(&tmp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, &self.path).with_context(|| format!("failed to replace {}", self.path.display()))?;
+    sync_dir(&self.path);
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl Store for FileStore {
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    let (namespace, key, value) = (namespace.into(), key.into(), value.into());
+    self
+      .update(move |data| {
+        data.entry(namespace).or_default().insert(key, value);
+      })
+      .await
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    let (namespace, key) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    self
+      .update(move |data| {
+        if let Some(st) = data.get_mut(&namespace) {
+          st.remove(&key);
+          if st.is_empty() {
+            data.remove(&namespace);
+          }
+        }
+      })
+      .await
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    let (namespace, key) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    self
+      .read(move |data| data.get(&namespace).and_then(|st| st.get(&key).cloned()))
+      .await
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    let namespace = namespace.as_ref().to_string();
+    self
+      .read(move |data| {
+        data
+          .get(&namespace)
+          .map(|st| st.keys().cloned().collect())
+          .unwrap_or_default()
+      })
+      .await
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    self.read(|data| data.keys().cloned().collect()).await
+  }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(u8, [u8; SALT_LEN], &[u8])> {
+  if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+    return Err(anyhow!("not an etrade file store"));
+  }
+  if bytes[MAGIC.len()] != VERSION {
+    return Err(anyhow!("unsupported file store version {}", bytes[MAGIC.len()]));
+  }
+  let mut salt = [0u8; SALT_LEN];
+  salt.copy_from_slice(&bytes[MAGIC.len() + 2..HEADER_LEN]);
+  Ok((bytes[MAGIC.len() + 1], salt, &bytes[HEADER_LEN..]))
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_os_string();
+  name.push(".");
+  name.push(suffix);
+  path.with_file_name(name)
+}
+
+fn lock_file(path: &Path) -> Result<File> {
+  let lock = sibling(path, "lock");
+  let mut opts = OpenOptions::new();
+  opts.read(true).write(true).create(true).truncate(false);
+  private(&mut opts);
+  opts
+    .open(&lock)
+    .with_context(|| format!("failed to open lock file {}", lock.display()))
+}
+
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+  let mut file = match File::open(path) {
+    Ok(file) => file,
+    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+  };
+  check_permissions(&file, path)?;
+  let mut bytes = vec![];
+  file.read_to_end(&mut bytes)?;
+  Ok(Some(bytes))
+}
+
+fn create_private_file(path: &Path) -> Result<File> {
+  let mut opts = OpenOptions::new();
+  opts.write(true).create(true).truncate(true);
+  private(&mut opts);
+  opts
+    .open(path)
+    .with_context(|| format!("failed to create {}", path.display()))
+}
+
+#[cfg(unix)]
+fn private(opts: &mut OpenOptions) {
+  use std::os::unix::fs::OpenOptionsExt;
+  opts.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn private(_opts: &mut OpenOptions) {}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> Result<()> {
+  use std::os::unix::fs::DirBuilderExt;
+  if dir.exists() {
+    return Ok(());
+  }
+  fs::DirBuilder::new()
+    .recursive(true)
+    .mode(0o700)
+    .create(dir)
+    .with_context(|| format!("failed to create {}", dir.display()))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> Result<()> {
+  fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))
+}
+
+#[cfg(unix)]
+fn check_permissions(file: &File, path: &Path) -> Result<()> {
+  use std::os::unix::fs::PermissionsExt;
+  let mode = file.metadata()?.permissions().mode();
+  if mode & 0o077 != 0 {
+    return Err(anyhow!(
+      "{} is accessible by other users (mode {:o}), restrict it to 0600",
+      path.display(),
+      mode & 0o777
+    ));
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_file: &File, _path: &Path) -> Result<()> {
+  Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) {
+  if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+    if let Ok(dir) = File::open(dir) {
+      let _ = dir.sync_all();
+    }
+  }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+  use super::FileStore;
+  use crate::Store;
+
+  #[tokio::test]
+  async fn test_file_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileStore::with_passphrase(dir.path().join("secrets"), "passphrase".into())
+      .await
+      .unwrap();
+    crate::testing::verify_store(store.clone()).await;
+    crate::testing::verify_listing_store(store).await;
+  }
+
+  #[tokio::test]
+  async fn persists_encrypted_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested").join("secrets");
+    let store = FileStore::with_passphrase(&path, "passphrase".into()).await.unwrap();
+    store.put("etrade", "apikey", "the-consumer-key").await.unwrap();
+
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(16).any(|w| w == b"the-consumer-key"));
+
+    let reopened = FileStore::with_passphrase(&path, "passphrase".into()).await.unwrap();
+    assert_eq!(
+      reopened.get("etrade", "apikey").await.unwrap().unwrap().unsecure(),
+      "the-consumer-key"
+    );
+    assert!(FileStore::with_passphrase(&path, "wrong".into()).await.is_err());
+    assert!(FileStore::with_keyfile(&path, &path).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn uses_key_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let keyfile = dir.path().join("key");
+    std::fs::write(&keyfile, [42u8; 64]).unwrap();
+    let path = dir.path().join("secrets");
+
+    let store = FileStore::with_keyfile(&path, &keyfile).await.unwrap();
+    store.put("etrade", "secret", "value").await.unwrap();
+    let reopened = FileStore::with_keyfile(&path, &keyfile).await.unwrap();
+    assert_eq!(
+      reopened.get("etrade", "secret").await.unwrap().unwrap().unsecure(),
+      "value"
+    );
+  }
+
+  #[tokio::test]
+  async fn concurrent_writers_do_not_lose_updates() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets");
+    let first = FileStore::with_passphrase(&path, "passphrase".into()).await.unwrap();
+    let second = FileStore::with_passphrase(&path, "passphrase".into()).await.unwrap();
+
+    let writes = (0..20).map(|i| {
+      let store = if i % 2 == 0 { first.clone() } else { second.clone() };
+      tokio::spawn(async move { store.put("etrade", format!("key{}", i), "value").await })
+    });
+    for write in writes.collect::<Vec<_>>() {
+      write.await.unwrap().unwrap();
+    }
+    for i in 0..20 {
+      assert!(first.get("etrade", format!("key{}", i)).await.unwrap().is_some());
+    }
+  }
+
+  #[cfg(unix)]
+  #[tokio::test]
+  async fn restricts_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets");
+    FileStore::with_passphrase(&path, "passphrase".into()).await.unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(FileStore::with_passphrase(&path, "passphrase".into()).await.is_err());
+  }
+}
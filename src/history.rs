@@ -0,0 +1,385 @@
+//! Tracks the value of the accounts over time.
+//!
+//! A [`Snapshotter`] captures a [`Snapshot`] of the balance and portfolio totals of every open brokerage account and
+//! hands it to a [`SnapshotSink`]. [`FileSink`] appends them to a CSV or JSON lines file, and with the `sqlite`
+//! feature [`SqliteSink`] keeps them in a database. Sinks keep one snapshot per account and `as_of_date`, so capturing
+//! more often than E*Trade updates the balance adds nothing. [`daily`] and [`daily_series`] turn the snapshots into one
+//! value per day for charting.
+
+use std::{
+  collections::BTreeMap,
+  fs::{File, OpenOptions},
+  io::{BufRead, BufReader, ErrorKind, Write},
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{NaiveDate, TimeZone, Utc};
+
+use crate::accounts::{BalanceRequest, BalanceResponse, PortfolioRequest, PortfolioTotals};
+use crate::clients::AccountsClient;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteSink;
+
+/// The value of an account at a point in time.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Snapshot {
+  pub account_id_key: String,
+  /// The epoch seconds of the balance, as sent by E*Trade.
+  pub as_of_date: i64,
+  pub net_account_value: f64,
+  pub cash_balance: f64,
+  /// The margin buying power for margin accounts, the cash buying power otherwise.
+  pub buying_power: Option<f64>,
+  pub total_market_value: Option<f64>,
+  pub total_gain_loss: Option<f64>,
+  pub todays_gain_loss: Option<f64>,
+}
+
+impl Snapshot {
+  /// Combines a balance and the totals of the portfolio, a balance without `as_of_date` is dated now.
+  pub fn new(account_id_key: impl Into<String>, balance: &BalanceResponse, totals: Option<&PortfolioTotals>) -> Self {
+    let computed = &balance.computed_balance;
+    Self {
+      account_id_key: account_id_key.into(),
+      as_of_date: balance.as_of_date.unwrap_or_else(|| Utc::now().timestamp()),
+      net_account_value: computed.real_time_values.total_account_value,
+      cash_balance: computed.cash_balance,
+      buying_power: computed.margin_buying_power.or(computed.cash_buying_power),
+      total_market_value: totals.map(|t| t.total_market_value),
+      total_gain_loss: totals.map(|t| t.total_gain_loss),
+      todays_gain_loss: totals.map(|t| t.todays_gain_loss),
+    }
+  }
+
+  /// The trading day of the snapshot, in the time zone of the exchanges.
+  pub fn date(&self) -> NaiveDate {
+    Utc
+      .timestamp_opt(self.as_of_date, 0)
+      .single()
+      .unwrap_or_default()
+      .with_timezone(&chrono_tz::US::Eastern)
+      .date_naive()
+  }
+
+  pub fn value(&self, metric: Metric) -> Option<f64> {
+    match metric {
+      Metric::NetAccountValue => Some(self.net_account_value),
+      Metric::CashBalance => Some(self.cash_balance),
+      Metric::BuyingPower => self.buying_power,
+      Metric::TotalMarketValue => self.total_market_value,
+      Metric::TotalGainLoss => self.total_gain_loss,
+      Metric::TodaysGainLoss => self.todays_gain_loss,
+    }
+  }
+}
+
+/// A value of a [`Snapshot`] to chart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+  NetAccountValue,
+  CashBalance,
+  BuyingPower,
+  TotalMarketValue,
+  TotalGainLoss,
+  TodaysGainLoss,
+}
+
+/// The last snapshot of every day, ordered by date.
+pub fn daily(snapshots: &[Snapshot]) -> Vec<Snapshot> {
+  let mut days: BTreeMap<NaiveDate, &Snapshot> = BTreeMap::new();
+  for snapshot in snapshots {
+    let day = days.entry(snapshot.date()).or_insert(snapshot);
+    if snapshot.as_of_date >= day.as_of_date {
+      *day = snapshot;
+    }
+  }
+  days.into_values().cloned().collect()
+}
+
+/// One value per day, days where the last snapshot doesn't have the metric are left out.
+pub fn daily_series(snapshots: &[Snapshot], metric: Metric) -> Vec<(NaiveDate, f64)> {
+  daily(snapshots)
+    .iter()
+    .filter_map(|s| s.value(metric).map(|v| (s.date(), v)))
+    .collect()
+}
+
+/// Where the [`Snapshotter`] keeps the snapshots.
+#[async_trait]
+pub trait SnapshotSink: Send + Sync {
+  /// Saves the snapshot unless there already is one for the same account and `as_of_date`, returns whether it was
+  /// saved.
+  async fn record(&self, snapshot: &Snapshot) -> Result<bool>;
+
+  /// The snapshots of the account, ordered by `as_of_date`.
+  async fn load(&self, account_id_key: &str) -> Result<Vec<Snapshot>>;
+}
+
+#[async_trait]
+impl<T> SnapshotSink for Arc<T>
+where
+  T: SnapshotSink + ?Sized,
+{
+  async fn record(&self, snapshot: &Snapshot) -> Result<bool> {
+    self.as_ref().record(snapshot).await
+  }
+
+  async fn load(&self, account_id_key: &str) -> Result<Vec<Snapshot>> {
+    self.as_ref().load(account_id_key).await
+  }
+}
+
+/// Appends the snapshots to a file, as CSV when the path ends in `.csv` and as JSON lines otherwise.
+///
+/// Every call reads the whole file to check for duplicates, which is fine for a few snapshots per account a day.
+#[derive(Debug, Clone)]
+pub struct FileSink {
+  path: PathBuf,
+  lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl FileSink {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self {
+      path: path.into(),
+      lock: Arc::new(tokio::sync::Mutex::new(())),
+    }
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn is_csv(&self) -> bool {
+    self.path.extension().map(|ext| ext == "csv").unwrap_or(false)
+  }
+
+  fn read_all(path: &Path, csv: bool) -> Result<Vec<Snapshot>> {
+    let file = match File::open(path) {
+      Ok(file) => file,
+      Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+      Err(e) => return Err(e).with_context(|| format!("failed to open {}", path.display())),
+    };
+    if csv {
+      let mut snapshots = vec![];
+      for record in csv::Reader::from_reader(file).deserialize() {
+        snapshots.push(record.with_context(|| format!("failed to parse {}", path.display()))?);
+      }
+      return Ok(snapshots);
+    }
+    let mut snapshots = vec![];
+    for line in BufReader::new(file).lines() {
+      let line = line?;
+      if !line.trim().is_empty() {
+        snapshots.push(serde_json::from_str(&line).with_context(|| format!("failed to parse {}", path.display()))?);
+      }
+    }
+    Ok(snapshots)
+  }
+
+  fn append(path: &Path, csv: bool, snapshot: &Snapshot) -> Result<()> {
+    let mut file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .with_context(|| format!("failed to open {}", path.display()))?;
+    if csv {
+      let empty = file.metadata()?.len() == 0;
+      let mut writer = csv::WriterBuilder::new().has_headers(empty).from_writer(file);
+      writer.serialize(snapshot)?;
+      writer.flush()?;
+    } else {
+      let mut line = serde_json::to_vec(snapshot)?;
+      line.push(b'\n');
+      file.write_all(&line)?;
+    }
+    Ok(())
+  }
+}
+
+#[async_trait]
+impl SnapshotSink for FileSink {
+  async fn record(&self, snapshot: &Snapshot) -> Result<bool> {
+    let _guard = self.lock.lock().await;
+    let (path, csv, snapshot) = (self.path.clone(), self.is_csv(), snapshot.clone());
+    tokio::task::spawn_blocking(move || {
+      let duplicate = Self::read_all(&path, csv)?
+        .iter()
+        .any(|s| s.account_id_key == snapshot.account_id_key && s.as_of_date == snapshot.as_of_date);
+      if duplicate {
+        return Ok(false);
+      }
+      Self::append(&path, csv, &snapshot)?;
+      Ok(true)
+    })
+    .await?
+  }
+
+  async fn load(&self, account_id_key: &str) -> Result<Vec<Snapshot>> {
+    let (path, csv, account_id_key) = (self.path.clone(), self.is_csv(), account_id_key.to_string());
+    tokio::task::spawn_blocking(move || {
+      let mut snapshots: Vec<Snapshot> = Self::read_all(&path, csv)?
+        .into_iter()
+        .filter(|s| s.account_id_key == account_id_key)
+        .collect();
+      snapshots.sort_by_key(|s| s.as_of_date);
+      Ok(snapshots)
+    })
+    .await?
+  }
+}
+
+/// Captures the snapshots of every open brokerage account into a sink.
+pub struct Snapshotter<A, S> {
+  accounts: A,
+  sink: S,
+}
+
+impl<A, S> Snapshotter<A, S>
+where
+  A: AccountsClient,
+  S: SnapshotSink,
+{
+  pub fn new(accounts: A, sink: S) -> Self {
+    Self { accounts, sink }
+  }
+
+  pub fn sink(&self) -> &S {
+    &self.sink
+  }
+
+  /// Takes a snapshot of every open brokerage account and returns the ones the sink didn't have yet.
+  pub async fn capture(&self) -> Result<Vec<Snapshot>> {
+    let mut recorded = vec![];
+    for account in self.accounts.list().await? {
+      if account.is_closed() || !account.is_brokerage() {
+        continue;
+      }
+      let key = &account.account_id_key;
+      let balance = self
+        .accounts
+        .balance(
+          key,
+          BalanceRequest {
+            real_time_nav: Some(true),
+            ..Default::default()
+          },
+        )
+        .await?;
+      let portfolio = self
+        .accounts
+        .portfolio(
+          key,
+          PortfolioRequest {
+            totals_required: Some(true),
+            ..Default::default()
+          },
+        )
+        .await?;
+      let snapshot = Snapshot::new(key.as_str(), &balance, portfolio.totals.as_ref());
+      if self.sink.record(&snapshot).await? {
+        recorded.push(snapshot);
+      }
+    }
+    Ok(recorded)
+  }
+
+  /// Captures the snapshots at the given interval, starting right away. Failed captures are logged and retried at the
+  /// next interval, the future never completes.
+  pub async fn run(&self, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      match self.capture().await {
+        Ok(recorded) => debug!("recorded {} balance snapshots", recorded.len()),
+        Err(e) => warn!("failed to capture the balance snapshots: {:#}", e),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use chrono::NaiveDate;
+
+  use super::{daily_series, FileSink, Metric, Snapshot, SnapshotSink, Snapshotter};
+  use crate::accounts;
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::Memstore;
+
+  fn snapshot(as_of_date: i64, net_account_value: f64) -> Snapshot {
+    Snapshot {
+      account_id_key: "key".to_string(),
+      as_of_date,
+      net_account_value,
+      ..Default::default()
+    }
+  }
+
+  pub(super) async fn verify_sink(sink: impl SnapshotSink) {
+    assert!(sink.record(&snapshot(1_700_000_000, 1.0)).await.unwrap());
+    assert!(!sink.record(&snapshot(1_700_000_000, 2.0)).await.unwrap());
+    assert!(sink.record(&snapshot(1_699_000_000, 3.0)).await.unwrap());
+    let mut other = snapshot(1_700_000_000, 4.0);
+    other.account_id_key = "other".to_string();
+    other.buying_power = Some(5.0);
+    assert!(sink.record(&other).await.unwrap());
+
+    let loaded = sink.load("key").await.unwrap();
+    assert_eq!(loaded, vec![snapshot(1_699_000_000, 3.0), snapshot(1_700_000_000, 1.0)]);
+    assert_eq!(sink.load("other").await.unwrap(), vec![other]);
+  }
+
+  #[tokio::test]
+  async fn file_sinks_skip_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    verify_sink(FileSink::new(dir.path().join("history.jsonl"))).await;
+    verify_sink(FileSink::new(dir.path().join("history.csv"))).await;
+  }
+
+  #[test]
+  fn keeps_the_last_snapshot_of_each_day() {
+    // 2023-11-14 in New York, twice, then the next day
+    let snapshots = vec![
+      snapshot(1_700_000_000, 1.0),
+      snapshot(1_699_990_000, 2.0),
+      snapshot(1_700_086_400, 3.0),
+    ];
+    assert_eq!(
+      daily_series(&snapshots, Metric::NetAccountValue),
+      vec![
+        (NaiveDate::from_ymd_opt(2023, 11, 14).unwrap(), 1.0),
+        (NaiveDate::from_ymd_opt(2023, 11, 15).unwrap(), 3.0),
+      ]
+    );
+    assert!(daily_series(&snapshots, Metric::BuyingPower).is_empty());
+  }
+
+  #[tokio::test]
+  async fn captures_the_open_accounts() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let session = Arc::new(server.session(Memstore::new()).await.unwrap());
+    let dir = tempfile::tempdir().unwrap();
+    let snapshotter = Snapshotter::new(
+      accounts::Api::new(session),
+      FileSink::new(dir.path().join("history.jsonl")),
+    );
+
+    let recorded = snapshotter.capture().await.unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].cash_balance, 10_000.0);
+    let key = recorded[0].account_id_key.clone();
+    assert_eq!(snapshotter.sink().load(&key).await.unwrap(), recorded);
+  }
+}
@@ -0,0 +1,135 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, Row};
+
+use super::{Snapshot, SnapshotSink};
+
+/// Keeps the snapshots in an SQLite database, which can be shared between processes.
+#[derive(Clone)]
+pub struct SqliteSink {
+  path: PathBuf,
+  conn: Arc<Mutex<Connection>>,
+}
+
+impl std::fmt::Debug for SqliteSink {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SqliteSink").field("path", &self.path).finish()
+  }
+}
+
+impl SqliteSink {
+  /// Opens or creates the database at `path`.
+  pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+    let path = path.into();
+    tokio::task::spawn_blocking(move || {
+      let conn = Connection::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+      conn.busy_timeout(Duration::from_secs(10))?;
+      conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS snapshots (
+           account_id_key TEXT NOT NULL,
+           as_of_date INTEGER NOT NULL,
+           net_account_value REAL NOT NULL,
+           cash_balance REAL NOT NULL,
+           buying_power REAL,
+           total_market_value REAL,
+           total_gain_loss REAL,
+           todays_gain_loss REAL,
+           PRIMARY KEY (account_id_key, as_of_date)
+         );",
+      )?;
+      Ok(Self {
+        path,
+        conn: Arc::new(Mutex::new(conn)),
+      })
+    })
+    .await?
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  async fn with_conn<R, F>(&self, f: F) -> Result<R>
+  where
+    R: Send + 'static,
+    F: FnOnce(&Connection) -> Result<R> + Send + 'static,
+  {
+    let conn = self.conn.clone();
+    tokio::task::spawn_blocking(move || f(&conn.lock().unwrap())).await?
+  }
+}
+
+fn snapshot(row: &Row) -> rusqlite::Result<Snapshot> {
+  Ok(Snapshot {
+    account_id_key: row.get(0)?,
+    as_of_date: row.get(1)?,
+    net_account_value: row.get(2)?,
+    cash_balance: row.get(3)?,
+    buying_power: row.get(4)?,
+    total_market_value: row.get(5)?,
+    total_gain_loss: row.get(6)?,
+    todays_gain_loss: row.get(7)?,
+  })
+}
+
+#[async_trait]
+impl SnapshotSink for SqliteSink {
+  async fn record(&self, snapshot: &Snapshot) -> Result<bool> {
+    let s = snapshot.clone();
+    self
+      .with_conn(move |conn| {
+        let inserted = conn.execute(
+          "INSERT OR IGNORE INTO snapshots (account_id_key, as_of_date, net_account_value, cash_balance, buying_power,
+             total_market_value, total_gain_loss, todays_gain_loss)
+           VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+          params![
+            s.account_id_key,
+            s.as_of_date,
+            s.net_account_value,
+            s.cash_balance,
+            s.buying_power,
+            s.total_market_value,
+            s.total_gain_loss,
+            s.todays_gain_loss
+          ],
+        )?;
+        Ok(inserted == 1)
+      })
+      .await
+  }
+
+  async fn load(&self, account_id_key: &str) -> Result<Vec<Snapshot>> {
+    let account_id_key = account_id_key.to_string();
+    self
+      .with_conn(move |conn| {
+        let mut stmt = conn.prepare(
+          "SELECT account_id_key, as_of_date, net_account_value, cash_balance, buying_power, total_market_value,
+             total_gain_loss, todays_gain_loss
+           FROM snapshots WHERE account_id_key = ?1 ORDER BY as_of_date",
+        )?;
+        let snapshots = stmt
+          .query_map(params![account_id_key], snapshot)?
+          .collect::<rusqlite::Result<Vec<Snapshot>>>()?;
+        Ok(snapshots)
+      })
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SqliteSink;
+
+  #[tokio::test]
+  async fn sqlite_sink_skips_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = SqliteSink::open(dir.path().join("history.db")).await.unwrap();
+    super::super::tests::verify_sink(sink).await;
+  }
+}
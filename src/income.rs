@@ -0,0 +1,591 @@
+//! The dividend income of the held positions.
+//!
+//! A [`Dividend`] is the dividend data of a symbol, from the quote details or the complete view of a position.
+//! [`project`] turns the holdings into the [`Payment`]s expected between two dates: the declared dividend on its pay
+//! date, then estimates that repeat it at the frequency the annual dividend implies. An [`IncomeCalendar`] groups the
+//! payments by month and account, reconciles them with the dividends of the transaction history and exports them as
+//! an iCalendar feed.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{Datelike, Duration, Months, NaiveDate, TimeZone, Utc};
+
+use crate::accounts::{CompleteView, PortfolioPosition, PortfolioRequest, PositionView};
+use crate::clients::{AccountsClient, MarketClient, TransactionsClient};
+use crate::options::{AllQuoteDetails, DetailFlag, GetQuotesRequest};
+use crate::transactions::{ListTransactionsRequest, TransactionDetailsResponse};
+use crate::SecurityType;
+
+/// The most symbols E*Trade quotes in one request.
+const QUOTES_PER_REQUEST: usize = 25;
+
+/// How many days a dividend transaction can be away from the pay date to match a payment.
+const MATCH_DAYS: i64 = 5;
+
+/// The dividend of a symbol.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dividend {
+  pub symbol: String,
+  pub ex_date: Option<NaiveDate>,
+  pub pay_date: Option<NaiveDate>,
+  /// The dividend per share of the next payment.
+  pub per_share: f64,
+  /// Whether the next payment is declared, rather than the last one repeated.
+  pub declared: bool,
+  /// The dividends per share of a year.
+  pub annual: f64,
+}
+
+impl Dividend {
+  pub fn from_quote(symbol: impl Into<String>, quote: &AllQuoteDetails) -> Self {
+    Self {
+      symbol: symbol.into(),
+      ex_date: date(quote.ex_dividend_date),
+      pay_date: date(quote.dividend_payable_date),
+      per_share: if quote.declared_dividend > 0.0 {
+        quote.declared_dividend
+      } else {
+        quote.dividend
+      },
+      declared: quote.declared_dividend > 0.0,
+      annual: quote.annual_dividend,
+    }
+  }
+
+  pub fn from_view(symbol: impl Into<String>, view: &CompleteView) -> Self {
+    Self {
+      symbol: symbol.into(),
+      ex_date: date(view.ex_dividend_date),
+      pay_date: date(view.div_pay_date),
+      per_share: view.dividend,
+      declared: false,
+      annual: view.annual_dividend,
+    }
+  }
+
+  /// The payments of a year, quarterly when the annual dividend doesn't tell.
+  pub fn frequency(&self) -> u32 {
+    if self.per_share > 0.0 && self.annual > 0.0 {
+      ((self.annual / self.per_share).round() as u32).clamp(1, 12)
+    } else {
+      4
+    }
+  }
+}
+
+/// Maps the epoch seconds of E*Trade to a date in New York, 0 means there is no date.
+fn date(secs: i64) -> Option<NaiveDate> {
+  if secs <= 0 {
+    return None;
+  }
+  Utc
+    .timestamp_opt(secs, 0)
+    .single()
+    .map(|d| d.with_timezone(&chrono_tz::US::Eastern).date_naive())
+}
+
+/// A position that can pay dividends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+  pub account_id_key: String,
+  pub symbol: String,
+  pub quantity: f64,
+}
+
+/// A dividend expected on a position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payment {
+  pub account_id_key: String,
+  pub symbol: String,
+  pub ex_date: Option<NaiveDate>,
+  pub pay_date: NaiveDate,
+  pub per_share: f64,
+  pub quantity: f64,
+  /// Whether the payment is declared, otherwise it is estimated from the last one.
+  pub declared: bool,
+}
+
+impl Payment {
+  pub fn amount(&self) -> f64 {
+    self.per_share * self.quantity
+  }
+}
+
+/// The payments of the holdings from `from` to `until`, ordered by pay date. Holdings without dividend data or
+/// without a date to start from are left out.
+pub fn project(holdings: &[Holding], dividends: &[Dividend], from: NaiveDate, until: NaiveDate) -> Vec<Payment> {
+  let mut payments = vec![];
+  for holding in holdings.iter().filter(|h| h.quantity > 0.0) {
+    let Some(dividend) = dividends.iter().find(|d| d.symbol == holding.symbol) else {
+      continue;
+    };
+    let per_share = if dividend.per_share > 0.0 {
+      dividend.per_share
+    } else {
+      dividend.annual / dividend.frequency() as f64
+    };
+    let Some(first) = dividend.pay_date.or(dividend.ex_date) else {
+      continue;
+    };
+    if per_share <= 0.0 {
+      continue;
+    }
+
+    let step = 12 / dividend.frequency();
+    for n in 0.. {
+      let Some(pay_date) = first.checked_add_months(Months::new(n * step)) else {
+        break;
+      };
+      if pay_date > until {
+        break;
+      }
+      if pay_date < from {
+        continue;
+      }
+      payments.push(Payment {
+        account_id_key: holding.account_id_key.clone(),
+        symbol: holding.symbol.clone(),
+        ex_date: if n == 0 { dividend.ex_date } else { None },
+        pay_date,
+        per_share,
+        quantity: holding.quantity,
+        declared: n == 0 && dividend.declared,
+      });
+    }
+  }
+  payments.sort_by(|a, b| a.pay_date.cmp(&b.pay_date).then(a.symbol.cmp(&b.symbol)));
+  payments
+}
+
+/// How a payment compares with the transaction history.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reconciliation {
+  /// A dividend transaction of the symbol was found near the pay date.
+  Received {
+    payment: Payment,
+    date: NaiveDate,
+    amount: f64,
+  },
+  /// The pay date is past and no dividend was found.
+  Missing(Payment),
+  /// The pay date isn't past yet.
+  Pending(Payment),
+  /// A dividend that wasn't expected.
+  Unexpected {
+    account_id_key: String,
+    symbol: String,
+    date: NaiveDate,
+    amount: f64,
+  },
+}
+
+/// The projected payments of accounts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IncomeCalendar {
+  pub payments: Vec<Payment>,
+}
+
+impl IncomeCalendar {
+  pub fn new(payments: Vec<Payment>) -> Self {
+    Self { payments }
+  }
+
+  /// Projects the payments of the long equity positions of the accounts, with the dividends of their quotes.
+  pub async fn load(
+    accounts: &dyn AccountsClient,
+    market: &dyn MarketClient,
+    account_id_keys: &[&str],
+    from: NaiveDate,
+    until: NaiveDate,
+  ) -> Result<Self> {
+    let mut holdings = vec![];
+    for key in account_id_keys {
+      let portfolio = accounts.portfolio_all(key, PortfolioRequest::default()).await?;
+      for position in portfolio.account_portfolio.iter().flat_map(|p| &p.position) {
+        if matches!(position.product.security_type, Some(SecurityType::Optn)) {
+          continue;
+        }
+        holdings.push(Holding {
+          account_id_key: key.to_string(),
+          symbol: position.product.symbol.clone(),
+          quantity: position.quantity,
+        });
+      }
+    }
+
+    let mut symbols: Vec<&str> = holdings.iter().map(|h| h.symbol.as_str()).collect();
+    symbols.sort_unstable();
+    symbols.dedup();
+    let mut dividends = vec![];
+    for chunk in symbols.chunks(QUOTES_PER_REQUEST) {
+      let quotes = market
+        .quotes(
+          &chunk.join(","),
+          GetQuotesRequest {
+            detail_flag: Some(DetailFlag::All),
+            ..Default::default()
+          },
+        )
+        .await?;
+      for quote in quotes.quote_data {
+        if let (Some(product), Some(all)) = (&quote.product, &quote.all) {
+          dividends.push(Dividend::from_quote(product.symbol.clone(), all));
+        }
+      }
+    }
+    Ok(Self::new(project(&holdings, &dividends, from, until)))
+  }
+
+  /// Projects the payments of positions listed with the complete view, without quoting them.
+  pub fn from_positions<'a>(
+    account_id_key: &str,
+    positions: impl IntoIterator<Item = &'a PortfolioPosition>,
+    from: NaiveDate,
+    until: NaiveDate,
+  ) -> Self {
+    let mut holdings = vec![];
+    let mut dividends = vec![];
+    for position in positions {
+      if let Some(PositionView::Complete(view)) = &position.view {
+        dividends.push(Dividend::from_view(position.product.symbol.clone(), view));
+        holdings.push(Holding {
+          account_id_key: account_id_key.to_string(),
+          symbol: position.product.symbol.clone(),
+          quantity: position.quantity,
+        });
+      }
+    }
+    Self::new(project(&holdings, &dividends, from, until))
+  }
+
+  pub fn total(&self) -> f64 {
+    self.payments.iter().map(|p| p.amount()).sum()
+  }
+
+  /// The income of every month, keyed by its first day, per account.
+  pub fn by_month(&self) -> BTreeMap<NaiveDate, BTreeMap<String, f64>> {
+    let mut months: BTreeMap<NaiveDate, BTreeMap<String, f64>> = BTreeMap::new();
+    for payment in &self.payments {
+      let month = payment.pay_date.with_day(1).unwrap();
+      *months
+        .entry(month)
+        .or_default()
+        .entry(payment.account_id_key.clone())
+        .or_default() += payment.amount();
+    }
+    months
+  }
+
+  /// Matches the payments of an account with the dividends of its transactions, as of `today`.
+  pub fn reconcile(
+    &self,
+    account_id_key: &str,
+    transactions: &[TransactionDetailsResponse],
+    today: NaiveDate,
+  ) -> Vec<Reconciliation> {
+    let mut received: Vec<(String, NaiveDate, f64)> = transactions
+      .iter()
+      .filter_map(|t| {
+        let brokerage = t.brokerage.as_ref()?;
+        let tpe = brokerage.transaction_type.to_ascii_lowercase();
+        if !tpe.contains("dividend") || tpe.contains("reinvest") {
+          return None;
+        }
+        let date = Utc
+          .timestamp_millis_opt(t.tranaction_date)
+          .single()?
+          .with_timezone(&chrono_tz::US::Eastern)
+          .date_naive();
+        Some((brokerage.product.symbol.clone(), date, t.amount))
+      })
+      .collect();
+
+    let mut reconciled = vec![];
+    for payment in self.payments.iter().filter(|p| p.account_id_key == account_id_key) {
+      let found = received.iter().position(|(symbol, date, _)| {
+        *symbol == payment.symbol && (*date - payment.pay_date).num_days().abs() <= MATCH_DAYS
+      });
+      reconciled.push(match found {
+        Some(i) => {
+          let (_, date, amount) = received.remove(i);
+          Reconciliation::Received {
+            payment: payment.clone(),
+            date,
+            amount,
+          }
+        }
+        None if payment.pay_date + Duration::days(MATCH_DAYS) < today => Reconciliation::Missing(payment.clone()),
+        None => Reconciliation::Pending(payment.clone()),
+      });
+    }
+    reconciled.extend(
+      received
+        .into_iter()
+        .map(|(symbol, date, amount)| Reconciliation::Unexpected {
+          account_id_key: account_id_key.to_string(),
+          symbol,
+          date,
+          amount,
+        }),
+    );
+    reconciled
+  }
+
+  /// Lists the transactions of the account around the payments and reconciles them, as of `today`.
+  pub async fn reconcile_with(
+    &self,
+    transactions: &dyn TransactionsClient,
+    account_id_key: &str,
+    today: NaiveDate,
+  ) -> Result<Vec<Reconciliation>> {
+    let dates = self
+      .payments
+      .iter()
+      .filter(|p| p.account_id_key == account_id_key)
+      .map(|p| p.pay_date);
+    let (Some(first), Some(last)) = (dates.clone().min(), dates.max()) else {
+      return Ok(vec![]);
+    };
+    let start_date = (first - Duration::days(MATCH_DAYS)).format("%m%d%Y").to_string();
+    let end_date = (last + Duration::days(MATCH_DAYS))
+      .min(today)
+      .format("%m%d%Y")
+      .to_string();
+    let listed = transactions
+      .list_all(
+        account_id_key,
+        ListTransactionsRequest {
+          start_date: Some(&start_date),
+          end_date: Some(&end_date),
+          ..Default::default()
+        },
+      )
+      .await?;
+    Ok(self.reconcile(account_id_key, &listed, today))
+  }
+
+  /// An iCalendar feed with an all day event on the pay date of every payment.
+  pub fn to_ical(&self) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+    let mut lines = vec![
+      "BEGIN:VCALENDAR".to_string(),
+      "VERSION:2.0".to_string(),
+      "PRODID:-//etrade//income//EN".to_string(),
+      "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for payment in &self.payments {
+      let mut description = format!(
+        "{} shares at ${:.4} in {}",
+        payment.quantity, payment.per_share, payment.account_id_key
+      );
+      if let Some(ex_date) = payment.ex_date {
+        description.push_str(&format!(", ex-dividend on {}", ex_date));
+      }
+      if !payment.declared {
+        description.push_str(", estimated");
+      }
+      lines.extend([
+        "BEGIN:VEVENT".to_string(),
+        format!(
+          "UID:{}-{}-{}@etrade",
+          payment.account_id_key,
+          payment.symbol,
+          payment.pay_date.format("%Y%m%d")
+        ),
+        format!("DTSTAMP:{}", stamp),
+        format!("DTSTART;VALUE=DATE:{}", payment.pay_date.format("%Y%m%d")),
+        format!(
+          "DTEND;VALUE=DATE:{}",
+          (payment.pay_date + Duration::days(1)).format("%Y%m%d")
+        ),
+        format!(
+          "SUMMARY:{}",
+          escape(&format!("{} dividend ${:.2}", payment.symbol, payment.amount()))
+        ),
+        format!("DESCRIPTION:{}", escape(&description)),
+        "END:VEVENT".to_string(),
+      ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|l| fold(l)).collect::<Vec<_>>().join("")
+  }
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('\\', "\\\\")
+    .replace(';', "\\;")
+    .replace(',', "\\,")
+    .replace('\n', "\\n")
+}
+
+/// Ends the line with CRLF, folding it at 75 bytes as the iCalendar format requires.
+fn fold(line: &str) -> String {
+  let mut folded = String::new();
+  let mut width = 0;
+  for c in line.chars() {
+    if width + c.len_utf8() > 75 {
+      folded.push_str("\r\n ");
+      width = 1;
+    }
+    folded.push(c);
+    width += c.len_utf8();
+  }
+  folded.push_str("\r\n");
+  folded
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{NaiveDate, TimeZone};
+
+  use super::{project, Dividend, Holding, IncomeCalendar, Reconciliation};
+  use crate::accounts::{AccountPortfolio, PortfolioPosition, PortfolioResponse};
+  use crate::options::{AllQuoteDetails, QuoteData, QuoteResponse};
+  use crate::testing::{MockAccounts, MockMarket};
+  use crate::transactions::{Brokerage, TransactionDetailsResponse};
+  use crate::Product;
+
+  fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+  }
+
+  fn holding(account: &str, symbol: &str, quantity: f64) -> Holding {
+    Holding {
+      account_id_key: account.to_string(),
+      symbol: symbol.to_string(),
+      quantity,
+    }
+  }
+
+  #[test]
+  fn projects_groups_and_reconciles_payments() {
+    let dividends = vec![
+      Dividend {
+        symbol: "KO".to_string(),
+        ex_date: Some(date(2024, 3, 14)),
+        pay_date: Some(date(2024, 4, 1)),
+        per_share: 0.485,
+        declared: true,
+        annual: 1.94,
+      },
+      Dividend {
+        symbol: "O".to_string(),
+        pay_date: Some(date(2024, 3, 15)),
+        per_share: 0.2565,
+        annual: 3.078,
+        ..Default::default()
+      },
+    ];
+    let holdings = vec![
+      holding("ira", "KO", 100.0),
+      holding("taxable", "KO", 10.0),
+      holding("taxable", "O", 40.0),
+      holding("taxable", "MSFT", 5.0),
+    ];
+    let calendar = IncomeCalendar::new(project(&holdings, &dividends, date(2024, 4, 1), date(2024, 6, 30)));
+
+    // KO pays quarterly from its declared payment, once in the range, O monthly from the month after its last one
+    let ko: Vec<_> = calendar.payments.iter().filter(|p| p.symbol == "KO").collect();
+    assert_eq!(ko.len(), 2);
+    assert!(ko[0].declared && ko[0].ex_date.is_some());
+    assert_eq!(
+      calendar
+        .payments
+        .iter()
+        .filter(|p| p.symbol == "O")
+        .map(|p| p.pay_date)
+        .collect::<Vec<_>>(),
+      vec![date(2024, 4, 15), date(2024, 5, 15), date(2024, 6, 15)]
+    );
+
+    let months = calendar.by_month();
+    assert_eq!(months.len(), 3);
+    assert!((months[&date(2024, 4, 1)]["ira"] - 48.5).abs() < 1e-9);
+    assert!((months[&date(2024, 4, 1)]["taxable"] - (4.85 + 10.26)).abs() < 1e-9);
+    assert!((calendar.total() - (48.5 + 4.85 + 10.26 * 3.0)).abs() < 1e-9);
+
+    let dividend = |symbol: &str, date: NaiveDate, amount| TransactionDetailsResponse {
+      tranaction_date: chrono_tz::US::Eastern
+        .from_local_datetime(&date.and_hms_opt(9, 0, 0).unwrap())
+        .unwrap()
+        .timestamp_millis(),
+      amount,
+      brokerage: Some(Brokerage {
+        transaction_type: "Dividend".to_string(),
+        product: Product {
+          symbol: symbol.to_string(),
+          ..Default::default()
+        },
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+    let reconciled = calendar.reconcile(
+      "taxable",
+      &[
+        dividend("KO", date(2024, 4, 2), 4.85),
+        dividend("O", date(2024, 4, 15), 10.26),
+        dividend("T", date(2024, 5, 1), 2.0),
+      ],
+      date(2024, 5, 31),
+    );
+    assert!(matches!(&reconciled[0], Reconciliation::Received { amount, .. } if *amount == 4.85));
+    assert!(matches!(&reconciled[1], Reconciliation::Received { payment, .. } if payment.symbol == "O"));
+    assert!(matches!(&reconciled[2], Reconciliation::Missing(p) if p.pay_date == date(2024, 5, 15)));
+    assert!(matches!(&reconciled[3], Reconciliation::Pending(_)));
+    assert!(matches!(&reconciled[4], Reconciliation::Unexpected { symbol, .. } if symbol == "T"));
+
+    let ical = calendar.to_ical();
+    assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert_eq!(ical.matches("BEGIN:VEVENT").count(), 5);
+    assert!(ical.contains("DTSTART;VALUE=DATE:20240401\r\n"));
+    assert!(ical.contains("SUMMARY:KO dividend $48.50\r\n"));
+    assert!(ical.lines().all(|l| l.trim_end_matches('\r').len() <= 75));
+  }
+
+  #[tokio::test]
+  async fn loads_positions_and_quotes() {
+    let accounts = MockAccounts::new().on_portfolio(Ok(PortfolioResponse {
+      account_portfolio: vec![AccountPortfolio {
+        position: vec![PortfolioPosition {
+          product: Product {
+            symbol: "KO".to_string(),
+            ..Default::default()
+          },
+          quantity: 100.0,
+          ..Default::default()
+        }],
+        ..Default::default()
+      }],
+      ..Default::default()
+    }));
+    let market = MockMarket::new().on_quotes(Ok(QuoteResponse {
+      quote_data: vec![QuoteData {
+        product: Some(Product {
+          symbol: "KO".to_string(),
+          ..Default::default()
+        }),
+        all: Some(AllQuoteDetails {
+          dividend: 0.485,
+          annual_dividend: 1.94,
+          declared_dividend: 0.5,
+          ex_dividend_date: 1_718_337_600,      // 2024-06-14
+          dividend_payable_date: 1_719_835_200, // 2024-07-01
+          ..Default::default()
+        }),
+        ..Default::default()
+      }],
+      ..Default::default()
+    }));
+
+    let calendar = IncomeCalendar::load(&accounts, &market, &["key"], date(2024, 6, 1), date(2024, 12, 31))
+      .await
+      .unwrap();
+    let dates: Vec<_> = calendar.payments.iter().map(|p| (p.pay_date, p.amount())).collect();
+    assert_eq!(dates, vec![(date(2024, 7, 1), 50.0), (date(2024, 10, 1), 50.0)]);
+    assert_eq!(calendar.payments[0].ex_date, Some(date(2024, 6, 14)));
+    assert_eq!(market.calls()[0].args["symbols"], "KO");
+  }
+}
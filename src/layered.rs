@@ -0,0 +1,275 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use secstr::SecUtf8;
+
+use crate::{ListingUnsupported, Store};
+
+/// Combines a read-only store for configuration with a writable store for the tokens.
+///
+/// Reads consult the read-only layer first and fall through to the writable layer, so injected configuration
+/// always wins. Writes and deletes only ever touch the writable layer.
+#[derive(Debug)]
+pub struct LayeredStore<R, W> {
+  read: R,
+  write: W,
+}
+
+impl<R, W> LayeredStore<R, W>
+where
+  R: Store,
+  W: Store,
+{
+  pub fn new(read: R, write: W) -> Self {
+    Self { read, write }
+  }
+}
+
+#[async_trait]
+impl<R, W> Store for LayeredStore<R, W>
+where
+  R: Store + Send + Sync,
+  W: Store + Send + Sync,
+{
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    self.write.put(namespace, key, value).await
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    self.write.del(namespace, key).await
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    match self.read.get(namespace.as_ref(), key.as_ref()).await? {
+      Some(v) => Ok(Some(v)),
+      None => self.write.get(namespace, key).await,
+    }
+  }
+
+  /// Lists the keys of both layers, a layer that can't list its content is left out.
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    merge(
+      self.read.list(namespace.as_ref()).await,
+      self.write.list(namespace.as_ref()).await,
+    )
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    merge(self.read.namespaces().await, self.write.namespaces().await)
+  }
+}
+
+fn merge(read: Result<Vec<String>>, write: Result<Vec<String>>) -> Result<Vec<String>> {
+  let mut names = match (read, write) {
+    (Err(e), _) | (_, Err(e)) if !e.is::<ListingUnsupported>() => return Err(e),
+    (Err(e), Err(_)) => return Err(e),
+    (Ok(mut read), Ok(write)) => {
+      read.extend(write);
+      read
+    }
+    (Ok(names), Err(_)) | (Err(_), Ok(names)) => names,
+  };
+  names.sort();
+  names.dedup();
+  Ok(names)
+}
+
+/// A read-only store backed by environment variables.
+///
+/// The variable for a key is the namespace and the key joined with `_`, upper cased, with every other character
+/// than letters and digits replaced by `_`. The consumer key of the live default profile is read from
+/// `ETRADE_APIKEY` and its secret from `ETRADE_SECRET`, for the profile `alice` they are `ETRADE_ALICE_APIKEY` and
+/// `ETRADE_ALICE_SECRET`.
+#[derive(Debug, Clone, Default)]
+pub struct EnvStore {
+  prefix: Option<String>,
+}
+
+impl EnvStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Prepends the prefix to the variable names, `APP` reads the consumer key from `APP_ETRADE_APIKEY`.
+  pub fn with_prefix(prefix: impl Into<String>) -> Self {
+    Self {
+      prefix: Some(prefix.into()),
+    }
+  }
+
+  pub fn var_name(&self, namespace: &str, key: &str) -> String {
+    let name = match &self.prefix {
+      Some(prefix) => format!("{}_{}_{}", prefix, namespace, key),
+      None => format!("{}_{}", namespace, key),
+    };
+    name
+      .chars()
+      .map(|c| {
+        if c.is_ascii_alphanumeric() {
+          c.to_ascii_uppercase()
+        } else {
+          '_'
+        }
+      })
+      .collect()
+  }
+}
+
+#[async_trait]
+impl Store for EnvStore {
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    _value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    Err(read_only("environment", &namespace.into(), &key.into()))
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    Err(read_only("environment", namespace.as_ref(), key.as_ref()))
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    Ok(
+      std::env::var(self.var_name(namespace.as_ref(), key.as_ref()))
+        .ok()
+        .map(SecUtf8::from),
+    )
+  }
+}
+
+/// A read-only store loaded from a YAML document that maps namespaces to keys and values.
+///
+/// ```yaml
+/// etrade:
+///   apikey: the consumer key
+///   secret: the consumer secret
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigStore {
+  data: HashMap<String, HashMap<String, SecUtf8>>,
+}
+
+impl ConfigStore {
+  pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let data = serde_yaml::from_reader(file).with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(Self { data })
+  }
+
+  pub fn from_yaml(yaml: &str) -> Result<Self> {
+    Ok(Self {
+      data: serde_yaml::from_str(yaml)?,
+    })
+  }
+}
+
+#[async_trait]
+impl Store for ConfigStore {
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    _value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    Err(read_only("configuration", &namespace.into(), &key.into()))
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    Err(read_only("configuration", namespace.as_ref(), key.as_ref()))
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    Ok(
+      self
+        .data
+        .get(namespace.as_ref())
+        .and_then(|r| r.get(key.as_ref()).cloned()),
+    )
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = self
+      .data
+      .get(namespace.as_ref())
+      .map(|r| r.keys().cloned().collect())
+      .unwrap_or_default();
+    keys.sort();
+    Ok(keys)
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    let mut namespaces: Vec<String> = self.data.keys().cloned().collect();
+    namespaces.sort();
+    Ok(namespaces)
+  }
+}
+
+fn read_only(source: &str, namespace: &str, key: &str) -> anyhow::Error {
+  anyhow!("can't change {}@{}, the {} store is read-only", key, namespace, source)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ConfigStore, EnvStore, LayeredStore};
+  use crate::{Memstore, Store};
+
+  #[tokio::test]
+  async fn test_layered_store() {
+    crate::testing::verify_store(LayeredStore::new(ConfigStore::default(), Memstore::new())).await;
+  }
+
+  #[tokio::test]
+  async fn reads_fall_through_and_writes_go_to_the_writable_layer() {
+    let config = ConfigStore::from_yaml("etrade:\n  apikey: from-config\n").unwrap();
+    let store = LayeredStore::new(config, Memstore::new());
+
+    store.put("etrade", "apikey", "from-memory").await.unwrap();
+    store.put("etrade", "access_token_key", "token").await.unwrap();
+    assert_eq!(
+      store.get("etrade", "apikey").await.unwrap().unwrap().unsecure(),
+      "from-config"
+    );
+    assert_eq!(
+      store
+        .get("etrade", "access_token_key")
+        .await
+        .unwrap()
+        .unwrap()
+        .unsecure(),
+      "token"
+    );
+    assert!(store.read.put("etrade", "apikey", "other").await.is_err());
+    assert!(store.read.del("etrade", "apikey").await.is_err());
+    assert_eq!(store.list("etrade").await.unwrap(), vec!["access_token_key", "apikey"]);
+
+    let env = LayeredStore::new(EnvStore::new(), Memstore::new());
+    env.put("etrade", "apikey", "from-memory").await.unwrap();
+    assert_eq!(env.namespaces().await.unwrap(), vec!["etrade"]);
+  }
+
+  #[tokio::test]
+  async fn reads_environment_variables() {
+    let env = EnvStore::with_prefix("LAYERED_TEST");
+    assert_eq!(
+      env.var_name("etrade:alice", "apikey"),
+      "LAYERED_TEST_ETRADE_ALICE_APIKEY"
+    );
+    std::env::set_var("LAYERED_TEST_ETRADE_ALICE_APIKEY", "from-env");
+
+    assert_eq!(
+      env.get("etrade:alice", "apikey").await.unwrap().unwrap().unsecure(),
+      "from-env"
+    );
+    assert!(env.get("etrade:alice", "secret").await.unwrap().is_none());
+    assert!(env.put("etrade:alice", "apikey", "other").await.is_err());
+  }
+}
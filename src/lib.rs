@@ -6,21 +6,47 @@ extern crate serde;
 
 use std::{
   collections::{BTreeSet, HashMap},
-  sync::Arc,
+  sync::{Arc, OnceLock},
 };
 
 use anyhow::Result;
 use async_trait::async_trait;
+use crypto::{Sealed, SecretKey};
 use secstr::SecUtf8;
 use std::sync::Mutex;
 use strum::EnumString;
 
 pub mod accounts;
 pub mod alerts;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod calendar;
+pub mod cassette;
+mod client;
+pub mod clients;
+mod crypto;
+mod dyn_store;
+mod epoch;
+mod file;
+pub mod history;
+pub mod income;
+mod layered;
+pub mod middleware;
 pub mod options;
 pub mod orders;
+pub mod paper;
+pub mod pdt;
+pub mod performance;
+pub mod rebalance;
+pub mod risk;
 mod session;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod strategy;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod transactions;
+pub mod transport;
 
 #[cfg(all(feature = "keychain", target_os = "linux"))]
 mod linux;
@@ -37,10 +63,24 @@ mod windows;
 #[cfg(all(feature = "keychain", target_os = "windows"))]
 pub use windows::KeychainStore;
 
+pub use dyn_store::DynStore;
+pub use file::FileStore;
+pub use layered::{ConfigStore, EnvStore, LayeredStore};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
 pub use accounts::Api as Accounts;
+pub use alerts::Api as Alerts;
+pub use client::{Client, ClientBuilder};
+pub use clients::{AccountsClient, AlertsClient, MarketClient, OrdersClient, TransactionsClient};
+pub use options::Api as Market;
+pub use orders::Api as Orders;
+pub use session::AuthorizationRequired;
 pub use session::CallbackProvider;
 pub use session::Session;
+pub use session::DEFAULT_PROFILE;
 pub use session::OOB;
+pub use transactions::Api as Transactions;
 
 // The sandbox url to use as base url for the etrade api
 const SANDBOX_URL: &str = "https://apisb.etrade.com";
@@ -92,11 +132,12 @@ pub struct Message {
   pub tpe: MessageType,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString, Default)]
 #[strum(serialize_all = "lowercase")]
 pub enum MessageType {
   #[serde(rename = "WARNING")]
   Warning,
+  #[default]
   #[serde(rename = "INFO")]
   Info,
   #[serde(rename = "INFO_HOLD")]
@@ -105,12 +146,6 @@ pub enum MessageType {
   Error,
 }
 
-impl Default for MessageType {
-  fn default() -> Self {
-    MessageType::Info
-  }
-}
-
 #[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(rename_all = "camelCase", default)]
 pub struct Product {
@@ -148,7 +183,7 @@ pub enum MarketSession {
   Extended,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum OptionType {
   #[serde(rename = "CALL")]
@@ -178,9 +213,10 @@ impl Credentials {
   }
 }
 
-impl Into<oauth::Credentials> for Credentials {
-  fn into(self) -> oauth::Credentials {
-    oauth::Credentials::new(self.key.into_unsecure(), self.secret.into_unsecure())
+/// Borrows the secrets for signing, so no unprotected copies of them are left behind on the heap.
+impl<'a> From<&'a Credentials> for oauth::Credentials<&'a str> {
+  fn from(input: &'a Credentials) -> Self {
+    oauth::Credentials::new(input.key.unsecure(), input.secret.unsecure())
   }
 }
 
@@ -206,11 +242,59 @@ pub trait Store {
   ) -> Result<()>;
   async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()>;
   async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>>;
+
+  /// Lists the keys stored in the namespace, stores that can't enumerate their content fail with
+  /// [`ListingUnsupported`].
+  async fn list(&self, _namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    Err(ListingUnsupported.into())
+  }
+
+  /// Lists the namespaces that hold at least one key, stores that can't enumerate their content fail with
+  /// [`ListingUnsupported`].
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    Err(ListingUnsupported.into())
+  }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("the store does not support listing its content")]
+pub struct ListingUnsupported;
+
+/// Copies every secret from one store into another and returns the number of copied secrets.
+///
+/// When the source can't list its content, the consumer credentials and tokens of the profiles registered in the
+/// source are copied instead.
+pub async fn migrate(from: &(impl Store + Sync), to: &impl Store) -> Result<usize> {
+  let entries = match from.namespaces().await {
+    Ok(namespaces) => {
+      let mut entries = vec![];
+      for namespace in namespaces {
+        for key in from.list(&namespace).await? {
+          entries.push((namespace.clone(), key));
+        }
+      }
+      entries
+    }
+    Err(e) if e.is::<ListingUnsupported>() => session::known_entries(from).await?,
+    Err(e) => return Err(e),
+  };
+
+  let mut copied = 0;
+  for (namespace, key) in entries {
+    if let Some(value) = from.get(&namespace, &key).await? {
+      to.put(namespace, key, value).await?;
+      copied += 1;
+    }
+  }
+  Ok(copied)
 }
 
+/// A store that keeps the values in memory, encrypted with a key generated for the process.
+///
+/// Replaced and removed values are wiped from memory.
 #[derive(Debug)]
 pub struct Memstore {
-  data: Arc<Mutex<HashMap<String, HashMap<String, SecUtf8>>>>,
+  data: Arc<Mutex<HashMap<String, HashMap<String, Sealed>>>>,
 }
 
 impl Memstore {
@@ -219,6 +303,11 @@ impl Memstore {
       data: Arc::new(Mutex::new(HashMap::new())),
     }
   }
+
+  fn key() -> &'static SecretKey {
+    static KEY: OnceLock<SecretKey> = OnceLock::new();
+    KEY.get_or_init(SecretKey::random)
+  }
 }
 
 impl Default for Memstore {
@@ -235,10 +324,11 @@ impl Store for Memstore {
     key: impl Into<String> + Send,
     value: impl Into<SecUtf8> + Send,
   ) -> Result<()> {
+    let sealed = Sealed::new(Memstore::key(), value.into().unsecure().as_bytes());
     let mut data = self.data.lock().unwrap();
 
-    let svc_state = data.entry(namespace.into()).or_insert_with(HashMap::new);
-    svc_state.insert(key.into(), value.into());
+    let svc_state = data.entry(namespace.into()).or_default();
+    svc_state.insert(key.into(), sealed);
     Ok(())
   }
 
@@ -253,30 +343,113 @@ impl Store for Memstore {
 
   async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
     let data = self.data.lock().unwrap();
-    Ok(data.get(namespace.as_ref()).and_then(|r| r.get(key.as_ref()).cloned()))
+    match data.get(namespace.as_ref()).and_then(|r| r.get(key.as_ref())) {
+      Some(sealed) => {
+        let plaintext = sealed.open(Memstore::key())?;
+        Ok(Some(SecUtf8::from(std::str::from_utf8(plaintext.unsecure())?)))
+      }
+      None => Ok(None),
+    }
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    let data = self.data.lock().unwrap();
+    let mut keys: Vec<String> = data
+      .get(namespace.as_ref())
+      .map(|r| r.keys().cloned().collect())
+      .unwrap_or_default();
+    keys.sort();
+    Ok(keys)
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    let data = self.data.lock().unwrap();
+    let mut namespaces: Vec<String> = data
+      .iter()
+      .filter(|(_, r)| !r.is_empty())
+      .map(|(ns, _)| ns.clone())
+      .collect();
+    namespaces.sort();
+    Ok(namespaces)
+  }
+}
+
+#[async_trait]
+impl<T> Store for Arc<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    self.as_ref().put(namespace, key, value).await
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    self.as_ref().del(namespace, key).await
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    self.as_ref().get(namespace, key).await
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    self.as_ref().list(namespace).await
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    self.as_ref().namespaces().await
   }
 }
 
 #[cfg(test)]
 pub mod tests {
 
-  use super::{Memstore, Store};
-  use anyhow::Result;
-  use secstr::SecUtf8;
+  use super::{migrate, Memstore, Store};
   pub(crate) fn init() {
     std::env::set_var("RUST_LOG", "debug");
     let _ = pretty_env_logger::try_init();
   }
   #[tokio::test]
   async fn test_mem_store() {
-    verify_token_store(Memstore::new()).await;
+    crate::testing::verify_store(Memstore::new()).await;
   }
 
-  pub async fn verify_token_store(token_store: impl Store) {
-    let expected: Result<SecUtf8> = Ok("hello".into());
-    token_store.put("my_svc", "api_key", "hello").await.unwrap();
-    assert_eq!(token_store.get("my_svc", "api_key").await.ok(), Some(expected.ok()));
-    assert!(token_store.del("my_svc", "api_key").await.is_ok());
-    assert!(token_store.get("my_svc", "api_key").await.unwrap().is_none());
+  #[tokio::test]
+  async fn test_mem_store_listing() {
+    crate::testing::verify_listing_store(Memstore::new()).await;
+  }
+
+  #[tokio::test]
+  async fn mem_store_encrypts_values() {
+    let store = Memstore::new();
+    store.put("my_svc", "api_key", "plaintext secret").await.unwrap();
+    {
+      let data = store.data.lock().unwrap();
+      let sealed = data["my_svc"]["api_key"].as_ref();
+      assert!(!sealed.windows(16).any(|w| w == b"plaintext secret"));
+    }
+    assert_eq!(
+      store.get("my_svc", "api_key").await.unwrap().unwrap().unsecure(),
+      "plaintext secret"
+    );
+  }
+
+  #[tokio::test]
+  async fn migrates_between_stores() {
+    let from = Memstore::new();
+    from.put("etrade", "apikey", "key").await.unwrap();
+    from.put("etrade:alice", "secret", "secret").await.unwrap();
+    let to = Memstore::new();
+
+    assert_eq!(migrate(&from, &to).await.unwrap(), 2);
+    assert_eq!(to.get("etrade", "apikey").await.unwrap().unwrap().unsecure(), "key");
+    assert_eq!(
+      to.get("etrade:alice", "secret").await.unwrap().unwrap().unsecure(),
+      "secret"
+    );
   }
 }
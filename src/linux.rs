@@ -1,21 +1,81 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
 use secstr::SecUtf8;
 
 use crate::Store;
 use anyhow::{anyhow, Result};
-use secret_service::{EncryptionType, SecretService};
+use secret_service::{Collection, EncryptionType, Item, SecretService};
 
-#[derive(Debug)]
-pub struct KeychainStore;
+const APPLICATION: &str = "etrade";
+
+/// A store backed by the Secret Service API (GNOME Keyring, KWallet).
+///
+/// The connection to the service is opened once and shared by the clones of the store.
+#[derive(Clone)]
+pub struct KeychainStore {
+  service: Arc<SecretService<'static>>,
+}
+
+impl std::fmt::Debug for KeychainStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("KeychainStore").finish()
+  }
+}
 
 impl KeychainStore {
   pub async fn new() -> Result<Self> {
-    Ok(Self)
+    let service = SecretService::connect(EncryptionType::Dh)
+      .await
+      .map_err(|e| anyhow!("failed to acquire secret service: {}", e))?;
+    Ok(Self {
+      service: Arc::new(service),
+    })
+  }
+
+  async fn collection(&self) -> Result<Collection<'_>> {
+    let coll = self
+      .service
+      .get_default_collection()
+      .await
+      .map_err(|e| anyhow!("failed to acquire secret service collection: {}", e))?;
+    if coll
+      .is_locked()
+      .await
+      .map_err(|e| anyhow!("failed to check the secret service collection: {}", e))?
+    {
+      coll
+        .unlock()
+        .await
+        .map_err(|e| anyhow!("failed to unlock the secret service collection: {}", e))?;
+    }
+    Ok(coll)
   }
 }
 
+fn attributes<'a>(namespace: &'a str, key: &'a str) -> HashMap<&'a str, &'a str> {
+  HashMap::from([("application", APPLICATION), ("namespace", namespace), ("key", key)])
+}
+
+/// Finds the items of a key, including the ones written by earlier versions which used the namespace as the
+/// attribute name and the key as its value.
+async fn find<'a>(coll: &'a Collection<'_>, namespace: &str, key: &str) -> Result<Vec<Item<'a>>> {
+  let mut items = coll
+    .search_items(attributes(namespace, key))
+    .await
+    .map_err(|e| anyhow!("failed to find secret ({}:{}): {}", namespace, key, e))?;
+  let legacy = coll
+    .search_items(HashMap::from([(namespace, key)]))
+    .await
+    .map_err(|e| anyhow!("failed to find secret ({}:{}): {}", namespace, key, e))?;
+  for item in legacy {
+    if !items.iter().any(|i| i.item_path == item.item_path) {
+      items.push(item);
+    }
+  }
+  Ok(items)
+}
+
 #[async_trait]
 impl Store for KeychainStore {
   async fn put(
@@ -26,79 +86,64 @@ impl Store for KeychainStore {
   ) -> Result<()> {
     let ns = namespace.into();
     let k = key.into();
-    let label = format!("secret for etradectl {}@{}", &k, &ns);
-    let svc = SecretService::connect(EncryptionType::Dh)
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service: {}", e))?;
-    let coll = svc
-      .get_default_collection()
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service collection: {}", e))?;
-    coll
-      .create_item(
-        &label,
-        vec![(&ns as &str, &k as &str)].into_iter().collect(),
-        value.into().unsecure().as_bytes(),
-        true,
-        "text/plain",
-      )
-      .await
-      .map(|_| ())
-      .map_err(|e| anyhow!("failed to create secret: {}", e))
+    let value = value.into();
+    let coll = self.collection().await?;
+
+    // update the first match in place and drop the duplicates, so the key only ever has one item
+    let mut items = find(&coll, &ns, &k).await?.into_iter();
+    match items.next() {
+      Some(item) => {
+        item
+          .set_secret(value.unsecure().as_bytes(), "text/plain")
+          .await
+          .map_err(|e| anyhow!("failed to update secret: {}", e))?;
+        item
+          .set_attributes(attributes(&ns, &k))
+          .await
+          .map_err(|e| anyhow!("failed to update secret: {}", e))?;
+      }
+      None => {
+        let label = format!("secret for etradectl {}@{}", &k, &ns);
+        coll
+          .create_item(
+            &label,
+            attributes(&ns, &k),
+            value.unsecure().as_bytes(),
+            true,
+            "text/plain",
+          )
+          .await
+          .map_err(|e| anyhow!("failed to create secret: {}", e))?;
+      }
+    }
+    for duplicate in items {
+      duplicate
+        .delete()
+        .await
+        .map_err(|e| anyhow!("failed to delete secret {}", e))?;
+    }
+    Ok(())
   }
 
   async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
-    let svc = SecretService::connect(EncryptionType::Dh)
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service: {}", e))?;
-
-    let coll = svc
-      .get_default_collection()
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service collection: {}", e))?;
-
-    let ns_key = (namespace.as_ref(), key.as_ref());
-
-    let results = coll
-      .search_items(HashMap::from([ns_key]))
-      .await
-      .map_err(|e| anyhow!("failed to find secret ({}:{}): {}", namespace.as_ref(), key.as_ref(), e))?;
-
-    match results.get(0) {
-      Some(item) => item
+    let coll = self.collection().await?;
+    for item in find(&coll, namespace.as_ref(), key.as_ref()).await? {
+      item
         .delete()
         .await
-        .map_err(|e| anyhow!("failed to delete secret {}", e)),
-      _ => Ok(()),
+        .map_err(|e| anyhow!("failed to delete secret {}", e))?;
     }
+    Ok(())
   }
 
   async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
-    let svc = SecretService::connect(EncryptionType::Dh)
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service: {}", e))?;
-    let coll = svc
-      .get_default_collection()
-      .await
-      .map_err(|e| anyhow!("failed to acquire secret service collection: {}", e))?;
-
-    let ns_key = (namespace.as_ref(), key.as_ref());
-
-    let results = coll
-      .search_items(HashMap::from([ns_key]))
-      .await
-      .map_err(|e| anyhow!("failed to find secret ({}:{}): {}", namespace.as_ref(), key.as_ref(), e))?;
-
-    match results.get(0) {
+    let coll = self.collection().await?;
+    match find(&coll, namespace.as_ref(), key.as_ref()).await?.first() {
       Some(item) => {
         let secret = item
           .get_secret()
           .await
           .map_err(|e| anyhow!("failed to get secret: {}", e))?;
-
-        if secret.is_empty() {
-          return Ok(None);
-        }
         Ok(Some(String::from_utf8(secret)?.into()))
       }
       None => Ok(None),
@@ -109,19 +154,9 @@ impl Store for KeychainStore {
 #[cfg(test)]
 mod tests {
   use super::KeychainStore;
-  use anyhow::Result;
-  use secstr::SecUtf8;
 
   #[tokio::test]
   async fn test_secret_service_store() {
-    verify_token_store(KeychainStore::new().await.unwrap()).await
-  }
-
-  async fn verify_token_store(token_store: impl crate::Store) {
-    let expected: Result<SecUtf8> = Ok("hello".into());
-    token_store.put("my_svc", "api_key", "hello").await.unwrap();
-    assert_eq!(token_store.get("my_svc", "api_key").await.ok(), Some(expected.ok()));
-    assert!(token_store.del("my_svc", "api_key").await.is_ok());
-    assert!(token_store.get("my_svc", "api_key").await.unwrap().is_none());
+    crate::testing::verify_store(KeychainStore::new().await.unwrap()).await
   }
 }
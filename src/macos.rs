@@ -1,12 +1,33 @@
-use crate::Store;
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use secstr::SecUtf8;
+use security_framework::base::Error;
 use security_framework::os::macos::keychain::SecKeychain;
-use security_framework::os::macos::passwords::find_generic_password;
 
-#[derive(Debug)]
+use crate::Store;
+
+/// `errSecItemNotFound`
+const ITEM_NOT_FOUND: i32 = -25300;
+
+/// A store backed by the default macOS keychain, the namespace is the service and the key the account of the
+/// generic password.
+#[derive(Debug, Clone, Default)]
 pub struct KeychainStore;
 
+impl KeychainStore {
+  pub async fn new() -> Result<Self> {
+    Ok(Self)
+  }
+}
+
+fn keychain() -> Result<SecKeychain> {
+  SecKeychain::default().map_err(|e| anyhow!("failed to open the default keychain: {}", e))
+}
+
+fn not_found(e: &Error) -> bool {
+  e.code() == ITEM_NOT_FOUND
+}
+
 #[async_trait]
 impl Store for KeychainStore {
   async fn put(
@@ -15,25 +36,46 @@ impl Store for KeychainStore {
     key: impl Into<String> + Send,
     value: impl Into<SecUtf8> + Send,
   ) -> Result<()> {
-    let ns = namespace.into();
-    let k = key.into();
-
-    SecKeychain::default()
-      .map_err(|e| anyhow!("{}", e))?
-      .set_generic_password(&ns, &k, value.into().unsecure().as_bytes())
-      .map_err(|e| anyhow!("{}", e))?;
-
-    Ok(())
+    let (ns, k, value) = (namespace.into(), key.into(), value.into());
+    tokio::task::spawn_blocking(move || {
+      // updates the password when the item exists and adds it otherwise
+      keychain()?
+        .set_generic_password(&ns, &k, value.unsecure().as_bytes())
+        .map_err(|e| anyhow!("failed to store secret ({}:{}): {}", ns, k, e))
+    })
+    .await?
   }
 
   async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
-    let (_, item) = find_generic_password(None, namespace.as_ref(), key.as_ref())?;
-    item.delete();
-    Ok(())
+    let (ns, k) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    tokio::task::spawn_blocking(move || match keychain()?.find_generic_password(&ns, &k) {
+      Ok((_, item)) => {
+        item.delete();
+        Ok(())
+      }
+      Err(e) if not_found(&e) => Ok(()),
+      Err(e) => Err(anyhow!("failed to find secret ({}:{}): {}", ns, k, e)),
+    })
+    .await?
   }
 
   async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
-    let (secret, _) = find_generic_password(None, namespace.as_ref(), key.as_ref())?;
-    Ok(Some(String::from_utf8(secret.to_vec())?.into()))
+    let (ns, k) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    tokio::task::spawn_blocking(move || match keychain()?.find_generic_password(&ns, &k) {
+      Ok((secret, _)) => Ok(Some(String::from_utf8(secret.to_vec())?.into())),
+      Err(e) if not_found(&e) => Ok(None),
+      Err(e) => Err(anyhow!("failed to find secret ({}:{}): {}", ns, k, e)),
+    })
+    .await?
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::KeychainStore;
+
+  #[tokio::test]
+  async fn test_keychain_store() {
+    crate::testing::verify_store(KeychainStore::new().await.unwrap()).await
   }
 }
@@ -0,0 +1,205 @@
+//! Hooks around the signed requests of a [`Session`](crate::Session).
+//!
+//! A [`Middleware`] gets the request right before it is sent, it can change it, observe or replace the response, or
+//! fail the call. Middlewares run in the order they were added with
+//! [`Session::with_middleware`](crate::Session::with_middleware), the first one sees the request first and the
+//! response last.
+
+use std::{
+  sync::Arc,
+  time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use http::{
+  header::{HeaderValue, AUTHORIZATION, USER_AGENT},
+  HeaderMap, Method, Request, Response, StatusCode, Uri,
+};
+use hyper::Body;
+
+use crate::transport::Transport;
+
+#[async_trait]
+pub trait Middleware: Send + Sync {
+  async fn handle(&self, req: Request<Body>, next: Next<'_>) -> Result<Response<Body>>;
+}
+
+/// The rest of the chain, ending with the [`Transport`] of the session.
+pub struct Next<'a> {
+  middlewares: &'a [Arc<dyn Middleware>],
+  transport: &'a dyn Transport,
+}
+
+impl<'a> Next<'a> {
+  pub(crate) fn new(middlewares: &'a [Arc<dyn Middleware>], transport: &'a dyn Transport) -> Self {
+    Self { middlewares, transport }
+  }
+
+  /// Passes the request on to the next middleware, or sends it when this is the end of the chain.
+  pub async fn run(self, req: Request<Body>) -> Result<Response<Body>> {
+    match self.middlewares.split_first() {
+      Some((middleware, rest)) => middleware.handle(req, Next::new(rest, self.transport)).await,
+      None => self.transport.send(req).await,
+    }
+  }
+}
+
+/// Logs requests and responses without the `Authorization` header, so the oauth signature and tokens never end up
+/// in the logs.
+///
+/// The session always logs through one at the debug level, right before sending.
+#[derive(Debug, Clone, Copy)]
+pub struct RedactedLogger {
+  level: log::Level,
+}
+
+impl RedactedLogger {
+  pub fn new(level: log::Level) -> Self {
+    Self { level }
+  }
+}
+
+impl Default for RedactedLogger {
+  fn default() -> Self {
+    Self::new(log::Level::Debug)
+  }
+}
+
+/// Copies the headers, replacing the value of the `Authorization` header.
+fn redacted(headers: &HeaderMap) -> HeaderMap {
+  let mut headers = headers.clone();
+  if headers.contains_key(AUTHORIZATION) {
+    headers.insert(AUTHORIZATION, HeaderValue::from_static("<redacted>"));
+  }
+  headers
+}
+
+#[async_trait]
+impl Middleware for RedactedLogger {
+  async fn handle(&self, req: Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+    log!(
+      self.level,
+      "request: {} {} {:?}",
+      req.method(),
+      req.uri(),
+      redacted(req.headers())
+    );
+    let resp = next.run(req).await?;
+    log!(self.level, "response: {} {:?}", resp.status(), redacted(resp.headers()));
+    Ok(resp)
+  }
+}
+
+/// What [`Timing`] reports for every request.
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+  pub method: Method,
+  pub uri: Uri,
+  /// `None` when the request failed without a response.
+  pub status: Option<StatusCode>,
+  pub elapsed: Duration,
+}
+
+/// Measures how long the rest of the chain takes to respond and reports it to a callback, to feed metrics.
+pub struct Timing {
+  report: Box<dyn Fn(&RequestTiming) + Send + Sync>,
+}
+
+impl Timing {
+  pub fn new(report: impl Fn(&RequestTiming) + Send + Sync + 'static) -> Self {
+    Self {
+      report: Box::new(report),
+    }
+  }
+}
+
+#[async_trait]
+impl Middleware for Timing {
+  async fn handle(&self, req: Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+    let (method, uri) = (req.method().clone(), req.uri().clone());
+    let start = Instant::now();
+    let resp = next.run(req).await;
+    (self.report)(&RequestTiming {
+      method,
+      uri,
+      status: resp.as_ref().ok().map(|r| r.status()),
+      elapsed: start.elapsed(),
+    });
+    resp
+  }
+}
+
+/// Sets the `User-Agent` header of every request.
+#[derive(Debug, Clone)]
+pub struct UserAgent(HeaderValue);
+
+impl UserAgent {
+  pub fn new(user_agent: impl AsRef<str>) -> Result<Self> {
+    Ok(Self(HeaderValue::from_str(user_agent.as_ref())?))
+  }
+}
+
+#[async_trait]
+impl Middleware for UserAgent {
+  async fn handle(&self, mut req: Request<Body>, next: Next<'_>) -> Result<Response<Body>> {
+    req.headers_mut().insert(USER_AGENT, self.0.clone());
+    next.run(req).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use anyhow::Result;
+  use async_trait::async_trait;
+  use http::{header::AUTHORIZATION, Request, Response};
+  use hyper::Body;
+
+  use super::{redacted, Middleware, Next, Timing, UserAgent};
+
+  /// Answers every request with the headers it received, instead of sending it.
+  struct Echo;
+
+  #[async_trait]
+  impl Middleware for Echo {
+    async fn handle(&self, req: Request<Body>, _next: Next<'_>) -> Result<Response<Body>> {
+      let mut resp = Response::new(Body::empty());
+      *resp.headers_mut() = req.headers().clone();
+      Ok(resp)
+    }
+  }
+
+  #[tokio::test]
+  async fn runs_the_chain_in_order() {
+    let timings = Arc::new(Mutex::new(vec![]));
+    let recorded = timings.clone();
+    let chain: Vec<Arc<dyn Middleware>> = vec![
+      Arc::new(Timing::new(move |t| recorded.lock().unwrap().push(t.status))),
+      Arc::new(UserAgent::new("etrade-tests").unwrap()),
+      Arc::new(Echo),
+    ];
+    let client = crate::transport::https_client();
+
+    let req = Request::get("http://localhost/v1/accounts/list")
+      .body(Body::empty())
+      .unwrap();
+    let resp = Next::new(&chain, &client).run(req).await.unwrap();
+    assert_eq!(resp.headers()["user-agent"], "etrade-tests");
+    assert_eq!(*timings.lock().unwrap(), vec![Some(http::StatusCode::OK)]);
+  }
+
+  #[test]
+  fn redacts_the_authorization_header() {
+    let req = Request::get("http://localhost")
+      .header(AUTHORIZATION, "OAuth oauth_token=\"secret\"")
+      .header("accept", "application/json")
+      .body(())
+      .unwrap();
+    let headers = redacted(req.headers());
+    assert_eq!(headers[AUTHORIZATION], "<redacted>");
+    assert_eq!(headers["accept"], "application/json");
+    assert!(!format!("{:?}", headers).contains("secret"));
+  }
+}
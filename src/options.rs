@@ -1,4 +1,4 @@
-use crate::{accounts::QuoteStatus, empty_body, qs_params, session::CallbackProvider, Messages};
+use crate::{accounts::QuoteStatus, empty_body, qs_params, Messages};
 use crate::{Product, Session, Store};
 use anyhow::Result;
 use http::Method;
@@ -17,52 +17,32 @@ where
     Self { session }
   }
 
-  pub async fn quotes(
-    &self,
-    symbols: &str,
-    params: GetQuotesRequest,
-    callbacks: impl CallbackProvider,
-  ) -> Result<QuoteResponse> {
+  pub async fn quotes(&self, symbols: &str, params: GetQuotesRequest) -> Result<QuoteResponse> {
     let quotes: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/market/quote/{}", symbols),
         qs_params(&params)?,
-        callbacks,
       )
       .await?;
     debug!("quotes json: {}", serde_json::to_string_pretty(&quotes)?);
     Ok(serde_json::from_value(quotes.get("QuoteResponse").unwrap().clone())?)
   }
 
-  pub async fn product(&self, search: &str, callbacks: impl CallbackProvider) -> Result<LookupResponse> {
+  pub async fn product(&self, search: &str) -> Result<LookupResponse> {
     let product: serde_json::Value = self
       .session
-      .send(
-        Method::GET,
-        format!("/v1/market/quote/{}", search),
-        empty_body(),
-        callbacks,
-      )
+      .send(Method::GET, format!("/v1/market/quote/{}", search), empty_body())
       .await?;
     debug!("product json: {}", serde_json::to_string_pretty(&product)?);
     Ok(serde_json::from_value(product.get("LookupResponse").unwrap().clone())?)
   }
 
-  pub async fn chains<'a>(
-    &self,
-    params: &'a GetOptionChainsRequest<'a>,
-    callbacks: impl CallbackProvider,
-  ) -> Result<OptionChainResponse> {
+  pub async fn chains<'a>(&self, params: &'a GetOptionChainsRequest<'a>) -> Result<OptionChainResponse> {
     let chains: serde_json::Value = self
       .session
-      .send(
-        Method::GET,
-        "/v1/market/quote/optionchains",
-        qs_params(params)?,
-        callbacks,
-      )
+      .send(Method::GET, "/v1/market/quote/optionchains", qs_params(params)?)
       .await?;
     debug!("chains json: {}", serde_json::to_string_pretty(&chains)?);
     Ok(serde_json::from_value(
@@ -73,16 +53,10 @@ where
   pub async fn expire_dates<'a>(
     &self,
     params: &'a GetOptionExpireDatesRequest<'a>,
-    callbacks: impl CallbackProvider,
   ) -> Result<OptionExpireDateResponse> {
     let dates: serde_json::Value = self
       .session
-      .send(
-        Method::GET,
-        "/v1/market/quote/optionexpiredate",
-        qs_params(params)?,
-        callbacks,
-      )
+      .send(Method::GET, "/v1/market/quote/optionexpiredate", qs_params(params)?)
       .await?;
     debug!("dates json: {}", serde_json::to_string_pretty(&dates)?);
     Ok(serde_json::from_value(
@@ -607,9 +581,10 @@ pub enum PriceType {
   All,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString, Default)]
 #[strum(serialize_all = "lowercase")]
 pub enum ExpiryType {
+  #[default]
   #[serde(rename = "UNSPECIFIED")]
   Unspecified,
   #[serde(rename = "DAILY")]
@@ -627,9 +602,3 @@ pub enum ExpiryType {
   #[serde(rename = "MONTHEND")]
   Monthend,
 }
-
-impl Default for ExpiryType {
-  fn default() -> Self {
-    ExpiryType::Unspecified
-  }
-}
@@ -1,4 +1,4 @@
-use crate::{qs_params, session::CallbackProvider, Messages};
+use crate::{qs_params, Messages};
 use crate::{MarketSession, Product, SecurityType, Session, Store};
 use anyhow::Result;
 use http::Method;
@@ -17,31 +17,20 @@ where
     Self { session }
   }
 
-  pub async fn list(
-    &self,
-    account_id_key: &str,
-    params: ListOrdersRequest,
-    callbacks: impl CallbackProvider,
-  ) -> Result<OrdersResponse> {
+  pub async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
     let orders: serde_json::Value = self
       .session
       .send(
         Method::GET,
         format!("/v1/accounts/{}/orders", account_id_key),
         qs_params(&params)?,
-        callbacks,
       )
       .await?;
     debug!("orders json: {}", serde_json::to_string_pretty(&orders)?);
     Ok(serde_json::from_value(orders.get("OrdersResponse").unwrap().clone())?)
   }
 
-  pub async fn preview(
-    &self,
-    account_id_key: &str,
-    params: PreviewOrderRequest,
-    callbacks: impl CallbackProvider,
-  ) -> Result<PreviewOrderResponse> {
+  pub async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
     let preview: serde_json::Value = self
       .session
       .clone()
@@ -49,7 +38,6 @@ where
         Method::POST,
         format!("/v1/accounts/{}/orders/preview", account_id_key),
         Some(params),
-        callbacks,
       )
       .await?;
     debug!("preview json: {}", serde_json::to_string_pretty(&preview)?);
@@ -58,12 +46,7 @@ where
     )?)
   }
 
-  pub async fn place(
-    &self,
-    account_id_key: &str,
-    params: PlaceOrderRequest,
-    callbacks: impl CallbackProvider,
-  ) -> Result<PlaceOrderResponse> {
+  pub async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
     let place: serde_json::Value = self
       .session
       .clone()
@@ -71,7 +54,6 @@ where
         Method::POST,
         format!("/v1/accounts/{}/orders/place", account_id_key),
         Some(params),
-        callbacks,
       )
       .await?;
     debug!("placed order json: {}", serde_json::to_string_pretty(&place)?);
@@ -80,12 +62,7 @@ where
     )?)
   }
 
-  pub async fn cancel(
-    &self,
-    account_id_key: &str,
-    params: CancelOrderRequest,
-    callback: impl CallbackProvider,
-  ) -> Result<CancelOrderResponse> {
+  pub async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
     let cancellation: serde_json::Value = self
       .session
       .clone()
@@ -93,7 +70,6 @@ where
         Method::PUT,
         format!("/v1/accounts/{}/orders/cancel", account_id_key),
         Some(params),
-        callback,
       )
       .await?;
     debug!("cancellation json: {}", serde_json::to_string_pretty(&cancellation)?);
@@ -107,7 +83,6 @@ where
     account_id_key: &str,
     order_id: &str,
     params: PreviewOrderRequest,
-    callback: impl CallbackProvider,
   ) -> Result<PreviewOrderResponse> {
     let preview: serde_json::Value = self
       .session
@@ -116,7 +91,6 @@ where
         Method::PUT,
         format!("/v1/accounts/{}/orders/{}/change/preview", account_id_key, order_id),
         Some(params),
-        callback,
       )
       .await?;
     debug!("changed preview json: {}", serde_json::to_string_pretty(&preview)?);
@@ -130,7 +104,6 @@ where
     account_id_key: &str,
     order_id: &str,
     params: PlaceOrderRequest,
-    callback: impl CallbackProvider,
   ) -> Result<PlaceOrderResponse> {
     let place: serde_json::Value = self
       .session
@@ -139,7 +112,6 @@ where
         Method::PUT,
         format!("/v1/accounts/{}/orders/{}/change/place", account_id_key, order_id),
         Some(params),
-        callback,
       )
       .await?;
     debug!("changed placed order json: {}", serde_json::to_string_pretty(&place)?);
@@ -485,9 +457,10 @@ pub struct Lot {
   pub size: f64,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString, Default)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum Currency {
+  #[default]
   #[serde(rename = "USD")]
   Usd,
   #[serde(rename = "EUR")]
@@ -502,12 +475,6 @@ pub enum Currency {
   Cad,
 }
 
-impl Default for Currency {
-  fn default() -> Self {
-    Currency::Usd
-  }
-}
-
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum QuantityType {
@@ -528,7 +495,7 @@ pub enum MFTransaction {
   Sell,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum OrderAction {
   #[serde(rename = "BUY")]
@@ -551,9 +518,10 @@ pub enum OrderAction {
   Exchange,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString, Default)]
 #[strum(serialize_all = "lowercase")]
 pub enum EventName {
+  #[default]
   #[serde(rename = "UNSPECIFIED")]
   Unspecified,
   #[serde(rename = "ORDER_PLACED")]
@@ -606,12 +574,6 @@ pub enum EventName {
   RejectionReversal,
 }
 
-impl Default for EventName {
-  fn default() -> Self {
-    EventName::Unspecified
-  }
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct Events {
@@ -755,9 +717,10 @@ pub enum OffsetType {
   TrailingStopPrct,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString, Default)]
 #[strum(serialize_all = "lowercase")]
 pub enum RoutingDestination {
+  #[default]
   #[serde(rename = "AUTO")]
   Auto,
   #[serde(rename = "AMEX")]
@@ -776,11 +739,6 @@ pub enum RoutingDestination {
   Phx,
 }
 
-impl Default for RoutingDestination {
-  fn default() -> Self {
-    RoutingDestination::Auto
-  }
-}
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, EnumString)]
 #[strum(serialize_all = "lowercase")]
 pub enum ConditionType {
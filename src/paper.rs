@@ -0,0 +1,1019 @@
+//! Paper trading: a [`PaperBroker`] takes the requests of [`orders::Api`](crate::orders::Api) and fills them against
+//! a [`QuoteSource`] instead of sending them to E*Trade.
+//!
+//! The broker keeps the cash and positions of a single account. Market orders fill right away at the ask when buying
+//! and the bid when selling, limit and stop orders stay open until [`PaperBroker::tick`] sees a quote that reaches
+//! their price. Only equity buys and sells of the market, limit and stop price types are supported, short sales are
+//! rejected.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+  },
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::sync::broadcast;
+
+use crate::accounts::{ComputedBalance, RealTimeValues};
+use crate::options::{self, DetailFlag, GetQuotesRequest};
+use crate::orders::{
+  CancelOrderRequest, CancelOrderResponse, Event, EventName, Events, ListOrdersRequest, Order, OrderAction,
+  OrderDetail, OrderId, OrderStatus, OrdersResponse, PlaceOrderRequest, PlaceOrderResponse, PreviewId,
+  PreviewOrderRequest, PreviewOrderResponse, PriceType,
+};
+use crate::Store;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quote {
+  pub bid: f64,
+  pub ask: f64,
+  pub last: f64,
+}
+
+impl Quote {
+  /// A quote without spread.
+  pub fn at(price: f64) -> Self {
+    Self {
+      bid: price,
+      ask: price,
+      last: price,
+    }
+  }
+}
+
+/// Where a [`PaperBroker`] gets its prices.
+#[async_trait]
+pub trait QuoteSource: Send + Sync {
+  async fn quote(&self, symbol: &str) -> Result<Quote>;
+}
+
+/// Live prices from the market api.
+#[async_trait]
+impl<T> QuoteSource for options::Api<T>
+where
+  T: Store + Send + Sync,
+{
+  async fn quote(&self, symbol: &str) -> Result<Quote> {
+    let params = GetQuotesRequest {
+      detail_flag: Some(DetailFlag::All),
+      ..Default::default()
+    };
+    let resp = self.quotes(symbol, params).await?;
+    let all = resp
+      .quote_data
+      .into_iter()
+      .find_map(|q| q.all)
+      .ok_or_else(|| anyhow!("no quote for {}", symbol))?;
+    Ok(Quote {
+      bid: all.bid,
+      ask: all.ask,
+      last: all.last_trade,
+    })
+  }
+}
+
+/// Replays series of historical quotes, [`ReplayQuotes::advance`] moves every symbol to its next quote.
+///
+/// A symbol stays at its last quote once its series runs out.
+#[derive(Debug, Default)]
+pub struct ReplayQuotes {
+  series: HashMap<String, Vec<Quote>>,
+  cursor: AtomicUsize,
+}
+
+impl ReplayQuotes {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_quotes(mut self, symbol: impl Into<String>, quotes: impl IntoIterator<Item = Quote>) -> Self {
+    self.series.insert(symbol.into(), quotes.into_iter().collect());
+    self
+  }
+
+  /// Replays prices without spread.
+  pub fn with_prices(self, symbol: impl Into<String>, prices: impl IntoIterator<Item = f64>) -> Self {
+    self.with_quotes(symbol, prices.into_iter().map(Quote::at))
+  }
+
+  /// Moves to the next quotes, returns false when every series is at its end.
+  pub fn advance(&self) -> bool {
+    let longest = self.series.values().map(Vec::len).max().unwrap_or_default();
+    let cursor = self.cursor.load(Ordering::SeqCst);
+    if cursor + 1 >= longest {
+      return false;
+    }
+    self.cursor.store(cursor + 1, Ordering::SeqCst);
+    true
+  }
+}
+
+#[async_trait]
+impl QuoteSource for ReplayQuotes {
+  async fn quote(&self, symbol: &str) -> Result<Quote> {
+    let series = self
+      .series
+      .get(symbol)
+      .filter(|s| !s.is_empty())
+      .ok_or_else(|| anyhow!("no quote for {}", symbol))?;
+    let cursor = self.cursor.load(Ordering::SeqCst).min(series.len() - 1);
+    Ok(series[cursor])
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Position {
+  pub symbol: String,
+  pub quantity: f64,
+  /// What was paid for the shares that are still held, commissions excluded.
+  pub total_cost: f64,
+}
+
+struct Preview {
+  request: PreviewOrderRequest,
+  replaces: Option<i64>,
+}
+
+#[derive(Default)]
+struct Book {
+  cash: f64,
+  positions: BTreeMap<String, Position>,
+  orders: Vec<Order>,
+  previews: HashMap<i64, Preview>,
+  last_id: i64,
+}
+
+impl Book {
+  fn next_id(&mut self) -> i64 {
+    self.last_id += 1;
+    self.last_id
+  }
+
+  fn open_order(&mut self, order_id: i64) -> Result<&mut Order> {
+    let order = self
+      .orders
+      .iter_mut()
+      .find(|o| o.order_id == order_id)
+      .ok_or_else(|| anyhow!("order {} not found", order_id))?;
+    if !is_open(order) {
+      return Err(anyhow!("order {} is not open", order_id));
+    }
+    Ok(order)
+  }
+
+  /// The cash held back for open buy orders and the shares for open sell orders, except for the given order.
+  fn reserved(&self, except: Option<i64>) -> (f64, HashMap<String, f64>) {
+    let mut cash = 0.0;
+    let mut shares: HashMap<String, f64> = HashMap::new();
+    for order in self.orders.iter().filter(|o| Some(o.order_id) != except) {
+      for detail in order.order_detail.iter().filter(|d| is_open_detail(d)) {
+        for i in &detail.instrument {
+          match i.order_action {
+            Some(OrderAction::Buy) => cash += i.quantity * reserve_price(detail),
+            _ => *shares.entry(i.product.symbol.clone()).or_default() += i.quantity,
+          }
+        }
+      }
+    }
+    (cash, shares)
+  }
+}
+
+fn is_open_detail(detail: &OrderDetail) -> bool {
+  matches!(detail.status, Some(OrderStatus::Open))
+}
+
+fn is_open(order: &Order) -> bool {
+  order.order_detail.iter().any(is_open_detail)
+}
+
+fn reserve_price(detail: &OrderDetail) -> f64 {
+  if detail.limit_price > 0.0 {
+    detail.limit_price
+  } else {
+    detail.stop_price
+  }
+}
+
+fn now() -> i64 {
+  Utc::now().timestamp_millis()
+}
+
+/// The price an order detail fills at given the quote, `None` while its price isn't reached.
+fn fill_price(detail: &OrderDetail, action: OrderAction, quote: &Quote) -> Option<f64> {
+  let buying = matches!(action, OrderAction::Buy);
+  let market = if buying { quote.ask } else { quote.bid };
+  match detail.price_type {
+    Some(PriceType::Market) | None => Some(market),
+    Some(PriceType::Limit) if buying => (market <= detail.limit_price).then_some(market),
+    Some(PriceType::Limit) => (market >= detail.limit_price).then_some(market),
+    Some(PriceType::Stop) if buying => (quote.last >= detail.stop_price).then_some(market),
+    Some(PriceType::Stop) => (quote.last <= detail.stop_price).then_some(market),
+    Some(_) => None,
+  }
+}
+
+/// A simulated broker for one account, with the order methods of [`orders::Api`](crate::orders::Api).
+pub struct PaperBroker<Q: QuoteSource> {
+  account_id_key: String,
+  quotes: Q,
+  commission: f64,
+  book: Mutex<Book>,
+  events: broadcast::Sender<Event>,
+}
+
+impl<Q> PaperBroker<Q>
+where
+  Q: QuoteSource,
+{
+  /// A broker for the account with the given key, starting with only cash.
+  pub fn new(account_id_key: impl Into<String>, cash: f64, quotes: Q) -> Self {
+    Self {
+      account_id_key: account_id_key.into(),
+      quotes,
+      commission: 0.0,
+      book: Mutex::new(Book {
+        cash,
+        ..Default::default()
+      }),
+      events: broadcast::channel(256).0,
+    }
+  }
+
+  /// Charges a flat commission on every filled order.
+  pub fn with_commission(mut self, commission: f64) -> Self {
+    self.commission = commission;
+    self
+  }
+
+  /// Starts with the given positions.
+  pub fn with_positions(self, positions: impl IntoIterator<Item = Position>) -> Self {
+    {
+      let mut book = self.book.lock().unwrap();
+      for position in positions {
+        book.positions.insert(position.symbol.clone(), position);
+      }
+    }
+    self
+  }
+
+  pub fn quotes(&self) -> &Q {
+    &self.quotes
+  }
+
+  /// Receives the events of every order as they happen: placed, executed, cancelled, modified or rejected.
+  pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+    self.events.subscribe()
+  }
+
+  pub fn cash(&self) -> f64 {
+    self.book.lock().unwrap().cash
+  }
+
+  pub fn positions(&self) -> Vec<Position> {
+    self.book.lock().unwrap().positions.values().cloned().collect()
+  }
+
+  /// The balance of the account, valued at the last price of the positions.
+  pub async fn balance(&self) -> Result<ComputedBalance> {
+    let positions = self.positions();
+    let mut market_value = 0.0;
+    for position in &positions {
+      market_value += position.quantity * self.quotes.quote(&position.symbol).await?.last;
+    }
+    let book = self.book.lock().unwrap();
+    let buying_power = book.cash - book.reserved(None).0;
+    Ok(ComputedBalance {
+      cash_available_for_investment: buying_power,
+      cash_available_for_withdrawal: book.cash,
+      net_cash: book.cash,
+      cash_balance: book.cash,
+      settled_cash_for_investment: book.cash,
+      cash_buying_power: Some(buying_power),
+      real_time_values: RealTimeValues {
+        total_account_value: book.cash + market_value,
+        net_mv: market_value,
+        net_mv_long: market_value,
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+  }
+
+  fn check_account(&self, account_id_key: &str) -> Result<()> {
+    if account_id_key != self.account_id_key {
+      return Err(anyhow!("unknown account {}", account_id_key));
+    }
+    Ok(())
+  }
+
+  async fn fetch_quotes<'a>(
+    &self,
+    details: impl IntoIterator<Item = &'a OrderDetail>,
+  ) -> Result<HashMap<String, Quote>> {
+    let mut quotes = HashMap::new();
+    for detail in details {
+      for i in &detail.instrument {
+        if !quotes.contains_key(&i.product.symbol) {
+          quotes.insert(i.product.symbol.clone(), self.quotes.quote(&i.product.symbol).await?);
+        }
+      }
+    }
+    Ok(quotes)
+  }
+
+  fn emit(&self, order: &mut Order, name: EventName, detail: Option<&OrderDetail>) {
+    let event = Event {
+      name,
+      date_time: now(),
+      order_number: order.order_id as isize,
+      instrument: detail.map(|d| d.instrument.clone()).unwrap_or_default(),
+    };
+    order.events.event.push(event.clone());
+    let _ = self.events.send(event);
+  }
+
+  pub async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
+    self.check_account(account_id_key)?;
+    let book = self.book.lock().unwrap();
+    let mut order: Vec<Order> = book
+      .orders
+      .iter()
+      .filter(|o| match params.status {
+        Some(status) => o.order_detail.iter().any(|d| {
+          d.status
+            .map(|s| std::mem::discriminant(&s) == std::mem::discriminant(&status))
+            .unwrap_or(false)
+        }),
+        None => true,
+      })
+      .cloned()
+      .collect();
+    if let Some(count) = params.count {
+      order.truncate(count);
+    }
+    Ok(OrdersResponse {
+      order,
+      ..Default::default()
+    })
+  }
+
+  pub async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
+    self.do_preview(account_id_key, params, None).await
+  }
+
+  pub async fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse> {
+    let order_id: i64 = order_id.parse()?;
+    self.book.lock().unwrap().open_order(order_id)?;
+    self.do_preview(account_id_key, params, Some(order_id)).await
+  }
+
+  pub async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
+    self.do_place(account_id_key, params, None).await
+  }
+
+  pub async fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse> {
+    self.do_place(account_id_key, params, Some(order_id.parse()?)).await
+  }
+
+  pub async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
+    self.check_account(account_id_key)?;
+    let mut book = self.book.lock().unwrap();
+    let order = book.open_order(params.order_id)?;
+    for detail in order.order_detail.iter_mut().filter(|d| is_open_detail(d)) {
+      detail.status = Some(OrderStatus::Cancelled);
+    }
+    self.emit(order, EventName::OrderCancelled, None);
+    Ok(CancelOrderResponse {
+      account_id: self.account_id_key.clone(),
+      order_id: params.order_id,
+      cancel_time: now(),
+      ..Default::default()
+    })
+  }
+
+  /// Fills the open orders whose price the current quotes reach.
+  pub async fn tick(&self) -> Result<()> {
+    let open: Vec<OrderDetail> = {
+      let book = self.book.lock().unwrap();
+      book
+        .orders
+        .iter()
+        .flat_map(|o| o.order_detail.iter())
+        .filter(|d| is_open_detail(d))
+        .cloned()
+        .collect()
+    };
+    let quotes = self.fetch_quotes(&open).await?;
+    let mut book = self.book.lock().unwrap();
+    for index in 0..book.orders.len() {
+      if is_open(&book.orders[index]) {
+        self.fill(&mut book, index, &quotes);
+      }
+    }
+    Ok(())
+  }
+
+  /// Checks that the cash and the shares not held back for the other open orders cover the order, and sets the value
+  /// of its details. The order that `replaces` is being changed doesn't hold anything back.
+  fn check(
+    &self,
+    book: &Book,
+    order: &mut [OrderDetail],
+    quotes: &HashMap<String, Quote>,
+    replaces: Option<i64>,
+  ) -> Result<()> {
+    let (reserved_cash, mut reserved_shares) = book.reserved(replaces);
+    let mut cost = self.commission;
+    for detail in order.iter_mut() {
+      if !matches!(
+        detail.price_type,
+        None | Some(PriceType::Market) | Some(PriceType::Limit) | Some(PriceType::Stop)
+      ) {
+        return Err(anyhow!("unsupported price type {:?}", detail.price_type));
+      }
+      for i in &detail.instrument {
+        if i.quantity <= 0.0 {
+          return Err(anyhow!("the quantity of {} must be positive", i.product.symbol));
+        }
+        let quote = &quotes[&i.product.symbol];
+        match i.order_action {
+          Some(OrderAction::Buy) => {
+            let price = match detail.price_type {
+              Some(PriceType::Limit) | Some(PriceType::Stop) => reserve_price(detail),
+              _ => quote.ask,
+            };
+            cost += i.quantity * price;
+            detail.order_value += i.quantity * price;
+          }
+          Some(OrderAction::Sell) => {
+            let held = book
+              .positions
+              .get(&i.product.symbol)
+              .map(|p| p.quantity)
+              .unwrap_or_default();
+            let reserved = reserved_shares.entry(i.product.symbol.clone()).or_default();
+            if i.quantity > held - *reserved {
+              return Err(anyhow!(
+                "can't sell {} {}, {} available",
+                i.quantity,
+                i.product.symbol,
+                held - *reserved
+              ));
+            }
+            *reserved += i.quantity;
+            detail.order_value += i.quantity * quote.bid;
+          }
+          action => return Err(anyhow!("unsupported order action {:?}", action)),
+        }
+      }
+    }
+    let buying_power = book.cash - reserved_cash;
+    if cost > buying_power {
+      return Err(anyhow!(
+        "insufficient buying power: the order needs {:.2}, {:.2} available",
+        cost,
+        buying_power
+      ));
+    }
+    Ok(())
+  }
+
+  async fn do_preview(
+    &self,
+    account_id_key: &str,
+    params: PreviewOrderRequest,
+    replaces: Option<i64>,
+  ) -> Result<PreviewOrderResponse> {
+    self.check_account(account_id_key)?;
+    if params.order.is_empty() {
+      return Err(anyhow!("the order has no details"));
+    }
+    let quotes = self.fetch_quotes(&params.order).await?;
+
+    let mut book = self.book.lock().unwrap();
+    let mut order = params.order.clone();
+    self.check(&book, &mut order, &quotes, replaces)?;
+
+    let preview_id = book.next_id();
+    let preview_time = now();
+    for (index, detail) in order.iter_mut().enumerate() {
+      detail.preview_id = preview_id;
+      detail.preview_time = preview_time;
+      detail.estimated_commission = if index == 0 { self.commission } else { 0.0 };
+    }
+    book.previews.insert(
+      preview_id,
+      Preview {
+        request: params.clone(),
+        replaces,
+      },
+    );
+    Ok(PreviewOrderResponse {
+      total_order_value: order.iter().map(|d| d.order_value).sum(),
+      total_commission: self.commission,
+      order,
+      preview_ids: vec![PreviewId {
+        preview_id,
+        cash_margin: "CASH".to_string(),
+      }],
+      preview_time,
+      account_id: self.account_id_key.clone(),
+      client_order_id: params.client_order_id,
+      ..Default::default()
+    })
+  }
+
+  async fn do_place(
+    &self,
+    account_id_key: &str,
+    params: PlaceOrderRequest,
+    replaces: Option<i64>,
+  ) -> Result<PlaceOrderResponse> {
+    self.check_account(account_id_key)?;
+    let preview_id = params
+      .preview_ids
+      .first()
+      .map(|p| p.preview_id)
+      .ok_or_else(|| anyhow!("missing preview id"))?;
+    let details = match self.book.lock().unwrap().previews.get(&preview_id) {
+      Some(preview) if preview.replaces == replaces => preview.request.order.clone(),
+      _ => return Err(anyhow!("unknown preview id {}", preview_id)),
+    };
+    let quotes = self.fetch_quotes(&details).await?;
+
+    let mut book = self.book.lock().unwrap();
+    if let Some(replaced) = replaces {
+      book.open_order(replaced)?;
+    }
+    // other orders may have been placed since the preview, check again before placing this one
+    self.check(&book, &mut details.clone(), &quotes, replaces)?;
+    let preview = book
+      .previews
+      .remove(&preview_id)
+      .ok_or_else(|| anyhow!("unknown preview id {}", preview_id))?;
+    let order_id = book.next_id();
+    let placed_time = now();
+    if let Some(replaced) = replaces {
+      let order = book.open_order(replaced)?;
+      for detail in order.order_detail.iter_mut() {
+        detail.status = Some(OrderStatus::Cancelled);
+        detail.replaced_by_order_id = order_id as isize;
+      }
+      self.emit(order, EventName::OrderModified, None);
+    }
+
+    let order_detail: Vec<OrderDetail> = preview
+      .request
+      .order
+      .into_iter()
+      .map(|mut d| {
+        d.account_id = self.account_id_key.clone();
+        d.preview_id = preview_id;
+        d.placed_time = placed_time;
+        d.status = Some(OrderStatus::Open);
+        d.replaces_order_id = replaces.unwrap_or_default() as isize;
+        d
+      })
+      .collect();
+    let order_type = params.order_type.or(preview.request.order_type);
+    let mut order = Order {
+      order_id,
+      order_type: order_type
+        .and_then(|t| serde_json::to_value(t).ok())
+        .and_then(|t| t.as_str().map(str::to_string))
+        .unwrap_or_default(),
+      order_detail: order_detail.clone(),
+      events: Events::default(),
+      ..Default::default()
+    };
+    self.emit(&mut order, EventName::OrderPlaced, None);
+    book.orders.push(order);
+    let index = book.orders.len() - 1;
+    self.fill(&mut book, index, &quotes);
+    let order = &book.orders[index];
+
+    Ok(PlaceOrderResponse {
+      order_type,
+      total_order_value: order.total_order_value,
+      total_commission: order.total_commission,
+      order_id,
+      order: order.order_detail.clone(),
+      account_id: self.account_id_key.clone(),
+      order_ids: vec![OrderId {
+        order_id,
+        cash_margin: "CASH".to_string(),
+      }],
+      placed_time,
+      client_order_id: params.client_order_id,
+      ..Default::default()
+    })
+  }
+
+  /// Fills the open details of the order whose price is reached, or rejects them when the cash or the shares don't
+  /// cover them anymore.
+  fn fill(&self, book: &mut Book, index: usize, quotes: &HashMap<String, Quote>) {
+    let mut order = book.orders[index].clone();
+    let mut events = vec![];
+    // The commission is charged once per order, with the first detail that fills.
+    let mut commission = if order.total_commission > 0.0 {
+      0.0
+    } else {
+      self.commission
+    };
+    for detail in order.order_detail.iter_mut().filter(|d| is_open_detail(d)) {
+      let prices: Option<Vec<f64>> = detail
+        .instrument
+        .iter()
+        .map(|i| {
+          let quote = quotes.get(&i.product.symbol)?;
+          fill_price(detail, i.order_action?, quote)
+        })
+        .collect();
+      let prices = match prices {
+        Some(prices) => prices,
+        None => continue,
+      };
+
+      let cost: f64 = detail
+        .instrument
+        .iter()
+        .zip(&prices)
+        .map(|(i, price)| match i.order_action {
+          Some(OrderAction::Buy) => i.quantity * price,
+          _ => -i.quantity * price,
+        })
+        .sum::<f64>()
+        + commission;
+      let short = detail.instrument.iter().any(|i| {
+        let held = book.positions.get(&i.product.symbol).map(|p| p.quantity);
+        !matches!(i.order_action, Some(OrderAction::Buy)) && held.unwrap_or_default() < i.quantity
+      });
+      if cost > book.cash || short {
+        detail.status = Some(OrderStatus::Rejected);
+        events.push((EventName::OrderRejected, detail.clone()));
+        continue;
+      }
+
+      book.cash -= cost;
+      let executed_time = now();
+      for (n, (i, price)) in detail.instrument.iter_mut().zip(prices).enumerate() {
+        let position = book
+          .positions
+          .entry(i.product.symbol.clone())
+          .or_insert_with(|| Position {
+            symbol: i.product.symbol.clone(),
+            ..Default::default()
+          });
+        if matches!(i.order_action, Some(OrderAction::Buy)) {
+          position.quantity += i.quantity;
+          position.total_cost += i.quantity * price;
+        } else {
+          position.total_cost -= position.total_cost / position.quantity * i.quantity;
+          position.quantity -= i.quantity;
+        }
+        if position.quantity <= 0.0 {
+          book.positions.remove(&i.product.symbol);
+        }
+        i.filled_quantity = i.quantity;
+        i.average_execution_price = price;
+        i.estimated_commission = if n == 0 { commission } else { 0.0 };
+      }
+      detail.status = Some(OrderStatus::Executed);
+      detail.executed_time = executed_time;
+      detail.order_value = detail
+        .instrument
+        .iter()
+        .map(|i| i.quantity * i.average_execution_price)
+        .sum();
+      order.total_order_value += detail.order_value;
+      order.total_commission += commission;
+      commission = 0.0;
+      events.push((EventName::OrderExecuted, detail.clone()));
+    }
+    for (name, detail) in events {
+      self.emit(&mut order, name, Some(&detail));
+    }
+    book.orders[index] = order;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::{PaperBroker, Position, Quote, QuoteSource, ReplayQuotes};
+  use crate::options;
+  use crate::orders::{
+    CancelOrderRequest, EventName, Instrument, ListOrdersRequest, OrderAction, OrderDetail, OrderStatus, OrderType,
+    PlaceOrderRequest, PreviewOrderRequest, PreviewOrderResponse, PriceType,
+  };
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::{Memstore, Product};
+
+  const ACCOUNT: &str = "paper";
+
+  fn order(action: OrderAction, symbol: &str, quantity: f64, price_type: PriceType, price: f64) -> PreviewOrderRequest {
+    PreviewOrderRequest {
+      order_type: Some(OrderType::Eq),
+      client_order_id: "1".to_string(),
+      order: vec![OrderDetail {
+        price_type: Some(price_type),
+        limit_price: if matches!(price_type, PriceType::Limit) {
+          price
+        } else {
+          0.0
+        },
+        stop_price: if matches!(price_type, PriceType::Stop) {
+          price
+        } else {
+          0.0
+        },
+        instrument: vec![Instrument {
+          product: Product {
+            symbol: symbol.to_string(),
+            ..Default::default()
+          },
+          order_action: Some(action),
+          quantity,
+          ..Default::default()
+        }],
+        ..Default::default()
+      }],
+    }
+  }
+
+  fn place(preview: &PreviewOrderResponse) -> PlaceOrderRequest {
+    PlaceOrderRequest {
+      order_type: Some(OrderType::Eq),
+      client_order_id: preview.client_order_id.clone(),
+      order: preview.order.clone(),
+      preview_ids: preview.preview_ids.clone(),
+    }
+  }
+
+  async fn submit(
+    broker: &PaperBroker<impl QuoteSource>,
+    request: PreviewOrderRequest,
+  ) -> anyhow::Result<crate::orders::PlaceOrderResponse> {
+    let preview = broker.preview(ACCOUNT, request).await?;
+    broker.place(ACCOUNT, place(&preview)).await
+  }
+
+  #[tokio::test]
+  async fn fills_market_orders() {
+    let quotes = ReplayQuotes::new().with_quotes(
+      "AAPL",
+      [Quote {
+        bid: 99.0,
+        ask: 100.0,
+        last: 99.5,
+      }],
+    );
+    let broker = PaperBroker::new(ACCOUNT, 1_000.0, quotes).with_commission(1.0);
+    let mut events = broker.subscribe();
+
+    let placed = submit(&broker, order(OrderAction::Buy, "AAPL", 5.0, PriceType::Market, 0.0))
+      .await
+      .unwrap();
+    assert!(matches!(placed.order[0].status, Some(OrderStatus::Executed)));
+    assert_eq!(broker.cash(), 499.0);
+    assert_eq!(
+      broker.positions(),
+      vec![Position {
+        symbol: "AAPL".to_string(),
+        quantity: 5.0,
+        total_cost: 500.0,
+      }]
+    );
+    assert!(matches!(events.recv().await.unwrap().name, EventName::OrderPlaced));
+    assert!(matches!(events.recv().await.unwrap().name, EventName::OrderExecuted));
+
+    submit(&broker, order(OrderAction::Sell, "AAPL", 2.0, PriceType::Market, 0.0))
+      .await
+      .unwrap();
+    assert_eq!(broker.cash(), 499.0 + 198.0 - 1.0);
+    assert_eq!(broker.positions()[0].quantity, 3.0);
+    assert_eq!(broker.positions()[0].total_cost, 300.0);
+
+    let balance = broker.balance().await.unwrap();
+    assert_eq!(balance.cash_balance, 696.0);
+    assert_eq!(balance.real_time_values.total_account_value, 696.0 + 3.0 * 99.5);
+  }
+
+  #[tokio::test]
+  async fn charges_the_commission_once_per_order() {
+    let quotes = ReplayQuotes::new()
+      .with_prices("AAPL", [100.0])
+      .with_prices("MSFT", [300.0]);
+    let broker = PaperBroker::new(ACCOUNT, 1_000.0, quotes).with_commission(1.0);
+    let mut request = order(OrderAction::Buy, "AAPL", 2.0, PriceType::Market, 0.0);
+    request
+      .order
+      .extend(order(OrderAction::Buy, "MSFT", 1.0, PriceType::Market, 0.0).order);
+
+    let preview = broker.preview(ACCOUNT, request).await.unwrap();
+    assert_eq!(preview.total_commission, 1.0);
+    assert_eq!(preview.order.iter().map(|d| d.estimated_commission).sum::<f64>(), 1.0);
+    let placed = broker.place(ACCOUNT, place(&preview)).await.unwrap();
+    assert_eq!(placed.total_commission, preview.total_commission);
+    assert_eq!(broker.cash(), 1_000.0 - 500.0 - 1.0);
+  }
+
+  #[tokio::test]
+  async fn fills_limit_and_stop_orders_when_the_price_is_reached() {
+    let quotes = ReplayQuotes::new().with_prices("MSFT", [300.0, 295.0, 289.0]);
+    let broker = PaperBroker::new(ACCOUNT, 10_000.0, quotes).with_positions([Position {
+      symbol: "MSFT".to_string(),
+      quantity: 10.0,
+      total_cost: 2_500.0,
+    }]);
+
+    let limit = submit(&broker, order(OrderAction::Buy, "MSFT", 10.0, PriceType::Limit, 290.0))
+      .await
+      .unwrap();
+    let stop = submit(&broker, order(OrderAction::Sell, "MSFT", 10.0, PriceType::Stop, 296.0))
+      .await
+      .unwrap();
+    assert!(matches!(limit.order[0].status, Some(OrderStatus::Open)));
+    assert_eq!(broker.balance().await.unwrap().cash_buying_power, Some(7_100.0));
+
+    assert!(broker.quotes().advance());
+    broker.tick().await.unwrap();
+    let executed = broker
+      .list(
+        ACCOUNT,
+        ListOrdersRequest {
+          status: Some(OrderStatus::Executed),
+          ..Default::default()
+        },
+      )
+      .await
+      .unwrap();
+    assert_eq!(executed.order.len(), 1);
+    assert_eq!(executed.order[0].order_id, stop.order_id);
+    assert_eq!(broker.cash(), 12_950.0);
+
+    assert!(broker.quotes().advance());
+    broker.tick().await.unwrap();
+    assert!(!broker.quotes().advance());
+    assert_eq!(broker.cash(), 12_950.0 - 2_890.0);
+    assert_eq!(broker.positions()[0].quantity, 10.0);
+  }
+
+  #[tokio::test]
+  async fn checks_buying_power_and_holdings() {
+    let broker = PaperBroker::new(ACCOUNT, 1_000.0, ReplayQuotes::new().with_prices("AAPL", [100.0]));
+    assert!(
+      submit(&broker, order(OrderAction::Buy, "AAPL", 11.0, PriceType::Market, 0.0))
+        .await
+        .is_err()
+    );
+    assert!(
+      submit(&broker, order(OrderAction::Sell, "AAPL", 1.0, PriceType::Market, 0.0))
+        .await
+        .is_err()
+    );
+    assert!(submit(
+      &broker,
+      order(OrderAction::SellShort, "AAPL", 1.0, PriceType::Market, 0.0)
+    )
+    .await
+    .is_err());
+    assert!(broker
+      .preview("other", order(OrderAction::Buy, "AAPL", 1.0, PriceType::Market, 0.0))
+      .await
+      .is_err());
+
+    let open = submit(&broker, order(OrderAction::Buy, "AAPL", 9.0, PriceType::Limit, 90.0))
+      .await
+      .unwrap();
+    assert!(
+      submit(&broker, order(OrderAction::Buy, "AAPL", 3.0, PriceType::Limit, 90.0))
+        .await
+        .is_err(),
+      "open orders hold back buying power"
+    );
+    broker
+      .cancel(
+        ACCOUNT,
+        CancelOrderRequest {
+          order_id: open.order_id,
+        },
+      )
+      .await
+      .unwrap();
+    submit(&broker, order(OrderAction::Buy, "AAPL", 3.0, PriceType::Limit, 90.0))
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn checks_previews_again_when_placing() {
+    let broker =
+      PaperBroker::new(ACCOUNT, 1_000.0, ReplayQuotes::new().with_prices("AAPL", [100.0])).with_positions([Position {
+        symbol: "AAPL".to_string(),
+        quantity: 10.0,
+        total_cost: 900.0,
+      }]);
+    let sell = || order(OrderAction::Sell, "AAPL", 10.0, PriceType::Market, 0.0);
+    let first = broker.preview(ACCOUNT, sell()).await.unwrap();
+    let second = broker.preview(ACCOUNT, sell()).await.unwrap();
+
+    broker.place(ACCOUNT, place(&first)).await.unwrap();
+    let err = broker.place(ACCOUNT, place(&second)).await.unwrap_err();
+    assert!(err.to_string().contains("can't sell 10 AAPL"), "{}", err);
+    assert!(broker.positions().is_empty());
+    assert_eq!(broker.cash(), 2_000.0);
+
+    let buy = || order(OrderAction::Buy, "AAPL", 15.0, PriceType::Market, 0.0);
+    let first = broker.preview(ACCOUNT, buy()).await.unwrap();
+    let second = broker.preview(ACCOUNT, buy()).await.unwrap();
+    broker.place(ACCOUNT, place(&first)).await.unwrap();
+    assert!(broker.place(ACCOUNT, place(&second)).await.is_err());
+    assert_eq!(broker.positions()[0].quantity, 15.0);
+  }
+
+  #[tokio::test]
+  async fn changes_open_orders() {
+    let broker = PaperBroker::new(ACCOUNT, 1_000.0, ReplayQuotes::new().with_prices("AAPL", [100.0]));
+    let open = submit(&broker, order(OrderAction::Buy, "AAPL", 9.0, PriceType::Limit, 90.0))
+      .await
+      .unwrap();
+    let order_id = open.order_id.to_string();
+    let preview = broker
+      .change_preview(
+        ACCOUNT,
+        &order_id,
+        order(OrderAction::Buy, "AAPL", 9.0, PriceType::Limit, 101.0),
+      )
+      .await
+      .unwrap();
+    let other = submit(&broker, order(OrderAction::Buy, "AAPL", 1.0, PriceType::Limit, 90.0))
+      .await
+      .unwrap();
+    let other_id = other.order_id.to_string();
+    let other_preview = broker
+      .change_preview(
+        ACCOUNT,
+        &other_id,
+        order(OrderAction::Buy, "AAPL", 1.0, PriceType::Limit, 95.0),
+      )
+      .await
+      .unwrap();
+    broker
+      .cancel(
+        ACCOUNT,
+        CancelOrderRequest {
+          order_id: other.order_id,
+        },
+      )
+      .await
+      .unwrap();
+    for _ in 0..2 {
+      let error = broker
+        .change_order(ACCOUNT, &other_id, place(&other_preview))
+        .await
+        .unwrap_err();
+      assert_eq!(error.to_string(), format!("order {} is not open", other_id));
+    }
+
+    let changed = broker.change_order(ACCOUNT, &order_id, place(&preview)).await.unwrap();
+    assert!(matches!(changed.order[0].status, Some(OrderStatus::Executed)));
+    assert_eq!(changed.order[0].replaces_order_id, open.order_id as isize);
+    assert_eq!(broker.cash(), 100.0);
+
+    let orders = broker.list(ACCOUNT, ListOrdersRequest::default()).await.unwrap();
+    assert!(matches!(
+      orders.order[0].order_detail[0].status,
+      Some(OrderStatus::Cancelled)
+    ));
+  }
+
+  #[tokio::test]
+  async fn uses_live_quotes() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let market = options::Api::new(Arc::new(server.session(Memstore::new()).await.unwrap()));
+    let broker = PaperBroker::new(ACCOUNT, 1_000.0, market);
+    let placed = submit(&broker, order(OrderAction::Buy, "AAPL", 2.0, PriceType::Market, 0.0))
+      .await
+      .unwrap();
+    assert!((placed.order[0].instrument[0].average_execution_price - 150.01).abs() < 1e-9);
+  }
+}
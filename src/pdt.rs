@@ -0,0 +1,510 @@
+//! Pattern day trader tracking.
+//!
+//! A margin account that makes more than [`DAY_TRADE_LIMIT`] day trades within [`WINDOW`] business days is flagged as
+//! a pattern day trader. A [`PdtTracker`] collects the executions of an account from its executed orders or its
+//! transactions, counts the day trades in the window with the NYSE [`calendar`](crate::calendar) and checks whether a
+//! [`PreviewOrderRequest`] would add one.
+//!
+//! A day trade is a sale of a position bought the same day, or a purchase covering a short sold the same day, counted
+//! once per closing execution. Executions at the same time, like transactions that only carry a date, are counted as
+//! if the openings came first, which can count a day trade too many but never one too few.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{NaiveDate, TimeZone, Utc};
+
+use crate::calendar;
+use crate::clients::{OrdersClient, TransactionsClient};
+use crate::orders::{EventName, ListOrdersRequest, Order, OrderAction, OrderStatus, PreviewOrderRequest};
+use crate::transactions::{ListTransactionsRequest, TransactionDetailsResponse};
+use crate::{Product, SecurityType};
+
+/// The number of day trades allowed within the window.
+pub const DAY_TRADE_LIMIT: usize = 3;
+
+/// The number of business days of the rolling window.
+pub const WINDOW: usize = 5;
+
+/// A fill of an order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+  /// The symbol of an equity or the OSI key of an option.
+  pub security: String,
+  pub action: OrderAction,
+  pub quantity: f64,
+  /// Epoch milliseconds, as sent by E*Trade.
+  pub executed_at: i64,
+}
+
+impl Execution {
+  /// The trading day of the execution, in New York.
+  pub fn date(&self) -> NaiveDate {
+    Utc
+      .timestamp_millis_opt(self.executed_at)
+      .single()
+      .unwrap_or_default()
+      .with_timezone(&chrono_tz::US::Eastern)
+      .date_naive()
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DayTrade {
+  pub date: NaiveDate,
+  pub security: String,
+}
+
+/// The day trades of the rolling window ending on a day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdtStatus {
+  /// The business days of the window, oldest first.
+  pub window: Vec<NaiveDate>,
+  pub day_trades: Vec<DayTrade>,
+}
+
+impl PdtStatus {
+  pub fn count(&self) -> usize {
+    self.day_trades.len()
+  }
+
+  /// The day trades left before the account gets flagged.
+  pub fn remaining(&self) -> usize {
+    DAY_TRADE_LIMIT.saturating_sub(self.count())
+  }
+
+  pub fn is_pattern_day_trader(&self) -> bool {
+    self.count() > DAY_TRADE_LIMIT
+  }
+}
+
+/// What placing a previewed order would do to the day trade count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewCheck {
+  /// The securities the order would day trade.
+  pub day_trades: Vec<String>,
+  /// The number of day trades in the window once the order is filled.
+  pub count_after: usize,
+}
+
+impl PreviewCheck {
+  pub fn creates_day_trade(&self) -> bool {
+    !self.day_trades.is_empty()
+  }
+
+  /// Whether the order would get the account flagged as a pattern day trader.
+  pub fn exceeds_limit(&self) -> bool {
+    self.creates_day_trade() && self.count_after > DAY_TRADE_LIMIT
+  }
+}
+
+/// The day trades of one account.
+#[derive(Debug, Clone, Default)]
+pub struct PdtTracker {
+  executions: Vec<Execution>,
+}
+
+impl PdtTracker {
+  pub fn new(executions: impl IntoIterator<Item = Execution>) -> Self {
+    let mut executions: Vec<Execution> = executions.into_iter().collect();
+    executions.sort_by_key(|e| (e.executed_at, !opens(e.action)));
+    Self { executions }
+  }
+
+  /// Collects the executed orders of the window ending today.
+  pub async fn from_orders(orders: &dyn OrdersClient, account_id_key: &str, today: NaiveDate) -> Result<Self> {
+    let window = calendar::trading_days_until(today, WINDOW);
+    let mut marker: Option<String> = None;
+    let mut listed = vec![];
+    loop {
+      let page = orders
+        .list(
+          account_id_key,
+          ListOrdersRequest {
+            marker: marker.clone(),
+            status: Some(OrderStatus::Executed),
+            from_date: Some(window[0].format("%m%d%Y").to_string()),
+            to_date: Some(today.format("%m%d%Y").to_string()),
+            ..Default::default()
+          },
+        )
+        .await?;
+      listed.extend(page.order);
+      if page.marker.is_empty() || marker.as_deref() == Some(page.marker.as_str()) {
+        break;
+      }
+      marker = Some(page.marker);
+    }
+    Ok(Self::new(executions_from_orders(&listed)))
+  }
+
+  /// Collects the trades of the transaction history of the window ending today.
+  pub async fn from_transactions(
+    transactions: &dyn TransactionsClient,
+    account_id_key: &str,
+    today: NaiveDate,
+  ) -> Result<Self> {
+    let window = calendar::trading_days_until(today, WINDOW);
+    let (start_date, end_date) = (
+      window[0].format("%m%d%Y").to_string(),
+      today.format("%m%d%Y").to_string(),
+    );
+    let listed = transactions
+      .list_all(
+        account_id_key,
+        ListTransactionsRequest {
+          start_date: Some(&start_date),
+          end_date: Some(&end_date),
+          ..Default::default()
+        },
+      )
+      .await?;
+    Ok(Self::new(executions_from_transactions(&listed)))
+  }
+
+  pub fn executions(&self) -> &[Execution] {
+    &self.executions
+  }
+
+  /// The day trades of the window ending on the given day.
+  pub fn status(&self, today: NaiveDate) -> PdtStatus {
+    let window = calendar::trading_days_until(today, WINDOW);
+    let day_trades = day_trades(&self.executions)
+      .into_iter()
+      .filter(|t| window.contains(&t.date))
+      .collect();
+    PdtStatus { window, day_trades }
+  }
+
+  /// Checks whether filling the order today would make day trades, and logs a warning when it would exceed the limit.
+  pub fn check_preview(&self, request: &PreviewOrderRequest, today: NaiveDate) -> PreviewCheck {
+    let mut open = opened_today(&self.executions, today);
+    let mut trades = vec![];
+    for instrument in request.order.iter().flat_map(|d| &d.instrument) {
+      let Some(action) = instrument.order_action else {
+        continue;
+      };
+      let security = security(&instrument.product, &instrument.osi_key);
+      if close(&mut open, &security, action, instrument.quantity) {
+        trades.push(security);
+      }
+    }
+
+    let check = PreviewCheck {
+      count_after: self.status(today).count() + trades.len(),
+      day_trades: trades,
+    };
+    if check.exceeds_limit() {
+      warn!(
+        "the order would make day trade {} within {} business days, day trading {}",
+        check.count_after,
+        WINDOW,
+        check.day_trades.join(", ")
+      );
+    }
+    check
+  }
+}
+
+fn opens(action: OrderAction) -> bool {
+  matches!(
+    action,
+    OrderAction::Buy | OrderAction::BuyOpen | OrderAction::SellShort | OrderAction::SellOpen
+  )
+}
+
+/// The quantities opened today and not closed yet, per security, long and short.
+type Open = HashMap<String, (f64, f64)>;
+
+fn opened_today(executions: &[Execution], today: NaiveDate) -> Open {
+  let mut open = Open::new();
+  for execution in executions.iter().filter(|e| e.date() == today) {
+    if !close(&mut open, &execution.security, execution.action, execution.quantity) {
+      open_position(&mut open, execution);
+    }
+  }
+  open
+}
+
+fn open_position(open: &mut Open, execution: &Execution) {
+  let entry = open.entry(execution.security.clone()).or_default();
+  match execution.action {
+    OrderAction::Buy | OrderAction::BuyOpen => entry.0 += execution.quantity,
+    OrderAction::SellShort | OrderAction::SellOpen => entry.1 += execution.quantity,
+    _ => {}
+  }
+}
+
+/// Closes what was opened the same day, returns whether that is a day trade.
+fn close(open: &mut Open, security: &str, action: OrderAction, quantity: f64) -> bool {
+  let Some(entry) = open.get_mut(security) else {
+    return false;
+  };
+  let opened = match action {
+    OrderAction::Sell | OrderAction::SellClose => &mut entry.0,
+    OrderAction::BuyToCover | OrderAction::BuyClose => &mut entry.1,
+    _ => return false,
+  };
+  if *opened <= 0.0 {
+    return false;
+  }
+  *opened = (*opened - quantity).max(0.0);
+  true
+}
+
+/// The day trades of the executions, which need to be ordered by time.
+pub fn day_trades(executions: &[Execution]) -> Vec<DayTrade> {
+  let mut trades = vec![];
+  let mut day: Option<NaiveDate> = None;
+  let mut open = Open::new();
+  for execution in executions {
+    let date = execution.date();
+    if day != Some(date) {
+      day = Some(date);
+      open.clear();
+    }
+    if close(&mut open, &execution.security, execution.action, execution.quantity) {
+      trades.push(DayTrade {
+        date,
+        security: execution.security.clone(),
+      });
+    } else {
+      open_position(&mut open, execution);
+    }
+  }
+  trades
+}
+
+fn security(product: &Product, osi_key: &str) -> String {
+  if !osi_key.is_empty() {
+    return osi_key.to_string();
+  }
+  match product.security_type {
+    Some(SecurityType::Optn) => format!(
+      "{}-{:04}{:02}{:02}-{}-{}",
+      product.symbol,
+      product.expiry_year,
+      product.expiry_month,
+      product.expiry_day,
+      product.call_put,
+      product.strike_price
+    ),
+    _ => product.symbol.clone(),
+  }
+}
+
+/// The fills of the `ORDER_EXECUTED` events, or of the executed order details when the events have no instruments.
+pub fn executions_from_orders(orders: &[Order]) -> Vec<Execution> {
+  let mut executions = vec![];
+  for order in orders {
+    let events: Vec<_> = order
+      .events
+      .event
+      .iter()
+      .filter(|e| matches!(e.name, EventName::OrderExecuted) && !e.instrument.is_empty())
+      .collect();
+    if !events.is_empty() {
+      for event in events {
+        for instrument in &event.instrument {
+          let quantity = if instrument.filled_quantity > 0.0 {
+            instrument.filled_quantity
+          } else {
+            instrument.quantity
+          };
+          if let Some(action) = instrument.order_action {
+            executions.push(Execution {
+              security: security(&instrument.product, &instrument.osi_key),
+              action,
+              quantity,
+              executed_at: event.date_time,
+            });
+          }
+        }
+      }
+      continue;
+    }
+    for detail in order.order_detail.iter().filter(|d| d.executed_time > 0) {
+      for instrument in detail.instrument.iter().filter(|i| i.filled_quantity > 0.0) {
+        if let Some(action) = instrument.order_action {
+          executions.push(Execution {
+            security: security(&instrument.product, &instrument.osi_key),
+            action,
+            quantity: instrument.filled_quantity,
+            executed_at: detail.executed_time,
+          });
+        }
+      }
+    }
+  }
+  executions
+}
+
+/// The trades of the transaction history, other transactions are left out.
+pub fn executions_from_transactions(transactions: &[TransactionDetailsResponse]) -> Vec<Execution> {
+  transactions
+    .iter()
+    .filter_map(|t| {
+      let brokerage = t.brokerage.as_ref()?;
+      let action = match brokerage.transaction_type.to_ascii_lowercase().as_str() {
+        "bought" | "buy" => OrderAction::Buy,
+        "sold" | "sell" => OrderAction::Sell,
+        "sold short" | "sell short" => OrderAction::SellShort,
+        "bought to cover" | "buy to cover" => OrderAction::BuyToCover,
+        "bought to open" | "buy open" => OrderAction::BuyOpen,
+        "sold to close" | "sell close" => OrderAction::SellClose,
+        "sold to open" | "sell open" => OrderAction::SellOpen,
+        "bought to close" | "buy close" => OrderAction::BuyClose,
+        _ => return None,
+      };
+      Some(Execution {
+        security: security(&brokerage.product, ""),
+        action,
+        quantity: brokerage.quantity.abs(),
+        executed_at: t.tranaction_date,
+      })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{NaiveDate, TimeZone};
+
+  use super::{Execution, PdtTracker};
+  use crate::orders::{
+    Event, EventName, Events, Instrument, Order, OrderAction, OrderDetail, OrdersResponse, PreviewOrderRequest,
+  };
+  use crate::testing::MockOrders;
+  use crate::transactions::{Brokerage, TransactionDetailsResponse};
+  use crate::Product;
+
+  fn at(day: u32, hour: u32) -> i64 {
+    chrono_tz::US::Eastern
+      .with_ymd_and_hms(2024, 7, day, hour, 0, 0)
+      .unwrap()
+      .timestamp_millis()
+  }
+
+  fn execution(security: &str, action: OrderAction, day: u32, hour: u32) -> Execution {
+    Execution {
+      security: security.to_string(),
+      action,
+      quantity: 10.0,
+      executed_at: at(day, hour),
+    }
+  }
+
+  fn instrument(symbol: &str, action: OrderAction) -> Instrument {
+    Instrument {
+      product: Product {
+        symbol: symbol.to_string(),
+        ..Default::default()
+      },
+      order_action: Some(action),
+      quantity: 10.0,
+      ..Default::default()
+    }
+  }
+
+  fn preview(symbol: &str, action: OrderAction) -> PreviewOrderRequest {
+    PreviewOrderRequest {
+      order: vec![OrderDetail {
+        instrument: vec![instrument(symbol, action)],
+        ..Default::default()
+      }],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn counts_day_trades_in_the_window() {
+    use OrderAction::*;
+    let tracker = PdtTracker::new(vec![
+      // outside the window ending on the 10th: the 2nd, 3rd, 5th, 8th, 9th and 10th are business days
+      execution("AAPL", Buy, 1, 10),
+      execution("AAPL", Sell, 1, 11),
+      execution("AAPL", Buy, 3, 10),
+      execution("AAPL", Sell, 3, 11),
+      execution("AAPL", Sell, 3, 12),
+      // sold before buying again, not a day trade
+      execution("MSFT", Sell, 5, 10),
+      execution("MSFT", Buy, 5, 11),
+      execution("TSLA", SellShort, 8, 10),
+      execution("TSLA", BuyToCover, 8, 11),
+      execution("MSFT", Buy, 9, 10),
+      execution("MSFT", Sell, 9, 11),
+      execution("NVDA", Buy, 10, 10),
+    ]);
+
+    let status = tracker.status(NaiveDate::from_ymd_opt(2024, 7, 10).unwrap());
+    assert_eq!(status.window[0], NaiveDate::from_ymd_opt(2024, 7, 3).unwrap());
+    assert_eq!(status.count(), 3);
+    assert_eq!(status.remaining(), 0);
+    assert!(!status.is_pattern_day_trader());
+
+    let today = NaiveDate::from_ymd_opt(2024, 7, 10).unwrap();
+    let check = tracker.check_preview(&preview("NVDA", Sell), today);
+    assert_eq!(check.day_trades, vec!["NVDA"]);
+    assert_eq!(check.count_after, 4);
+    assert!(check.exceeds_limit());
+    assert!(!tracker.check_preview(&preview("MSFT", Sell), today).creates_day_trade());
+  }
+
+  #[test]
+  fn reads_trades_from_transactions() {
+    let transaction = |tpe: &str, quantity: f64, hour| TransactionDetailsResponse {
+      tranaction_date: at(9, hour),
+      brokerage: Some(Brokerage {
+        transaction_type: tpe.to_string(),
+        product: Product {
+          symbol: "AAPL".to_string(),
+          ..Default::default()
+        },
+        quantity,
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+    let executions = super::executions_from_transactions(&[
+      transaction("Sold", -10.0, 0),
+      transaction("Bought", 10.0, 0),
+      transaction("Dividend", 0.0, 0),
+    ]);
+    assert_eq!(executions.len(), 2);
+    let status = PdtTracker::new(executions).status(NaiveDate::from_ymd_opt(2024, 7, 9).unwrap());
+    assert_eq!(status.count(), 1);
+  }
+
+  #[tokio::test]
+  async fn reads_executed_orders() {
+    let order = |id, action, hour| Order {
+      order_id: id,
+      events: Events {
+        event: vec![Event {
+          name: EventName::OrderExecuted,
+          date_time: at(9, hour),
+          instrument: vec![instrument("AAPL", action)],
+          ..Default::default()
+        }],
+      },
+      ..Default::default()
+    };
+    let orders = MockOrders::new()
+      .on_list(Ok(OrdersResponse {
+        marker: "next".to_string(),
+        order: vec![order(1, OrderAction::Buy, 10)],
+        ..Default::default()
+      }))
+      .on_list(Ok(OrdersResponse {
+        order: vec![order(2, OrderAction::Sell, 11)],
+        ..Default::default()
+      }));
+
+    let today = NaiveDate::from_ymd_opt(2024, 7, 9).unwrap();
+    let tracker = PdtTracker::from_orders(&orders, "key", today).await.unwrap();
+    assert_eq!(tracker.status(today).count(), 1);
+    let calls = orders.calls();
+    assert_eq!(calls[0].args["params"]["fromDate"], "07022024");
+    assert_eq!(calls[1].args["params"]["marker"], "next");
+  }
+}
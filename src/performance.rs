@@ -0,0 +1,446 @@
+//! Performance of the accounts over a range of dates.
+//!
+//! The value of an account comes from its balance [`Snapshot`]s and the money moved in and out of it from its
+//! transactions. [`analyze`] measures the time-weighted return, which takes the deposits and withdrawals out and
+//! compares with a benchmark, and the money-weighted return, the annualized internal rate of return of the money put
+//! in. It also sums the dividends, interest and fees and finds the drawdowns of the time-weighted value. [`aggregate`]
+//! combines accounts to measure them together.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use chrono::{NaiveDate, TimeZone, Utc};
+
+use crate::clients::TransactionsClient;
+use crate::history::{daily_series, Metric, Snapshot, SnapshotSink};
+use crate::transactions::{ListTransactionsRequest, TransactionDetailsResponse};
+
+/// What a [`CashFlow`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CashFlowKind {
+  Deposit,
+  Withdrawal,
+  Dividend,
+  Interest,
+  Fee,
+}
+
+impl CashFlowKind {
+  /// Deposits and withdrawals move money in and out of the account, the other flows are part of its return.
+  pub fn is_external(&self) -> bool {
+    matches!(self, CashFlowKind::Deposit | CashFlowKind::Withdrawal)
+  }
+}
+
+/// Money in or out of an account, positive when it comes in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CashFlow {
+  pub date: NaiveDate,
+  pub kind: CashFlowKind,
+  pub amount: f64,
+}
+
+/// The cash flows of the transactions, trades and other transactions are left out.
+pub fn cash_flows(transactions: &[TransactionDetailsResponse]) -> Vec<CashFlow> {
+  let mut flows: Vec<CashFlow> = transactions
+    .iter()
+    .filter(|t| t.amount != 0.0)
+    .filter_map(|t| {
+      let tpe = t
+        .brokerage
+        .as_ref()
+        .map(|b| b.transaction_type.to_ascii_lowercase())
+        .unwrap_or_default();
+      let kind = if tpe.contains("dividend") && !tpe.contains("reinvest") {
+        CashFlowKind::Dividend
+      } else if tpe.contains("interest") {
+        if t.amount > 0.0 {
+          CashFlowKind::Interest
+        } else {
+          CashFlowKind::Fee
+        }
+      } else if tpe.contains("fee") || tpe.contains("commission") {
+        CashFlowKind::Fee
+      } else if tpe.contains("deposit") || tpe.contains("contribution") {
+        CashFlowKind::Deposit
+      } else if tpe.contains("withdrawal") || tpe.contains("distribution") {
+        CashFlowKind::Withdrawal
+      } else if tpe.contains("transfer") {
+        if t.amount > 0.0 {
+          CashFlowKind::Deposit
+        } else {
+          CashFlowKind::Withdrawal
+        }
+      } else {
+        return None;
+      };
+      Some(CashFlow {
+        date: date(t.tranaction_date),
+        kind,
+        amount: t.amount,
+      })
+    })
+    .collect();
+  flows.sort_by_key(|f| f.date);
+  flows
+}
+
+fn date(millis: i64) -> NaiveDate {
+  Utc
+    .timestamp_millis_opt(millis)
+    .single()
+    .unwrap_or_default()
+    .with_timezone(&chrono_tz::US::Eastern)
+    .date_naive()
+}
+
+/// The daily values and the cash flows of an account, or of accounts put together with [`aggregate`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountHistory {
+  /// The net account value at the end of each day, ordered by date.
+  pub values: Vec<(NaiveDate, f64)>,
+  pub cash_flows: Vec<CashFlow>,
+}
+
+impl AccountHistory {
+  pub fn new(snapshots: &[Snapshot], cash_flows: Vec<CashFlow>) -> Self {
+    Self {
+      values: daily_series(snapshots, Metric::NetAccountValue),
+      cash_flows,
+    }
+  }
+
+  /// Loads the snapshots of the sink and the cash flows of the transactions between the dates.
+  pub async fn load(
+    sink: &dyn SnapshotSink,
+    transactions: &dyn TransactionsClient,
+    account_id_key: &str,
+    start: NaiveDate,
+    end: NaiveDate,
+  ) -> Result<Self> {
+    let snapshots = sink.load(account_id_key).await?;
+    let (start_date, end_date) = (start.format("%m%d%Y").to_string(), end.format("%m%d%Y").to_string());
+    let listed = transactions
+      .list_all(
+        account_id_key,
+        ListTransactionsRequest {
+          start_date: Some(&start_date),
+          end_date: Some(&end_date),
+          ..Default::default()
+        },
+      )
+      .await?;
+    Ok(Self::new(&snapshots, cash_flows(&listed)))
+  }
+}
+
+/// Puts accounts together, from the first day every account has a value.
+pub fn aggregate(accounts: &[AccountHistory]) -> AccountHistory {
+  let Some(first) = accounts.iter().map(|a| a.values.first().map(|v| v.0)).max().flatten() else {
+    return AccountHistory::default();
+  };
+  let dates: Vec<NaiveDate> = accounts
+    .iter()
+    .flat_map(|a| a.values.iter().map(|v| v.0))
+    .filter(|d| *d >= first)
+    .collect::<BTreeSet<_>>()
+    .into_iter()
+    .collect();
+
+  let mut totals: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+  for account in accounts {
+    // days an account has no value carry its last value forward
+    let mut values = account.values.iter().peekable();
+    let mut last = 0.0;
+    for date in &dates {
+      while let Some((_, value)) = values.next_if(|v| v.0 <= *date) {
+        last = *value;
+      }
+      *totals.entry(*date).or_default() += last;
+    }
+  }
+
+  let mut cash_flows: Vec<CashFlow> = accounts.iter().flat_map(|a| a.cash_flows.iter().cloned()).collect();
+  cash_flows.sort_by_key(|f| f.date);
+  AccountHistory {
+    values: totals.into_iter().collect(),
+    cash_flows,
+  }
+}
+
+/// A fall of the time-weighted value from a peak.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drawdown {
+  pub peak: NaiveDate,
+  pub trough: NaiveDate,
+  /// The day the value is back at the peak, while it isn't the drawdown is ongoing.
+  pub recovered: Option<NaiveDate>,
+  /// The fall as a fraction of the peak.
+  pub depth: f64,
+}
+
+/// The performance of an account between two dates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Performance {
+  /// The first day with a value in the range, its value is the starting point.
+  pub start: NaiveDate,
+  pub end: NaiveDate,
+  pub start_value: f64,
+  pub end_value: f64,
+  pub deposits: f64,
+  /// The money taken out, as a positive amount.
+  pub withdrawals: f64,
+  pub dividends: f64,
+  pub interest: f64,
+  /// The fees and margin interest paid, as a positive amount.
+  pub fees: f64,
+  /// The compounded return of the days, as a fraction.
+  pub time_weighted_return: f64,
+  /// The annualized internal rate of return, when there is one.
+  pub money_weighted_return: Option<f64>,
+  /// The drawdowns of the time-weighted value, in order.
+  pub drawdowns: Vec<Drawdown>,
+}
+
+impl Performance {
+  pub fn net_contributions(&self) -> f64 {
+    self.deposits - self.withdrawals
+  }
+
+  /// What the account made, after taking out the money put in.
+  pub fn gain(&self) -> f64 {
+    self.end_value - self.start_value - self.net_contributions()
+  }
+
+  pub fn max_drawdown(&self) -> Option<&Drawdown> {
+    self.drawdowns.iter().max_by(|a, b| a.depth.total_cmp(&b.depth))
+  }
+}
+
+/// Measures the performance between the dates, the cash flows of the first day are part of its value. Returns
+/// `None` when the range has less than two days with a value.
+pub fn analyze(history: &AccountHistory, start: NaiveDate, end: NaiveDate) -> Option<Performance> {
+  let values: Vec<(NaiveDate, f64)> = history
+    .values
+    .iter()
+    .copied()
+    .filter(|(d, _)| *d >= start && *d <= end)
+    .collect();
+  let (first, last) = (*values.first()?, *values.last()?);
+  if values.len() < 2 {
+    return None;
+  }
+  let flows: Vec<&CashFlow> = history
+    .cash_flows
+    .iter()
+    .filter(|f| f.date > first.0 && f.date <= last.0)
+    .collect();
+  let total = |kind| flows.iter().filter(|f| f.kind == kind).map(|f| f.amount).sum::<f64>();
+
+  // the growth of one dollar, with the deposits and withdrawals of a day made at its end
+  let mut index = vec![(first.0, 1.0)];
+  for window in values.windows(2) {
+    let ((from, before), (to, after)) = (window[0], window[1]);
+    let external: f64 = flows
+      .iter()
+      .filter(|f| f.kind.is_external() && f.date > from && f.date <= to)
+      .map(|f| f.amount)
+      .sum();
+    let growth = if before > 0.0 { (after - external) / before } else { 1.0 };
+    index.push((to, index.last().unwrap().1 * growth));
+  }
+
+  let mut xirr_flows = vec![(first.0, -first.1)];
+  xirr_flows.extend(
+    flows
+      .iter()
+      .filter(|f| f.kind.is_external())
+      .map(|f| (f.date, -f.amount)),
+  );
+  xirr_flows.push((last.0, last.1));
+
+  Some(Performance {
+    start: first.0,
+    end: last.0,
+    start_value: first.1,
+    end_value: last.1,
+    deposits: total(CashFlowKind::Deposit),
+    withdrawals: -total(CashFlowKind::Withdrawal),
+    dividends: total(CashFlowKind::Dividend),
+    interest: total(CashFlowKind::Interest),
+    fees: -total(CashFlowKind::Fee),
+    time_weighted_return: index.last().unwrap().1 - 1.0,
+    money_weighted_return: xirr(&xirr_flows),
+    drawdowns: drawdowns(&index),
+  })
+}
+
+/// The drawdowns of a series of values, ordered by date.
+pub fn drawdowns(values: &[(NaiveDate, f64)]) -> Vec<Drawdown> {
+  let mut drawdowns = vec![];
+  let mut peak: Option<(NaiveDate, f64)> = None;
+  let mut current: Option<Drawdown> = None;
+  for &(date, value) in values {
+    match peak {
+      Some((_, high)) if value < high => {
+        let depth = (high - value) / high;
+        let drawdown = current.get_or_insert(Drawdown {
+          peak: peak.unwrap().0,
+          trough: date,
+          recovered: None,
+          depth,
+        });
+        if depth > drawdown.depth {
+          drawdown.trough = date;
+          drawdown.depth = depth;
+        }
+      }
+      _ => {
+        if let Some(mut drawdown) = current.take() {
+          drawdown.recovered = Some(date);
+          drawdowns.push(drawdown);
+        }
+        peak = Some((date, value));
+      }
+    }
+  }
+  drawdowns.extend(current);
+  drawdowns
+}
+
+/// The annualized rate at which the flows, negative when paid and positive when received, are worth nothing on the
+/// first date. Returns `None` when the flows don't change sign.
+pub fn xirr(flows: &[(NaiveDate, f64)]) -> Option<f64> {
+  let first = flows.iter().map(|f| f.0).min()?;
+  let npv = |rate: f64| {
+    flows
+      .iter()
+      .map(|(date, amount)| amount / (1.0 + rate).powf((*date - first).num_days() as f64 / 365.0))
+      .sum::<f64>()
+  };
+
+  let (mut low, mut high) = (-0.999_999, 1.0);
+  while npv(high) > 0.0 {
+    if high > 1e9 {
+      return None;
+    }
+    high *= 2.0;
+  }
+  if npv(low) < 0.0 {
+    return None;
+  }
+  for _ in 0..200 {
+    let mid = (low + high) / 2.0;
+    if npv(mid) > 0.0 {
+      low = mid;
+    } else {
+      high = mid;
+    }
+  }
+  Some((low + high) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+
+  use super::{aggregate, analyze, cash_flows, xirr, AccountHistory, CashFlow, CashFlowKind};
+  use crate::transactions::{Brokerage, TransactionDetailsResponse};
+
+  fn date(month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+  }
+
+  #[test]
+  fn measures_returns_and_drawdowns() {
+    let history = AccountHistory {
+      values: vec![
+        (date(7, 1), 1000.0),
+        (date(7, 2), 1100.0),
+        (date(7, 3), 990.0),
+        (date(7, 5), 1210.0),
+      ],
+      cash_flows: vec![
+        CashFlow {
+          date: date(7, 2),
+          kind: CashFlowKind::Deposit,
+          amount: 50.0,
+        },
+        CashFlow {
+          date: date(7, 3),
+          kind: CashFlowKind::Dividend,
+          amount: 5.0,
+        },
+        CashFlow {
+          date: date(7, 3),
+          kind: CashFlowKind::Fee,
+          amount: -2.0,
+        },
+      ],
+    };
+
+    let performance = analyze(&history, date(7, 1), date(7, 31)).unwrap();
+    assert_eq!(performance.net_contributions(), 50.0);
+    assert_eq!(performance.gain(), 160.0);
+    assert_eq!((performance.dividends, performance.fees), (5.0, 2.0));
+    // 1050 / 1000 * 990 / 1100 * 1210 / 990
+    assert!((performance.time_weighted_return - 0.155).abs() < 1e-9);
+    assert!(performance.money_weighted_return.unwrap() > 0.155);
+
+    let drawdown = performance.max_drawdown().unwrap();
+    assert_eq!((drawdown.peak, drawdown.trough), (date(7, 2), date(7, 3)));
+    assert_eq!(drawdown.recovered, Some(date(7, 5)));
+    assert!((drawdown.depth - 0.1).abs() < 1e-9);
+
+    let partial = analyze(&history, date(7, 3), date(7, 5)).unwrap();
+    assert_eq!(partial.deposits, 0.0);
+    assert!((partial.time_weighted_return - 1210.0 / 990.0 + 1.0).abs() < 1e-9);
+    assert!(analyze(&history, date(7, 5), date(7, 31)).is_none());
+
+    let year = xirr(&[
+      (NaiveDate::from_ymd_opt(2023, 1, 1).unwrap(), -1000.0),
+      (NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), 1100.0),
+    ]);
+    assert!((year.unwrap() - 0.1).abs() < 1e-6);
+  }
+
+  #[test]
+  fn aggregates_accounts_and_classifies_transactions() {
+    let transaction = |tpe: &str, amount: f64| TransactionDetailsResponse {
+      tranaction_date: 1720094400000, // 2024-07-04 08:00 in New York
+      amount,
+      brokerage: Some(Brokerage {
+        transaction_type: tpe.to_string(),
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+    let flows = cash_flows(&[
+      transaction("Bought", -500.0),
+      transaction("Transfer", -100.0),
+      transaction("Dividend", 3.0),
+      transaction("Margin Interest", -1.0),
+      transaction("Deposit", 200.0),
+    ]);
+    let kinds: Vec<_> = flows.iter().map(|f| f.kind).collect();
+    use CashFlowKind::*;
+    assert_eq!(kinds, vec![Withdrawal, Dividend, Fee, Deposit]);
+    assert_eq!(flows[0].date, date(7, 4));
+
+    let total = aggregate(&[
+      AccountHistory {
+        values: vec![(date(7, 1), 100.0), (date(7, 2), 110.0), (date(7, 3), 120.0)],
+        cash_flows: flows,
+      },
+      AccountHistory {
+        values: vec![(date(7, 2), 50.0), (date(7, 5), 60.0)],
+        cash_flows: vec![],
+      },
+    ]);
+    assert_eq!(
+      total.values,
+      vec![(date(7, 2), 160.0), (date(7, 3), 170.0), (date(7, 5), 180.0)]
+    );
+    assert_eq!(total.cash_flows.len(), 4);
+  }
+}
@@ -0,0 +1,543 @@
+//! Rebalancing the positions of accounts to target weights.
+//!
+//! [`Targets`] gives a weight to asset classes, a class is a group of symbols or a single symbol. A [`Planner`] takes
+//! the positions and the cash of accounts, and [`Planner::plan`] works out the drift of every class and the trades
+//! that bring it back to its target: sales first, then purchases with the cash that is there. Trades smaller than the
+//! minimum trade size are left out. Sales prefer the lots held long term and, among those, the ones with the smallest
+//! gain. The trades of a [`Plan`] can be turned into market orders with [`Plan::preview_orders`] for review.
+//!
+//! Positions in symbols without a target are left out of the plan and of the value it splits.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+
+use crate::accounts::{BalanceRequest, PortfolioPosition, PortfolioRequest, PositionLot};
+use crate::clients::AccountsClient;
+use crate::orders::{Instrument, OrderAction, OrderDetail, OrderTerm, OrderType, PreviewOrderRequest, PriceType};
+use crate::paper::QuoteSource;
+use crate::{Product, SecurityType};
+
+/// The weights of the asset classes, as fractions of the value of the accounts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Targets {
+  classes: Vec<(String, f64, Vec<String>)>,
+}
+
+impl Targets {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// A class of its own for the symbol.
+  pub fn symbol(self, symbol: impl Into<String>, weight: f64) -> Self {
+    let symbol = symbol.into();
+    self.class(symbol.clone(), weight, [symbol])
+  }
+
+  /// A class of symbols, purchases for the class buy the first one.
+  pub fn class<S: Into<String>>(
+    mut self,
+    name: impl Into<String>,
+    weight: f64,
+    symbols: impl IntoIterator<Item = S>,
+  ) -> Self {
+    self
+      .classes
+      .push((name.into(), weight, symbols.into_iter().map(Into::into).collect()));
+    self
+  }
+
+  /// The class the symbol belongs to.
+  pub fn class_of(&self, symbol: &str) -> Option<&str> {
+    self
+      .classes
+      .iter()
+      .find(|(_, _, symbols)| symbols.iter().any(|s| s == symbol))
+      .map(|(name, _, _)| name.as_str())
+  }
+
+  pub fn weight(&self, class: &str) -> Option<f64> {
+    self.classes.iter().find(|(name, ..)| name == class).map(|(_, w, _)| *w)
+  }
+
+  fn symbols(&self) -> impl Iterator<Item = &String> {
+    self.classes.iter().flat_map(|(_, _, symbols)| symbols)
+  }
+}
+
+/// A long equity position of an account.
+#[derive(Debug, Clone, Default)]
+pub struct Holding {
+  pub account_id_key: String,
+  pub symbol: String,
+  pub quantity: f64,
+  pub price: f64,
+  /// The tax lots of the position, a position without lots is sold as if it was held short term.
+  pub lots: Vec<PositionLot>,
+}
+
+impl Holding {
+  pub fn market_value(&self) -> f64 {
+    self.quantity * self.price
+  }
+}
+
+/// The sale of part of a tax lot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotSale {
+  /// The `position_log_id` of the lot, 0 for a position without lots.
+  pub lot_id: i64,
+  pub quantity: f64,
+  pub long_term: bool,
+  /// The estimated gain, negative for a loss.
+  pub gain: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+  pub account_id_key: String,
+  pub symbol: String,
+  pub action: OrderAction,
+  pub quantity: f64,
+  /// The price the trade is estimated at.
+  pub price: f64,
+  /// The lots sold, empty for purchases.
+  pub lots: Vec<LotSale>,
+}
+
+impl Trade {
+  pub fn value(&self) -> f64 {
+    self.quantity * self.price
+  }
+}
+
+/// How far a class is from its target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+  pub class: String,
+  pub target_weight: f64,
+  pub weight: f64,
+  pub value: f64,
+  pub target_value: f64,
+}
+
+impl Drift {
+  /// The weight above the target, negative when under it.
+  pub fn drift(&self) -> f64 {
+    self.weight - self.target_weight
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+  /// The value of the targeted positions and the cash.
+  pub total_value: f64,
+  pub classes: Vec<Drift>,
+  /// The sales, then the purchases.
+  pub trades: Vec<Trade>,
+  /// The cash of every account once the trades are done.
+  pub cash: BTreeMap<String, f64>,
+}
+
+impl Plan {
+  /// A market order for the day per trade, with the account to preview it in.
+  pub fn preview_orders(&self) -> Vec<(String, PreviewOrderRequest)> {
+    let prefix = Utc::now().timestamp();
+    self
+      .trades
+      .iter()
+      .enumerate()
+      .map(|(i, trade)| {
+        let request = PreviewOrderRequest {
+          order_type: Some(OrderType::Eq),
+          client_order_id: format!("{:x}{:03}", prefix, i),
+          order: vec![OrderDetail {
+            order_term: Some(OrderTerm::GoodForDay),
+            price_type: Some(PriceType::Market),
+            instrument: vec![Instrument {
+              product: Product {
+                symbol: trade.symbol.clone(),
+                security_type: Some(SecurityType::Eq),
+                ..Default::default()
+              },
+              order_action: Some(trade.action),
+              quantity: trade.quantity,
+              ..Default::default()
+            }],
+            ..Default::default()
+          }],
+        };
+        (trade.account_id_key.clone(), request)
+      })
+      .collect()
+  }
+}
+
+/// Gathers the positions, cash and prices to plan with.
+#[derive(Debug, Clone)]
+pub struct Planner {
+  targets: Targets,
+  holdings: Vec<Holding>,
+  cash: BTreeMap<String, f64>,
+  prices: HashMap<String, f64>,
+  min_trade: f64,
+  fractional: bool,
+}
+
+impl Planner {
+  pub fn new(targets: Targets) -> Self {
+    Self {
+      targets,
+      holdings: vec![],
+      cash: BTreeMap::new(),
+      prices: HashMap::new(),
+      min_trade: 0.0,
+      fractional: false,
+    }
+  }
+
+  pub fn holding(mut self, holding: Holding) -> Self {
+    self.prices.entry(holding.symbol.clone()).or_insert(holding.price);
+    self.holdings.push(holding);
+    self
+  }
+
+  /// Adds the long equity positions, other positions are left out.
+  pub fn positions<'a>(
+    mut self,
+    account_id_key: &str,
+    positions: impl IntoIterator<Item = &'a PortfolioPosition>,
+  ) -> Self {
+    for position in positions {
+      if position.quantity <= 0.0 || matches!(position.product.security_type, Some(SecurityType::Optn)) {
+        continue;
+      }
+      self = self.holding(Holding {
+        account_id_key: account_id_key.to_string(),
+        symbol: position.product.symbol.clone(),
+        quantity: position.quantity,
+        price: position.price,
+        lots: position.position_lot.clone(),
+      });
+    }
+    self
+  }
+
+  /// The cash the account can invest.
+  pub fn cash(mut self, account_id_key: impl Into<String>, amount: f64) -> Self {
+    *self.cash.entry(account_id_key.into()).or_default() += amount;
+    self
+  }
+
+  /// The price of a symbol, for the symbols to buy that aren't held.
+  pub fn price(mut self, symbol: impl Into<String>, price: f64) -> Self {
+    self.prices.insert(symbol.into(), price);
+    self
+  }
+
+  /// The smallest trade worth making, in dollars.
+  pub fn min_trade(mut self, value: f64) -> Self {
+    self.min_trade = value;
+    self
+  }
+
+  /// Trades fractions of shares instead of whole shares.
+  pub fn fractional(mut self, fractional: bool) -> Self {
+    self.fractional = fractional;
+    self
+  }
+
+  /// Adds the portfolio and the cash available for investment of the account, and the lots of the targeted positions.
+  pub async fn load(mut self, accounts: &dyn AccountsClient, account_id_key: &str) -> Result<Self> {
+    let balance = accounts.balance(account_id_key, BalanceRequest::default()).await?;
+    self = self.cash(account_id_key, balance.computed_balance.cash_available_for_investment);
+
+    let portfolio = accounts
+      .portfolio_all(account_id_key, PortfolioRequest::default())
+      .await?;
+    let mut positions: Vec<PortfolioPosition> = portfolio
+      .account_portfolio
+      .into_iter()
+      .flat_map(|p| p.position)
+      .collect();
+    for position in positions.iter_mut() {
+      if position.position_lot.is_empty() && self.targets.class_of(&position.product.symbol).is_some() {
+        let lots = accounts
+          .position_lots(account_id_key, &position.position_id.to_string())
+          .await?;
+        position.position_lot = lots.position_lot;
+      }
+    }
+    Ok(self.positions(account_id_key, &positions))
+  }
+
+  /// Quotes the targeted symbols without a price.
+  pub async fn load_prices(mut self, quotes: &dyn QuoteSource) -> Result<Self> {
+    let missing: Vec<String> = self
+      .targets
+      .symbols()
+      .filter(|s| !self.prices.contains_key(*s))
+      .cloned()
+      .collect();
+    for symbol in missing {
+      let quote = quotes.quote(&symbol).await?;
+      self.prices.insert(symbol, quote.last);
+    }
+    Ok(self)
+  }
+
+  /// Plans the trades, fails when a class to buy has no price for its first symbol.
+  pub fn plan(&self) -> Result<Plan> {
+    let holdings: Vec<&Holding> = self
+      .holdings
+      .iter()
+      .filter(|h| self.targets.class_of(&h.symbol).is_some())
+      .collect();
+    let total_value = holdings.iter().map(|h| h.market_value()).sum::<f64>() + self.cash.values().sum::<f64>();
+
+    let mut classes = vec![];
+    for (name, target_weight, _) in &self.targets.classes {
+      let value: f64 = holdings
+        .iter()
+        .filter(|h| self.targets.class_of(&h.symbol) == Some(name))
+        .map(|h| h.market_value())
+        .sum();
+      classes.push(Drift {
+        class: name.clone(),
+        target_weight: *target_weight,
+        weight: if total_value > 0.0 { value / total_value } else { 0.0 },
+        value,
+        target_value: target_weight * total_value,
+      });
+    }
+
+    let mut cash = self.cash.clone();
+    let mut trades = vec![];
+    for drift in classes.iter().filter(|d| d.value - d.target_value > self.min_trade) {
+      let class_holdings = holdings
+        .iter()
+        .filter(|h| self.targets.class_of(&h.symbol) == Some(drift.class.as_str()));
+      for trade in self.sell(class_holdings, drift.value - drift.target_value) {
+        *cash.entry(trade.account_id_key.clone()).or_default() += trade.value();
+        trades.push(trade);
+      }
+    }
+
+    let mut short: Vec<&Drift> = classes
+      .iter()
+      .filter(|d| d.target_value - d.value > self.min_trade)
+      .collect();
+    short.sort_by(|a, b| (b.target_value - b.value).total_cmp(&(a.target_value - a.value)));
+    for drift in short {
+      let (_, _, symbols) = self.targets.classes.iter().find(|(n, ..)| *n == drift.class).unwrap();
+      let Some(symbol) = symbols.first() else {
+        continue;
+      };
+      let price = *self
+        .prices
+        .get(symbol)
+        .filter(|p| **p > 0.0)
+        .ok_or_else(|| anyhow!("no price to buy {}", symbol))?;
+
+      // the account that holds the most of the symbol when it has the cash, else the one with the most cash
+      let holder = holdings
+        .iter()
+        .filter(|h| h.symbol == *symbol)
+        .max_by(|a, b| a.market_value().total_cmp(&b.market_value()))
+        .map(|h| h.account_id_key.clone());
+      let richest = cash.iter().max_by(|a, b| a.1.total_cmp(b.1)).map(|(k, _)| k.clone());
+      let wanted = drift.target_value - drift.value;
+      let Some(account) = holder
+        .filter(|k| cash.get(k).copied().unwrap_or_default() >= wanted)
+        .or(richest)
+      else {
+        continue;
+      };
+      let available = cash.get(&account).copied().unwrap_or_default();
+      let quantity = self.shares(wanted.min(available) / price);
+      if quantity <= 0.0 || quantity * price < self.min_trade {
+        continue;
+      }
+      *cash.entry(account.clone()).or_default() -= quantity * price;
+      trades.push(Trade {
+        account_id_key: account,
+        symbol: symbol.clone(),
+        action: OrderAction::Buy,
+        quantity,
+        price,
+        lots: vec![],
+      });
+    }
+
+    Ok(Plan {
+      total_value,
+      classes,
+      trades,
+      cash,
+    })
+  }
+
+  fn shares(&self, quantity: f64) -> f64 {
+    if self.fractional {
+      quantity
+    } else {
+      quantity.floor()
+    }
+  }
+
+  /// Sells about `amount` of the holdings, long term lots with the smallest gain first.
+  fn sell<'a>(&self, holdings: impl Iterator<Item = &'a &'a Holding>, amount: f64) -> Vec<Trade> {
+    let year_ago = (Utc::now() - Duration::days(365)).timestamp_millis();
+    // (holding, lot id, quantity, cost per share, long term)
+    let mut lots: Vec<(&Holding, i64, f64, f64, bool)> = vec![];
+    for holding in holdings {
+      if holding.lots.is_empty() {
+        lots.push((holding, 0, holding.quantity, holding.price, false));
+      }
+      for lot in holding.lots.iter().filter(|l| l.remaining_qty > 0.0) {
+        let long_term = lot.acquired_date > 0 && lot.acquired_date <= year_ago;
+        lots.push((holding, lot.position_log_id, lot.remaining_qty, lot.price, long_term));
+      }
+    }
+    lots.sort_by(|a, b| b.4.cmp(&a.4).then(b.3.total_cmp(&a.3)));
+
+    let mut trades: Vec<Trade> = vec![];
+    let mut left = amount;
+    for (holding, lot_id, available, cost, long_term) in lots {
+      if left <= 0.0 {
+        break;
+      }
+      let quantity = self.shares((left / holding.price).min(available));
+      if quantity <= 0.0 {
+        continue;
+      }
+      left -= quantity * holding.price;
+      let sale = LotSale {
+        lot_id,
+        quantity,
+        long_term,
+        gain: (holding.price - cost) * quantity,
+      };
+      match trades
+        .iter_mut()
+        .find(|t| t.account_id_key == holding.account_id_key && t.symbol == holding.symbol)
+      {
+        Some(trade) => {
+          trade.quantity += quantity;
+          trade.lots.push(sale);
+        }
+        None => trades.push(Trade {
+          account_id_key: holding.account_id_key.clone(),
+          symbol: holding.symbol.clone(),
+          action: OrderAction::Sell,
+          quantity,
+          price: holding.price,
+          lots: vec![sale],
+        }),
+      }
+    }
+    trades.retain(|t| t.value() >= self.min_trade);
+    trades
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use chrono::{Duration, Utc};
+
+  use super::{Holding, Planner, Targets};
+  use crate::accounts::{self, PositionLot};
+  use crate::options;
+  use crate::orders::OrderAction;
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::Memstore;
+
+  fn lot(id: i64, quantity: f64, price: f64, days_ago: i64) -> PositionLot {
+    PositionLot {
+      position_log_id: id,
+      remaining_qty: quantity,
+      price,
+      acquired_date: (Utc::now() - Duration::days(days_ago)).timestamp_millis(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn sells_long_term_lots_and_buys_with_the_proceeds() {
+    let targets = Targets::new()
+      .class("stocks", 0.6, ["VTI", "ITOT"])
+      .class("bonds", 0.4, ["BND"]);
+    let planner = Planner::new(targets)
+      .holding(Holding {
+        account_id_key: "taxable".to_string(),
+        symbol: "VTI".to_string(),
+        quantity: 60.0,
+        price: 100.0,
+        lots: vec![lot(1, 30.0, 50.0, 30), lot(2, 20.0, 90.0, 800), lot(3, 10.0, 60.0, 900)],
+      })
+      .holding(Holding {
+        account_id_key: "taxable".to_string(),
+        symbol: "AAPL".to_string(),
+        quantity: 10.0,
+        price: 150.0,
+        lots: vec![],
+      })
+      .holding(Holding {
+        account_id_key: "taxable".to_string(),
+        symbol: "BND".to_string(),
+        quantity: 30.0,
+        price: 70.0,
+        lots: vec![],
+      })
+      .cash("taxable", 900.0)
+      .min_trade(100.0);
+
+    let plan = planner.plan().unwrap();
+    // VTI 6000, BND 2100 and the cash make 9000, AAPL has no target
+    assert_eq!(plan.total_value, 9000.0);
+    assert!((plan.classes[0].drift() - (6000.0 / 9000.0 - 0.6)).abs() < 1e-9);
+
+    // 600 of stocks over the target are sold from the long term lot with the smallest gain
+    let sale = &plan.trades[0];
+    assert_eq!((sale.action, sale.quantity), (OrderAction::Sell, 6.0));
+    assert_eq!(sale.lots.len(), 1);
+    assert_eq!(sale.lots[0].lot_id, 2);
+    assert!(sale.lots[0].long_term);
+
+    // the bonds are 1500 short, with 900 + 600 of cash
+    let purchase = &plan.trades[1];
+    assert_eq!(
+      (purchase.symbol.as_str(), purchase.action, purchase.quantity),
+      ("BND", OrderAction::Buy, 21.0)
+    );
+    assert_eq!(plan.cash["taxable"], 30.0);
+
+    let orders = plan.preview_orders();
+    assert_eq!(orders.len(), 2);
+    assert_eq!(orders[1].0, "taxable");
+    assert_eq!(orders[1].1.order[0].instrument[0].quantity, 21.0);
+  }
+
+  #[tokio::test]
+  async fn loads_the_accounts_and_prices() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let session = Arc::new(server.session(Memstore::new()).await.unwrap());
+
+    let plan = Planner::new(Targets::new().symbol("AAPL", 0.5).symbol("MSFT", 0.5))
+      .load(&accounts::Api::new(session.clone()), "fake-account-key")
+      .await
+      .unwrap()
+      .load_prices(&options::Api::new(session))
+      .await
+      .unwrap()
+      .plan()
+      .unwrap();
+    assert_eq!(plan.total_value, 11_500.0);
+    let trades: Vec<_> = plan.trades.iter().map(|t| (t.symbol.as_str(), t.quantity)).collect();
+    assert_eq!(trades, vec![("MSFT", 19.0), ("AAPL", 28.0)]);
+  }
+}
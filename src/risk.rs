@@ -0,0 +1,482 @@
+//! Margin risk: interprets the margin fields of a balance against [`Thresholds`].
+//!
+//! [`evaluate`] reports the open margin calls, the thresholds that are breached and, when the thresholds have a
+//! [`Shock`], the equity projected after that move in the prices of the positions. A [`RiskMonitor`] runs the
+//! evaluation for the open margin accounts and broadcasts a [`RiskEvent`] whenever a threshold or margin call starts
+//! or stops applying.
+
+use std::{
+  collections::{HashMap, HashSet},
+  sync::Mutex,
+  time::Duration,
+};
+
+use anyhow::Result;
+use tokio::sync::broadcast;
+
+use crate::accounts::{BalanceRequest, BalanceResponse, PortfolioPosition, PortfolioRequest};
+use crate::clients::AccountsClient;
+
+/// The limits to check, a limit that is `None` isn't checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thresholds {
+  /// The lowest acceptable Reg T equity, as a percentage of the long market value.
+  pub min_regt_equity_percent: Option<f64>,
+  pub min_margin_buying_power: Option<f64>,
+  /// The lowest acceptable excess equity of portfolio margin accounts.
+  pub min_excess_equity: Option<f64>,
+  /// The price move to project the equity for.
+  pub shock: Option<Shock>,
+  /// The lowest acceptable equity percentage after the shock.
+  pub min_shocked_equity_percent: Option<f64>,
+}
+
+impl Default for Thresholds {
+  /// Warns 5 points above the usual 25% maintenance requirement.
+  fn default() -> Self {
+    Self {
+      min_regt_equity_percent: Some(30.0),
+      min_margin_buying_power: None,
+      min_excess_equity: None,
+      shock: None,
+      min_shocked_equity_percent: None,
+    }
+  }
+}
+
+/// A hypothetical move in prices, in percent, `-20.0` for a 20% drop.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shock {
+  pub percent: f64,
+  /// Moves that replace `percent` for some symbols.
+  pub symbols: HashMap<String, f64>,
+}
+
+impl Shock {
+  /// Moves every position by the same percentage.
+  pub fn uniform(percent: f64) -> Self {
+    Self {
+      percent,
+      symbols: HashMap::new(),
+    }
+  }
+
+  pub fn with_symbol(mut self, symbol: impl Into<String>, percent: f64) -> Self {
+    self.symbols.insert(symbol.into(), percent);
+    self
+  }
+
+  fn percent_for(&self, symbol: &str) -> f64 {
+    self.symbols.get(symbol).copied().unwrap_or(self.percent)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallKind {
+  Fed,
+  House,
+  Cash,
+  MinEquity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarginCall {
+  pub kind: CallKind,
+  pub amount: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RiskMetric {
+  RegtEquityPercent,
+  MarginBuyingPower,
+  ExcessEquity,
+  ShockedEquityPercent,
+}
+
+/// A metric below its threshold.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Breach {
+  pub metric: RiskMetric,
+  pub value: f64,
+  pub threshold: f64,
+}
+
+/// The equity of the account after a [`Shock`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+  pub equity_before: f64,
+  pub equity_after: f64,
+  pub long_value_after: f64,
+  /// The equity as a percentage of the long market value, `None` without long positions.
+  pub equity_percent_after: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RiskReport {
+  pub margin_calls: Vec<MarginCall>,
+  pub breaches: Vec<Breach>,
+  pub projection: Option<Projection>,
+}
+
+impl RiskReport {
+  pub fn is_ok(&self) -> bool {
+    self.margin_calls.is_empty() && self.breaches.is_empty()
+  }
+}
+
+/// The market value of the position, negative for short positions.
+fn signed_market_value(position: &PortfolioPosition) -> f64 {
+  if position.position_type.eq_ignore_ascii_case("SHORT") {
+    -position.market_value.abs()
+  } else {
+    position.market_value
+  }
+}
+
+/// Projects the net account value after moving the price of every position by the shock.
+pub fn project(balance: &BalanceResponse, positions: &[PortfolioPosition], shock: &Shock) -> Projection {
+  let equity_before = balance.computed_balance.real_time_values.total_account_value;
+  let mut change = 0.0;
+  let mut long_value_after = 0.0;
+  for position in positions {
+    let value = signed_market_value(position);
+    let moved = value * (1.0 + shock.percent_for(&position.product.symbol) / 100.0);
+    change += moved - value;
+    if value > 0.0 {
+      long_value_after += moved;
+    }
+  }
+  let equity_after = equity_before + change;
+  Projection {
+    equity_before,
+    equity_after,
+    long_value_after,
+    equity_percent_after: (long_value_after > 0.0).then(|| equity_after / long_value_after * 100.0),
+  }
+}
+
+/// Checks the balance and positions of an account against the thresholds.
+pub fn evaluate(balance: &BalanceResponse, positions: &[PortfolioPosition], thresholds: &Thresholds) -> RiskReport {
+  let computed = &balance.computed_balance;
+  let calls = &computed.open_calls;
+  let margin_calls = [
+    (CallKind::Fed, calls.fed_call),
+    (CallKind::House, calls.house_call),
+    (CallKind::Cash, Some(calls.cash_call)),
+    (CallKind::MinEquity, calls.min_equity_call),
+  ]
+  .into_iter()
+  .filter_map(|(kind, amount)| amount.filter(|a| *a > 0.0).map(|amount| MarginCall { kind, amount }))
+  .collect();
+
+  let projection = thresholds.shock.as_ref().map(|s| project(balance, positions, s));
+  let checks = [
+    (
+      RiskMetric::RegtEquityPercent,
+      computed.regt_equity_percent,
+      thresholds.min_regt_equity_percent,
+    ),
+    (
+      RiskMetric::MarginBuyingPower,
+      computed.margin_buying_power,
+      thresholds.min_margin_buying_power,
+    ),
+    (
+      RiskMetric::ExcessEquity,
+      computed.portfolio_margin.as_ref().map(|p| p.excess_equity),
+      thresholds.min_excess_equity,
+    ),
+    (
+      RiskMetric::ShockedEquityPercent,
+      projection.and_then(|p| p.equity_percent_after),
+      thresholds.min_shocked_equity_percent,
+    ),
+  ];
+  let breaches = checks
+    .into_iter()
+    .filter_map(|(metric, value, threshold)| match (value, threshold) {
+      (Some(value), Some(threshold)) if value < threshold => Some(Breach {
+        metric,
+        value,
+        threshold,
+      }),
+      _ => None,
+    })
+    .collect();
+
+  RiskReport {
+    margin_calls,
+    breaches,
+    projection,
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskEventKind {
+  Breached(Breach),
+  Recovered(RiskMetric),
+  MarginCall(MarginCall),
+  MarginCallMet(CallKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskEvent {
+  pub account_id_key: String,
+  pub kind: RiskEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Condition {
+  Breach(RiskMetric),
+  Call(CallKind),
+}
+
+/// Evaluates the risk of accounts and broadcasts the changes.
+pub struct RiskMonitor<A> {
+  accounts: A,
+  thresholds: Thresholds,
+  events: broadcast::Sender<RiskEvent>,
+  active: Mutex<HashMap<String, HashSet<Condition>>>,
+}
+
+impl<A> RiskMonitor<A>
+where
+  A: AccountsClient,
+{
+  pub fn new(accounts: A, thresholds: Thresholds) -> Self {
+    Self {
+      accounts,
+      thresholds,
+      events: broadcast::channel(256).0,
+      active: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub fn thresholds(&self) -> &Thresholds {
+    &self.thresholds
+  }
+
+  /// Receives an event when a threshold is breached or recovers, and when a margin call opens or is met.
+  pub fn subscribe(&self) -> broadcast::Receiver<RiskEvent> {
+    self.events.subscribe()
+  }
+
+  /// Evaluates one account and emits the events for what changed since its previous check.
+  pub async fn check(&self, account_id_key: &str) -> Result<RiskReport> {
+    let balance = self
+      .accounts
+      .balance(
+        account_id_key,
+        BalanceRequest {
+          real_time_nav: Some(true),
+          ..Default::default()
+        },
+      )
+      .await?;
+    let positions: Vec<PortfolioPosition> = if self.thresholds.shock.is_some() {
+      self
+        .accounts
+        .portfolio_all(account_id_key, PortfolioRequest::default())
+        .await?
+        .account_portfolio
+        .into_iter()
+        .flat_map(|p| p.position)
+        .collect()
+    } else {
+      vec![]
+    };
+    let report = evaluate(&balance, &positions, &self.thresholds);
+    self.emit_changes(account_id_key, &report);
+    Ok(report)
+  }
+
+  /// Evaluates every open margin account.
+  pub async fn check_all(&self) -> Result<Vec<(String, RiskReport)>> {
+    let mut reports = vec![];
+    for account in self.accounts.list().await? {
+      if account.is_closed() || !account.is_margin() {
+        continue;
+      }
+      let report = self.check(&account.account_id_key).await?;
+      reports.push((account.account_id_key, report));
+    }
+    Ok(reports)
+  }
+
+  /// Checks the margin accounts at the given interval, starting right away. Failed checks are logged and retried at
+  /// the next interval, the future never completes.
+  pub async fn run(&self, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+      interval.tick().await;
+      if let Err(e) = self.check_all().await {
+        warn!("failed to check the margin risk: {:#}", e);
+      }
+    }
+  }
+
+  fn emit_changes(&self, account_id_key: &str, report: &RiskReport) {
+    let mut current: HashMap<Condition, RiskEventKind> = HashMap::new();
+    for breach in &report.breaches {
+      current.insert(Condition::Breach(breach.metric), RiskEventKind::Breached(*breach));
+    }
+    for call in &report.margin_calls {
+      current.insert(Condition::Call(call.kind), RiskEventKind::MarginCall(*call));
+    }
+
+    let mut active = self.active.lock().unwrap();
+    let previous = active.entry(account_id_key.to_string()).or_default();
+    let mut events = vec![];
+    for condition in previous.iter().filter(|c| !current.contains_key(c)) {
+      events.push(match condition {
+        Condition::Breach(metric) => RiskEventKind::Recovered(*metric),
+        Condition::Call(kind) => RiskEventKind::MarginCallMet(*kind),
+      });
+    }
+    for (condition, kind) in &current {
+      if !previous.contains(condition) {
+        events.push(kind.clone());
+      }
+    }
+    *previous = current.into_keys().collect();
+    drop(active);
+
+    for kind in events {
+      // no subscribers is fine, the report is returned as well
+      let _ = self.events.send(RiskEvent {
+        account_id_key: account_id_key.to_string(),
+        kind,
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    evaluate, Breach, CallKind, MarginCall, RiskEvent, RiskEventKind, RiskMetric, RiskMonitor, Shock, Thresholds,
+  };
+  use crate::accounts::{BalanceResponse, ComputedBalance, OpenCalls, PortfolioPosition, RealTimeValues};
+  use crate::testing::MockAccounts;
+  use crate::Product;
+
+  fn balance(regt_equity_percent: f64, fed_call: f64) -> BalanceResponse {
+    BalanceResponse {
+      computed_balance: ComputedBalance {
+        regt_equity_percent: Some(regt_equity_percent),
+        margin_buying_power: Some(5_000.0),
+        open_calls: OpenCalls {
+          fed_call: Some(fed_call),
+          ..Default::default()
+        },
+        real_time_values: RealTimeValues {
+          total_account_value: 6_000.0,
+          ..Default::default()
+        },
+        ..Default::default()
+      },
+      ..Default::default()
+    }
+  }
+
+  fn position(symbol: &str, position_type: &str, market_value: f64) -> PortfolioPosition {
+    PortfolioPosition {
+      product: Product {
+        symbol: symbol.to_string(),
+        ..Default::default()
+      },
+      position_type: position_type.to_string(),
+      market_value,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn reports_calls_breaches_and_shocks() {
+    let positions = vec![
+      position("AAPL", "LONG", 10_000.0),
+      position("TSLA", "LONG", 5_000.0),
+      position("GME", "SHORT", -1_000.0),
+    ];
+    let thresholds = Thresholds {
+      min_margin_buying_power: Some(1_000.0),
+      shock: Some(Shock::uniform(-10.0).with_symbol("TSLA", -40.0)),
+      min_shocked_equity_percent: Some(30.0),
+      ..Default::default()
+    };
+
+    let report = evaluate(&balance(28.0, 250.0), &positions, &thresholds);
+    assert_eq!(
+      report.margin_calls,
+      vec![MarginCall {
+        kind: CallKind::Fed,
+        amount: 250.0
+      }]
+    );
+    let projection = report.projection.unwrap();
+    // -1000 on AAPL, -2000 on TSLA and +100 on the short
+    assert_eq!(projection.equity_after, 3_100.0);
+    assert_eq!(projection.long_value_after, 12_000.0);
+    assert_eq!(
+      report.breaches,
+      vec![
+        Breach {
+          metric: RiskMetric::RegtEquityPercent,
+          value: 28.0,
+          threshold: 30.0
+        },
+        Breach {
+          metric: RiskMetric::ShockedEquityPercent,
+          value: 3_100.0 / 12_000.0 * 100.0,
+          threshold: 30.0
+        },
+      ]
+    );
+    assert!(!report.is_ok());
+    assert!(evaluate(&balance(45.0, 0.0), &[], &Thresholds::default()).is_ok());
+  }
+
+  #[tokio::test]
+  async fn emits_events_when_thresholds_are_crossed() {
+    let accounts = MockAccounts::new()
+      .on_balance(Ok(balance(28.0, 0.0)))
+      .on_balance(Ok(balance(28.0, 100.0)))
+      .on_balance(Ok(balance(40.0, 0.0)));
+    let monitor = RiskMonitor::new(accounts, Thresholds::default());
+    let mut events = monitor.subscribe();
+
+    let event = |kind| RiskEvent {
+      account_id_key: "key".to_string(),
+      kind,
+    };
+    monitor.check("key").await.unwrap();
+    assert_eq!(
+      events.try_recv().unwrap(),
+      event(RiskEventKind::Breached(Breach {
+        metric: RiskMetric::RegtEquityPercent,
+        value: 28.0,
+        threshold: 30.0
+      }))
+    );
+    monitor.check("key").await.unwrap();
+    assert_eq!(
+      events.try_recv().unwrap(),
+      event(RiskEventKind::MarginCall(MarginCall {
+        kind: CallKind::Fed,
+        amount: 100.0
+      }))
+    );
+    assert!(events.try_recv().is_err());
+    monitor.check("key").await.unwrap();
+    let mut recovered = vec![events.try_recv().unwrap(), events.try_recv().unwrap()];
+    recovered.sort_by_key(|e| matches!(e.kind, RiskEventKind::MarginCallMet(_)));
+    assert_eq!(
+      recovered,
+      vec![
+        event(RiskEventKind::Recovered(RiskMetric::RegtEquityPercent)),
+        event(RiskEventKind::MarginCallMet(CallKind::Fed)),
+      ]
+    );
+  }
+}
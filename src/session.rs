@@ -1,3 +1,5 @@
+use crate::middleware::{Middleware, Next, RedactedLogger};
+use crate::transport::{https_client, Transport};
 use crate::{Credentials, Mode, Store};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
@@ -13,22 +15,24 @@ use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use tokio::io::{self, *};
 
-use hyper::{
-  client::{connect::dns::GaiResolver, HttpConnector},
-  Client,
-};
-use hyper_tls::HttpsConnector;
-
 use secstr::SecUtf8;
 
-use hyper::service::Service;
-use std::{collections::BTreeMap, fmt::Debug, iter::FromIterator};
+use std::{collections::BTreeMap, iter::FromIterator, sync::Arc};
 
 use super::{LIVE_URL, SANDBOX_URL};
 
 const SANDBOX_NAMESPACE: &str = "etradesandbox";
 const LIVE_NAMESPACE: &str = "etrade";
 
+/// The profile a session uses unless [`Session::with_profile`] picks another one.
+pub const DEFAULT_PROFILE: &str = "default";
+
+const PROFILES_KEY: &str = "profiles";
+
+/// Serializes the updates of the profile registry between the sessions of this process. Processes sharing a store
+/// can still lose each other's updates, the stores have no compare-and-swap to guard against that.
+static PROFILES_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
 const API_KEY: &str = "apikey";
 const SECRET_KEY: &str = "secret";
 const ACCESS_TOKEN_KEY: &str = "access_token_key";
@@ -37,17 +41,32 @@ const REQUEST_TOKEN_KEY: &str = "request_token_key";
 const REQUEST_TOKEN_SECRET: &str = "request_token_secret";
 const REQUEST_TOKEN_CREATED: &str = "request_token_ts";
 
+const SESSION_KEYS: &[&str] = &[
+  API_KEY,
+  SECRET_KEY,
+  ACCESS_TOKEN_KEY,
+  ACCESS_TOKEN_SECRET,
+  REQUEST_TOKEN_KEY,
+  REQUEST_TOKEN_SECRET,
+  REQUEST_TOKEN_CREATED,
+];
+
 const REQUEST_TOKEN_URL: &str = "https://api.etrade.com/oauth/request_token";
 const ACCESS_TOKEN_URL: &str = "https://api.etrade.com/oauth/access_token";
 const RENEW_ACCESS_TOKEN_URL: &str = "https://api.etrade.com/oauth/renew_access_token";
-
-type HttpClient = Client<HttpsConnector<HttpConnector<GaiResolver>>, hyper::Body>;
+const AUTHORIZE_URL: &str = "https://us.etrade.com/e/t/etws/authorize";
 
 #[async_trait]
-pub trait CallbackProvider: Clone {
+pub trait CallbackProvider: Send + Sync {
   async fn verifier_code(&self, url: &str) -> Result<String>;
 }
 
+/// Returned when the session needs the user to authorize the application but it was configured
+/// without a [`CallbackProvider`] to ask for the verifier code.
+#[derive(Debug, thiserror::Error)]
+#[error("authorization required: the session is non-interactive and has no valid access token")]
+pub struct AuthorizationRequired;
+
 #[derive(Debug, Clone, Copy)]
 pub struct OOB;
 
@@ -67,29 +86,42 @@ impl CallbackProvider for OOB {
   }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct UrlConfig<'a> {
-  pub access_token_url: &'a str,
-  pub renew_access_token_url: &'a str,
-  pub request_token_url: &'a str,
+#[derive(Debug, Clone)]
+struct UrlConfig {
+  pub access_token_url: String,
+  pub renew_access_token_url: String,
+  pub request_token_url: String,
+  pub authorize_url: String,
 }
 
-impl<'a> UrlConfig<'a> {
+impl UrlConfig {
+  /// The oauth endpoints of a server other than E*Trade, like [`FakeServer`](crate::testing::FakeServer).
+  fn with_base_url(base_url: &str) -> Self {
+    Self {
+      access_token_url: format!("{}/oauth/access_token", base_url),
+      renew_access_token_url: format!("{}/oauth/renew_access_token", base_url),
+      request_token_url: format!("{}/oauth/request_token", base_url),
+      authorize_url: format!("{}/e/t/etws/authorize", base_url),
+    }
+  }
+
   pub fn authorize_url(&self, key: &SecUtf8, token: &SecUtf8) -> String {
     format!(
-      "https://us.etrade.com/e/t/etws/authorize?key={}&token={}",
+      "{}?key={}&token={}",
+      self.authorize_url,
       key.unsecure(),
       token.unsecure(),
     )
   }
 }
 
-impl<'a> Default for UrlConfig<'a> {
+impl Default for UrlConfig {
   fn default() -> Self {
     Self {
-      access_token_url: ACCESS_TOKEN_URL,
-      renew_access_token_url: RENEW_ACCESS_TOKEN_URL,
-      request_token_url: REQUEST_TOKEN_URL,
+      access_token_url: ACCESS_TOKEN_URL.to_string(),
+      renew_access_token_url: RENEW_ACCESS_TOKEN_URL.to_string(),
+      request_token_url: REQUEST_TOKEN_URL.to_string(),
+      authorize_url: AUTHORIZE_URL.to_string(),
     }
   }
 }
@@ -97,43 +129,158 @@ impl<'a> Default for UrlConfig<'a> {
 pub struct Session<T: Store> {
   store: T,
   mode: Mode,
-  client: HttpClient,
-  urls: UrlConfig<'static>,
+  transport: Box<dyn Transport>,
+  urls: UrlConfig,
+  /// Overrides the url picked by the mode.
+  base_url: Option<String>,
+  callbacks: Option<Box<dyn CallbackProvider>>,
+  profile: String,
+  /// Always ends with the redacted debug logger, so it sees the request as it is sent.
+  middlewares: Vec<Arc<dyn Middleware>>,
 }
 
 impl<T> Session<T>
 where
   T: Store,
 {
+  /// Creates a session that prompts for the verifier code on the terminal with [`OOB`].
   pub fn new(mode: Mode, store: T) -> Self {
-    let https = HttpsConnector::new();
-
     Self {
       store,
       mode,
-      client: Client::builder().build(https),
+      transport: Box::new(https_client()),
       urls: UrlConfig::default(),
+      base_url: None,
+      callbacks: Some(Box::new(OOB)),
+      profile: DEFAULT_PROFILE.to_string(),
+      middlewares: vec![Arc::new(RedactedLogger::default())],
     }
   }
 
+  /// Keeps the consumer credentials and tokens of this session apart from other profiles in the same store.
+  pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+    self.profile = profile.into();
+    self
+  }
+
+  pub fn profile(&self) -> &str {
+    &self.profile
+  }
+
+  /// Uses the given provider to obtain the verifier code when the user needs to authorize the application.
+  pub fn with_callbacks(mut self, callbacks: impl CallbackProvider + 'static) -> Self {
+    self.callbacks = Some(Box::new(callbacks));
+    self
+  }
+
+  /// Adds a middleware around the requests of this session, see [`crate::middleware`].
+  pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+    self
+      .middlewares
+      .insert(self.middlewares.len() - 1, Arc::new(middleware));
+    self
+  }
+
+  /// Sends the requests with the given transport instead of the default HTTPS client.
+  pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+    self.transport = Box::new(transport);
+    self
+  }
+
+  /// Sends the api and oauth requests to another server than E*Trade, for example a
+  /// [`FakeServer`](crate::testing::FakeServer).
+  pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+    let base_url = base_url.into().trim_end_matches('/').to_string();
+    self.urls = UrlConfig::with_base_url(&base_url);
+    self.base_url = Some(base_url);
+    self
+  }
+
+  /// Never prompts for authorization, requests fail with [`AuthorizationRequired`] as soon as there is no cached
+  /// access token instead of starting the oauth flow.
+  pub fn non_interactive(mut self) -> Self {
+    self.callbacks = None;
+    self
+  }
+
   fn base_url(&self) -> &str {
+    if let Some(base_url) = &self.base_url {
+      return base_url;
+    }
     match self.mode {
       Mode::Sandbox => SANDBOX_URL,
       Mode::Live => LIVE_URL,
     }
   }
 
-  fn namespace(&self) -> &str {
+  fn mode_namespace(&self) -> &str {
     match self.mode {
       Mode::Sandbox => SANDBOX_NAMESPACE,
       Mode::Live => LIVE_NAMESPACE,
     }
   }
 
+  fn namespace(&self) -> String {
+    profile_namespace(self.mode_namespace(), &self.profile)
+  }
+
   pub async fn initialize(&self, key: String, secret: String) -> Result<()> {
     self.store.put(self.namespace(), API_KEY, key).await?;
     self.store.put(self.namespace(), SECRET_KEY, secret).await?;
-    Ok(())
+
+    self
+      .update_profiles(|profiles| {
+        if !profiles.contains(&self.profile) {
+          profiles.push(self.profile.clone());
+        }
+      })
+      .await
+  }
+
+  /// Lists the profiles that were initialized in the store for the mode of this session.
+  pub async fn profiles(&self) -> Result<Vec<String>> {
+    let mut profiles = self.registered_profiles().await?;
+    let default_initialized = self.store.get(self.mode_namespace(), API_KEY).await?.is_some();
+    if default_initialized && !profiles.iter().any(|p| p == DEFAULT_PROFILE) {
+      profiles.insert(0, DEFAULT_PROFILE.to_string());
+    }
+    Ok(profiles)
+  }
+
+  /// Deletes the consumer credentials and tokens of this session's profile from the store.
+  pub async fn remove(&self) -> Result<()> {
+    self.invalidate().await?;
+    self.store.del(self.namespace(), API_KEY).await?;
+    self.store.del(self.namespace(), SECRET_KEY).await?;
+
+    self
+      .update_profiles(|profiles| profiles.retain(|p| p != &self.profile))
+      .await
+  }
+
+  async fn registered_profiles(&self) -> Result<Vec<String>> {
+    match self.store.get(self.mode_namespace(), PROFILES_KEY).await? {
+      Some(v) => Ok(serde_json::from_str(v.unsecure())?),
+      None => Ok(vec![]),
+    }
+  }
+
+  /// Reads, changes and writes back the registered profiles, see [`PROFILES_LOCK`] for what this guards against.
+  async fn update_profiles(&self, update: impl FnOnce(&mut Vec<String>)) -> Result<()> {
+    let _guard = PROFILES_LOCK.lock().await;
+    let mut profiles = self.registered_profiles().await?;
+    let before = profiles.clone();
+    update(&mut profiles);
+    if profiles == before {
+      return Ok(());
+    }
+    if profiles.is_empty() {
+      return self.store.del(self.mode_namespace(), PROFILES_KEY).await;
+    }
+    self
+      .store
+      .put(self.mode_namespace(), PROFILES_KEY, serde_json::to_string(&profiles)?)
+      .await
   }
 
   async fn consumer(&self) -> Result<Credentials> {
@@ -187,12 +334,12 @@ where
       }
       _ => {
         debug!("getting a new request token");
-        let uri = http::Uri::from_static(self.urls.request_token_url);
-        let authorization = oauth::Builder::<_, _>::new(consumer.clone().into(), oauth::HMAC_SHA1)
+        let uri: http::Uri = self.urls.request_token_url.parse()?;
+        let authorization = oauth::Builder::<_, _>::new(consumer.into(), oauth::HMAC_SHA1)
           .callback("oob")
           .get(&uri, &());
 
-        let body = send_request(uri, authorization, &self.client).await;
+        let body = self.fetch_token(uri, authorization).await?;
         let creds: oauth_credentials::Credentials<Box<str>> = serde_urlencoded::from_bytes(&body)?;
 
         debug!("created request token: {:?}", &creds);
@@ -217,7 +364,7 @@ where
     }
   }
 
-  async fn access_token(&self, callback: impl CallbackProvider) -> Result<Credentials> {
+  async fn access_token(&self) -> Result<Credentials> {
     let consumer = self.consumer().await?;
 
     let access_token = self.store.get(self.namespace(), ACCESS_TOKEN_KEY).await?;
@@ -228,11 +375,12 @@ where
         debug!("using cached access token");
         Ok(Credentials::new(token, secret))
       }
+      _ if self.callbacks.is_none() => Err(AuthorizationRequired.into()),
       _ => {
         let request_token = self.request_token(&consumer).await;
         if request_token.is_err() {
           debug!("restarting full flow because request token has an error");
-          return self.full_access_token_flow(consumer, callback).await;
+          return self.full_access_token_flow(consumer).await;
         }
 
         match self.renew_access_token(&consumer, &request_token.unwrap()).await {
@@ -240,17 +388,14 @@ where
             debug!("using renewed access token");
             Ok(access_token)
           }
-          Err(_) => self.full_access_token_flow(consumer, callback).await,
+          Err(_) => self.full_access_token_flow(consumer).await,
         }
       }
     }
   }
 
-  async fn full_access_token_flow(
-    &self,
-    consumer: Credentials,
-    callback: impl CallbackProvider,
-  ) -> Result<Credentials> {
+  async fn full_access_token_flow(&self, consumer: Credentials) -> Result<Credentials> {
+    let callback = self.callbacks.as_ref().ok_or(AuthorizationRequired)?;
     self.invalidate().await?;
 
     let request_token = self.request_token(&consumer).await?;
@@ -269,12 +414,12 @@ where
     pin: impl AsRef<str>,
   ) -> Result<Credentials> {
     debug!("getting an access token");
-    let uri = http::Uri::from_static(self.urls.access_token_url);
-    let authorization = oauth::Builder::<_, _>::new(consumer.clone().into(), oauth::HMAC_SHA1)
-      .token(Some(request_token.clone().into()))
+    let uri: http::Uri = self.urls.access_token_url.parse()?;
+    let authorization = oauth::Builder::<_, _>::new(consumer.into(), oauth::HMAC_SHA1)
+      .token(Some(request_token.into()))
       .verifier(pin.as_ref())
       .get(&uri, &());
-    let body = send_request(uri, authorization, &self.client).await;
+    let body = self.fetch_token(uri, authorization).await?;
     let creds: oauth_credentials::Credentials<Box<str>> = serde_urlencoded::from_bytes(&body)?;
 
     debug!("created access token: {:?}", &creds);
@@ -292,12 +437,12 @@ where
 
   async fn renew_access_token(&self, consumer: &Credentials, request_token: &Credentials) -> Result<Credentials> {
     debug!("renewing an access token");
-    let uri = http::Uri::from_static(self.urls.renew_access_token_url);
-    let authorization = oauth::Builder::<_, _>::new(consumer.clone().into(), oauth::HMAC_SHA1)
-      .token(Some(request_token.clone().into()))
+    let uri: http::Uri = self.urls.renew_access_token_url.parse()?;
+    let authorization = oauth::Builder::<_, _>::new(consumer.into(), oauth::HMAC_SHA1)
+      .token(Some(request_token.into()))
       .get(&uri, &());
 
-    let body = send_request(uri, authorization, &self.client).await;
+    let body = self.fetch_token(uri, authorization).await?;
     let creds: oauth_credentials::Credentials<Box<str>> = serde_urlencoded::from_bytes(&body)?;
     debug!("renewed access token: {:?}", &creds);
     let access_token: Credentials = creds.into();
@@ -312,20 +457,13 @@ where
     Ok(access_token)
   }
 
-  async fn do_send<P, B, C>(
-    &self,
-    method: http::Method,
-    path: P,
-    input: Option<B>,
-    callback: C,
-  ) -> Result<Response<hyper::Body>>
+  async fn do_send<P, B>(&self, method: http::Method, path: P, input: Option<B>) -> Result<Response<hyper::Body>>
   where
     P: AsRef<str> + Send + Sync,
     B: Serialize + Clone + Send + Sync,
-    C: CallbackProvider + Clone,
   {
     let consumer = self.consumer().await?;
-    let access_token = self.access_token(callback.clone()).await?;
+    let access_token = self.access_token().await?;
 
     let uri = format!("{}{}", self.base_url(), path.as_ref());
 
@@ -348,8 +486,8 @@ where
 
     let oreq = oauth::request::AssertSorted::new(&params);
 
-    let authorization = oauth::Builder::new(consumer.into(), oauth::HMAC_SHA1)
-      .token(Some(access_token.into()))
+    let authorization = oauth::Builder::new((&consumer).into(), oauth::HMAC_SHA1)
+      .token(Some((&access_token).into()))
       .authorize(method.as_str(), bare_uri, &oreq);
 
     let body: hyper::Body = match input.clone() {
@@ -365,31 +503,39 @@ where
       .header(ACCEPT, "application/json")
       .header(AUTHORIZATION, authorization)
       .uri(full_uri)
-      .body(body)
-      .unwrap();
+      .body(body)?;
+
+    self.execute(req).await
+  }
 
-    // let req = builder
-    debug!("{:?}", req);
-    let resp = self.client.request(req).await?;
-    debug!("{:?}", resp);
-    Ok(resp)
+  /// Sends the request through the middlewares.
+  async fn execute(&self, req: Request<hyper::Body>) -> Result<Response<hyper::Body>> {
+    Next::new(&self.middlewares, self.transport.as_ref()).run(req).await
   }
 
-  pub async fn send<P, B, R, C>(&self, method: http::Method, path: P, input: Option<B>, callback: C) -> Result<R>
+  async fn fetch_token(&self, uri: http::Uri, authorization: String) -> Result<Vec<u8>> {
+    let req = Request::get(&uri)
+      .header(AUTHORIZATION, authorization)
+      .body(hyper::Body::empty())?;
+    let resp = self.execute(req).await?;
+    if resp.status().as_u16() / 100 != 2 {
+      return Err(anyhow!("{} responded with {}", uri, resp.status()));
+    }
+    Ok(hyper::body::to_bytes(resp.into_body()).await?.to_vec())
+  }
+
+  pub async fn send<P, B, R>(&self, method: http::Method, path: P, input: Option<B>) -> Result<R>
   where
     P: AsRef<str> + Send + Sync,
     B: Serialize + Clone + Send + Sync,
     R: DeserializeOwned + Send + Sync,
-    C: CallbackProvider + Clone,
   {
-    let mut resp = self
-      .do_send(method.clone(), path.as_ref(), input.clone(), callback.clone())
-      .await?;
+    let mut resp = self.do_send(method.clone(), path.as_ref(), input.clone()).await?;
 
     if resp.status().as_u16() == 401 {
       debug!("auth error, retrying with invalidated session");
       self.invalidate().await?;
-      resp = self.do_send(method, path, input, callback).await?;
+      resp = self.do_send(method, path, input).await?;
     }
 
     debug!("reading status code");
@@ -413,9 +559,39 @@ where
     match content_type.as_str() {
       "application/xml" => Ok(quick_xml::de::from_reader(body.reader())?),
       "application/json" => Ok(serde_json::from_reader(body.reader())?),
-      v => return Err(anyhow!("api responded with unknown content type {}", v)),
+      v => Err(anyhow!("api responded with unknown content type {}", v)),
+    }
+  }
+}
+
+fn profile_namespace(mode_namespace: &str, profile: &str) -> String {
+  if profile == DEFAULT_PROFILE {
+    mode_namespace.to_string()
+  } else {
+    format!("{}:{}", mode_namespace, profile)
+  }
+}
+
+/// The namespaces and keys a session may have written to the store, for every registered profile.
+pub(crate) async fn known_entries(store: &(impl Store + Sync)) -> Result<Vec<(String, String)>> {
+  let mut entries = vec![];
+  for mode_namespace in [LIVE_NAMESPACE, SANDBOX_NAMESPACE] {
+    let mut profiles: Vec<String> = match store.get(mode_namespace, PROFILES_KEY).await? {
+      Some(v) => {
+        entries.push((mode_namespace.to_string(), PROFILES_KEY.to_string()));
+        serde_json::from_str(v.unsecure())?
+      }
+      None => vec![],
+    };
+    if !profiles.iter().any(|p| p == DEFAULT_PROFILE) {
+      profiles.push(DEFAULT_PROFILE.to_string());
+    }
+    for profile in profiles {
+      let namespace = profile_namespace(mode_namespace, &profile);
+      entries.extend(SESSION_KEYS.iter().map(|key| (namespace.clone(), key.to_string())));
     }
   }
+  Ok(entries)
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -424,32 +600,21 @@ pub struct ErrorData {
   pub message: String,
 }
 
-async fn send_request<S, B>(uri: http::Uri, authorization: String, mut http: S) -> Vec<u8>
-where
-  S: Service<http::Request<B>, Response = http::Response<B>>,
-  S::Error: Debug,
-  B: http_body::Body<Error = S::Error> + Default + From<Vec<u8>> + Debug,
-{
-  let req = http::Request::get(uri)
-    .header(AUTHORIZATION, authorization)
-    .body(B::default())
-    .unwrap();
-
-  debug!("{:?}", req);
-  let resp = http.call(req).await.unwrap();
-  debug!("{:?}", resp);
-  if resp.status().as_u16() / 100 == 2 {
-    hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()
-  } else {
-    vec![]
-  }
-}
-
 #[cfg(test)]
 mod tests {
-  use std::net::TcpListener;
+  use std::sync::Arc;
+
+  use http::{Method, Request, Response};
+  use hyper::Body;
+
+  use anyhow::Result;
+  use secstr::SecUtf8;
 
-  use hyper::Client;
+  use super::{AuthorizationRequired, Session};
+  use crate::accounts::{self, BalanceRequest};
+  use crate::middleware::{Middleware, Next, UserAgent};
+  use crate::testing::{FakeServer, Fixtures};
+  use crate::{Memstore, Mode, Store};
 
   #[test]
   fn encodes_query_string() {
@@ -483,42 +648,206 @@ mod tests {
   }
 
   #[tokio::test]
-  async fn it_works() {
+  async fn non_interactive_session_requires_authorization() {
+    crate::tests::init();
+    let session = Session::new(Mode::Sandbox, Memstore::new()).non_interactive();
+    session.initialize("key".into(), "secret".into()).await.unwrap();
+
+    let err = session
+      .send::<_, _, serde_json::Value>(Method::GET, "/v1/accounts/list", crate::empty_body())
+      .await
+      .unwrap_err();
+    assert!(err.downcast_ref::<AuthorizationRequired>().is_some());
+  }
+
+  #[tokio::test]
+  async fn profiles_share_a_store() {
     crate::tests::init();
-    info!("inside the working test");
-    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
-    let client = Client::new();
-    let base_url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
-
-    let _th = tokio::task::spawn(async move { server::test_server(listener).await });
-    let uri: http::Uri = base_url.parse().unwrap();
-    let resp = client.get(uri).await.unwrap();
-    let body = String::from_utf8(hyper::body::to_bytes(resp.into_body()).await.unwrap().to_vec()).unwrap();
-
-    assert_eq!("Hello, world!", &body);
-  }
-
-  mod server {
-    use anyhow::{anyhow, Result};
-    use http::Response;
-    use hyper::service::{make_service_fn, service_fn};
-    use hyper::Body;
-    use hyper::Server;
-    use std::{convert::Infallible, net::TcpListener};
-
-    pub async fn test_server(listener: TcpListener) -> Result<()> {
-      let server = Server::from_tcp(listener)?;
-      let service = service_fn(|req| async move {
-        info!("{:?}", req);
-        Ok::<_, Infallible>(Response::new(Body::from("Hello, world!")))
+    let store = Arc::new(Memstore::new());
+    let alice = Session::new(Mode::Live, store.clone()).with_profile("alice");
+    let bob = Session::new(Mode::Live, store.clone()).with_profile("bob");
+    alice
+      .initialize("alice_key".into(), "alice_secret".into())
+      .await
+      .unwrap();
+    bob.initialize("bob_key".into(), "bob_secret".into()).await.unwrap();
+
+    assert_eq!(alice.consumer().await.unwrap().key.unsecure(), "alice_key");
+    assert_eq!(bob.consumer().await.unwrap().key.unsecure(), "bob_key");
+    assert_eq!(alice.profiles().await.unwrap(), vec!["alice", "bob"]);
+
+    let sandbox = Session::new(Mode::Sandbox, store.clone());
+    assert!(sandbox.profiles().await.unwrap().is_empty());
+
+    alice.remove().await.unwrap();
+    assert!(alice.consumer().await.is_err());
+    assert_eq!(bob.profiles().await.unwrap(), vec!["bob"]);
+
+    let default = Session::new(Mode::Live, store);
+    default.initialize("key".into(), "secret".into()).await.unwrap();
+    assert_eq!(default.consumer().await.unwrap().key.unsecure(), "key");
+    assert_eq!(bob.profiles().await.unwrap(), vec!["bob", "default"]);
+  }
+
+  /// Yields after every read, so concurrent registry updates interleave between reading and writing the profiles.
+  struct Yielding(Memstore);
+
+  #[async_trait::async_trait]
+  impl Store for Yielding {
+    async fn put(
+      &self,
+      namespace: impl Into<String> + Send,
+      key: impl Into<String> + Send,
+      value: impl Into<SecUtf8> + Send,
+    ) -> Result<()> {
+      self.0.put(namespace, key, value).await
+    }
+
+    async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+      self.0.del(namespace, key).await
+    }
+
+    async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+      let value = self.0.get(namespace, key).await;
+      tokio::task::yield_now().await;
+      value
+    }
+  }
+
+  #[tokio::test]
+  async fn registers_profiles_initialized_concurrently() {
+    let store = Arc::new(Yielding(Memstore::new()));
+    let alice = Session::new(Mode::Live, store.clone()).with_profile("alice");
+    let bob = Session::new(Mode::Live, store.clone()).with_profile("bob");
+    let (a, b) = tokio::join!(
+      alice.initialize("alice_key".into(), "alice_secret".into()),
+      bob.initialize("bob_key".into(), "bob_secret".into())
+    );
+    a.unwrap();
+    b.unwrap();
+    let mut profiles = alice.profiles().await.unwrap();
+    profiles.sort();
+    assert_eq!(profiles, vec!["alice", "bob"]);
+  }
+
+  /// Answers with the user agent and authorization scheme it received, instead of sending the request.
+  struct Echo;
+
+  #[async_trait::async_trait]
+  impl Middleware for Echo {
+    async fn handle(&self, req: Request<Body>, _next: Next<'_>) -> Result<Response<Body>> {
+      let body = serde_json::json!({
+        "userAgent": req.headers()["user-agent"].to_str()?,
+        "oauth": req.headers()["authorization"].to_str()?.starts_with("OAuth "),
       });
-      let make_service = make_service_fn(|_| async move { Ok::<_, Infallible>(service) });
-      server
-        .tcp_nodelay(true)
-        .tcp_keepalive(None)
-        .serve(make_service)
-        .await
-        .map_err(|e| anyhow!("{}", e))
+      Ok(
+        Response::builder()
+          .header("content-type", "application/json")
+          .body(serde_json::to_vec(&body)?.into())?,
+      )
     }
   }
+
+  #[tokio::test]
+  async fn sends_requests_through_the_middlewares() {
+    crate::tests::init();
+    let store = Memstore::new();
+    store.put("etradesandbox", "access_token_key", "token").await.unwrap();
+    store
+      .put("etradesandbox", "access_token_secret", "secret")
+      .await
+      .unwrap();
+    let session = Session::new(Mode::Sandbox, store)
+      .non_interactive()
+      .with_middleware(UserAgent::new("etrade-tests").unwrap())
+      .with_middleware(Echo);
+    session.initialize("key".into(), "secret".into()).await.unwrap();
+
+    let echoed: serde_json::Value = session
+      .send(Method::GET, "/v1/accounts/list", None as Option<()>)
+      .await
+      .unwrap();
+    assert_eq!(echoed, serde_json::json!({"userAgent": "etrade-tests", "oauth": true}));
+  }
+
+  /// Hides the listing support of the wrapped store.
+  struct Unlisted(Memstore);
+
+  #[async_trait::async_trait]
+  impl Store for Unlisted {
+    async fn put(
+      &self,
+      namespace: impl Into<String> + Send,
+      key: impl Into<String> + Send,
+      value: impl Into<SecUtf8> + Send,
+    ) -> Result<()> {
+      self.0.put(namespace, key, value).await
+    }
+
+    async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+      self.0.del(namespace, key).await
+    }
+
+    async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+      self.0.get(namespace, key).await
+    }
+  }
+
+  #[tokio::test]
+  async fn migrates_the_profiles_of_stores_without_listing() {
+    crate::tests::init();
+    let from = Arc::new(Unlisted(Memstore::new()));
+    Session::new(Mode::Live, from.clone())
+      .initialize("key".into(), "secret".into())
+      .await
+      .unwrap();
+    Session::new(Mode::Sandbox, from.clone())
+      .with_profile("alice")
+      .initialize("alice_key".into(), "alice_secret".into())
+      .await
+      .unwrap();
+    from.put("unrelated", "key", "value").await.unwrap();
+
+    let to = Memstore::new();
+    assert_eq!(crate::migrate(from.as_ref(), &to).await.unwrap(), 6);
+    let alice = Session::new(Mode::Sandbox, Arc::new(to)).with_profile("alice");
+    assert_eq!(alice.consumer().await.unwrap().key.unsecure(), "alice_key");
+    assert_eq!(alice.profiles().await.unwrap(), vec!["alice"]);
+  }
+
+  #[tokio::test]
+  async fn authorizes_against_the_fake_server() {
+    crate::tests::init();
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let store = Arc::new(Memstore::new());
+    let session = Arc::new(server.session(store.clone()).await.unwrap());
+    let api = accounts::Api::new(session.clone());
+
+    let accounts = api.list().await.unwrap();
+    assert_eq!(accounts[0].account_id, "12345678");
+    let token = store.get("etradesandbox", "access_token_key").await.unwrap().unwrap();
+
+    server.expire_tokens();
+    let balance = api
+      .balance(&accounts[0].account_id_key, BalanceRequest::default())
+      .await
+      .unwrap();
+    assert_eq!(balance.account_id, "12345678");
+    let renewed = store.get("etradesandbox", "access_token_key").await.unwrap().unwrap();
+    assert_ne!(
+      token.unsecure(),
+      renewed.unsecure(),
+      "authorizes again after the tokens expire"
+    );
+  }
+
+  #[tokio::test]
+  async fn rejects_unknown_consumers() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let session = Session::new(Mode::Sandbox, Memstore::new())
+      .with_base_url(server.url())
+      .with_callbacks(server.authorizer());
+    session.initialize("other".into(), "secret".into()).await.unwrap();
+    assert!(accounts::Api::new(Arc::new(session)).list().await.is_err());
+  }
 }
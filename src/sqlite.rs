@@ -0,0 +1,253 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use secstr::SecUtf8;
+
+use crate::crypto::{random_bytes, SecretKey, SALT_LEN};
+use crate::Store;
+
+const CHECK: &[u8] = b"etrade";
+
+/// A store backed by an SQLite database, the values are encrypted with a key derived from a passphrase.
+///
+/// SQLite takes care of locking, so the database can be shared between processes.
+#[derive(Clone)]
+pub struct SqliteStore {
+  path: PathBuf,
+  conn: Arc<Mutex<Connection>>,
+  key: Arc<SecretKey>,
+}
+
+impl std::fmt::Debug for SqliteStore {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("SqliteStore").field("path", &self.path).finish()
+  }
+}
+
+impl SqliteStore {
+  /// Opens or creates the database at `path`.
+  pub async fn open(path: impl Into<PathBuf>, passphrase: SecUtf8) -> Result<Self> {
+    let path = path.into();
+    tokio::task::spawn_blocking(move || {
+      let conn = Connection::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+      restrict_permissions(&path)?;
+      conn.busy_timeout(Duration::from_secs(10))?;
+      conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (name TEXT PRIMARY KEY, value BLOB NOT NULL);
+         CREATE TABLE IF NOT EXISTS secrets (
+           namespace TEXT NOT NULL,
+           key TEXT NOT NULL,
+           value BLOB NOT NULL,
+           PRIMARY KEY (namespace, key)
+         );",
+      )?;
+
+      // processes creating the database at the same time each offer a salt and a check, the first one wins and
+      // everyone reads it back
+      let salt: [u8; SALT_LEN] = random_bytes();
+      conn.execute(
+        "INSERT OR IGNORE INTO meta (name, value) VALUES ('salt', ?1)",
+        params![&salt[..]],
+      )?;
+      let salt = match meta(&conn, "salt")? {
+        Some(salt) if salt.len() == SALT_LEN => {
+          let mut buf = [0u8; SALT_LEN];
+          buf.copy_from_slice(&salt);
+          buf
+        }
+        _ => return Err(anyhow!("{} has a corrupted salt", path.display())),
+      };
+      let key = SecretKey::from_passphrase(passphrase.unsecure().as_bytes(), &salt)?;
+      conn.execute(
+        "INSERT OR IGNORE INTO meta (name, value) VALUES ('check', ?1)",
+        params![key.seal(CHECK)],
+      )?;
+      let check = meta(&conn, "check")?.ok_or_else(|| anyhow!("{} has no passphrase check", path.display()))?;
+      key
+        .open(&check)
+        .with_context(|| format!("wrong passphrase for {}", path.display()))?;
+
+      Ok(Self {
+        path,
+        conn: Arc::new(Mutex::new(conn)),
+        key: Arc::new(key),
+      })
+    })
+    .await?
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  async fn with_conn<R, F>(&self, f: F) -> Result<R>
+  where
+    R: Send + 'static,
+    F: FnOnce(&Connection, &SecretKey) -> Result<R> + Send + 'static,
+  {
+    let (conn, key) = (self.conn.clone(), self.key.clone());
+    tokio::task::spawn_blocking(move || f(&conn.lock().unwrap(), &key)).await?
+  }
+}
+
+fn meta(conn: &Connection, name: &str) -> Result<Option<Vec<u8>>> {
+  Ok(
+    conn
+      .query_row("SELECT value FROM meta WHERE name = ?1", params![name], |row| {
+        row.get(0)
+      })
+      .optional()?,
+  )
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) -> Result<()> {
+  use std::os::unix::fs::PermissionsExt;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+    .with_context(|| format!("failed to restrict the permissions of {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) -> Result<()> {
+  Ok(())
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+  async fn put(
+    &self,
+    namespace: impl Into<String> + Send,
+    key: impl Into<String> + Send,
+    value: impl Into<SecUtf8> + Send,
+  ) -> Result<()> {
+    let (namespace, key, value) = (namespace.into(), key.into(), value.into());
+    self
+      .with_conn(move |conn, secret| {
+        conn.execute(
+          "INSERT INTO secrets (namespace, key, value) VALUES (?1, ?2, ?3)
+           ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value",
+          params![namespace, key, secret.seal(value.unsecure().as_bytes())],
+        )?;
+        Ok(())
+      })
+      .await
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    let (namespace, key) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    self
+      .with_conn(move |conn, _| {
+        conn.execute(
+          "DELETE FROM secrets WHERE namespace = ?1 AND key = ?2",
+          params![namespace, key],
+        )?;
+        Ok(())
+      })
+      .await
+  }
+
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    let (namespace, key) = (namespace.as_ref().to_string(), key.as_ref().to_string());
+    self
+      .with_conn(move |conn, secret| {
+        let sealed: Option<Vec<u8>> = conn
+          .query_row(
+            "SELECT value FROM secrets WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+            |row| row.get(0),
+          )
+          .optional()?;
+        match sealed {
+          Some(sealed) => {
+            let plaintext = secret.open(&sealed)?;
+            Ok(Some(SecUtf8::from(std::str::from_utf8(plaintext.unsecure())?)))
+          }
+          None => Ok(None),
+        }
+      })
+      .await
+  }
+
+  async fn list(&self, namespace: impl AsRef<str> + Send) -> Result<Vec<String>> {
+    let namespace = namespace.as_ref().to_string();
+    self
+      .with_conn(move |conn, _| {
+        let mut stmt = conn.prepare("SELECT key FROM secrets WHERE namespace = ?1 ORDER BY key")?;
+        let keys = stmt
+          .query_map(params![namespace], |row| row.get(0))?
+          .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(keys)
+      })
+      .await
+  }
+
+  async fn namespaces(&self) -> Result<Vec<String>> {
+    self
+      .with_conn(|conn, _| {
+        let mut stmt = conn.prepare("SELECT DISTINCT namespace FROM secrets ORDER BY namespace")?;
+        let namespaces = stmt
+          .query_map([], |row| row.get(0))?
+          .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(namespaces)
+      })
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::SqliteStore;
+  use crate::Store;
+
+  #[tokio::test]
+  async fn test_sqlite_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = SqliteStore::open(dir.path().join("secrets.db"), "passphrase".into())
+      .await
+      .unwrap();
+    crate::testing::verify_store(store.clone()).await;
+    crate::testing::verify_listing_store(store).await;
+  }
+
+  #[tokio::test]
+  async fn persists_encrypted_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.db");
+    let store = SqliteStore::open(&path, "passphrase".into()).await.unwrap();
+    store.put("etrade", "apikey", "the-consumer-key").await.unwrap();
+    store.put("etrade", "apikey", "the-rotated-key").await.unwrap();
+    drop(store);
+
+    let raw = std::fs::read(&path).unwrap();
+    assert!(!raw.windows(15).any(|w| w == b"the-rotated-key"));
+
+    let reopened = SqliteStore::open(&path, "passphrase".into()).await.unwrap();
+    assert_eq!(
+      reopened.get("etrade", "apikey").await.unwrap().unwrap().unsecure(),
+      "the-rotated-key"
+    );
+    assert!(SqliteStore::open(&path, "wrong".into()).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn creates_the_database_from_concurrent_opens() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secrets.db");
+    let opens = (0..4).map(|_| SqliteStore::open(path.clone(), "passphrase".into()));
+    let stores: Vec<SqliteStore> = futures::future::try_join_all(opens).await.unwrap();
+
+    stores[0].put("etrade", "apikey", "the-consumer-key").await.unwrap();
+    for store in &stores {
+      assert_eq!(
+        store.get("etrade", "apikey").await.unwrap().unwrap().unsecure(),
+        "the-consumer-key"
+      );
+    }
+  }
+}
@@ -0,0 +1,644 @@
+//! Option positions grouped into strategies.
+//!
+//! [`Leg::from_position`] reads an option position of the portfolio, with the Greeks and underlying price of its
+//! complete or options watch view. A [`Dashboard`] groups the legs of every underlying into the strategies it
+//! recognizes: iron condors, verticals, straddles, strangles and covered calls, splitting legs when the quantities
+//! don't match. What doesn't fit is left as single legs. Every [`Strategy`] has its net Greeks and, when they are
+//! bounded, its maximum profit and loss from the prices paid. Short options in the money close to expiration are
+//! flagged as an [`AssignmentRisk`].
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::NaiveDate;
+
+use crate::accounts::{PortfolioPosition, PortfolioRequest, PortfolioView, PositionView};
+use crate::clients::AccountsClient;
+use crate::{OptionType, SecurityType};
+
+/// The shares of a standard contract.
+const MULTIPLIER: f64 = 100.0;
+
+/// An option position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg {
+  pub position_id: i64,
+  pub underlying: String,
+  pub option_type: OptionType,
+  pub strike: f64,
+  pub expiry: NaiveDate,
+  /// The contracts, negative when short.
+  pub quantity: f64,
+  pub multiplier: f64,
+  /// The premium per share paid, or received for a short leg.
+  pub price_paid: f64,
+  /// The current premium per share.
+  pub price: f64,
+  pub total_gain: f64,
+  /// The Greeks of one share, zero without a view that has them.
+  pub delta: f64,
+  pub gamma: f64,
+  pub theta: f64,
+  pub vega: f64,
+  pub underlying_price: Option<f64>,
+}
+
+impl Leg {
+  /// Reads an option position, `None` for other positions.
+  pub fn from_position(position: &PortfolioPosition) -> Option<Self> {
+    let product = &position.product;
+    if !matches!(product.security_type, Some(SecurityType::Optn)) {
+      return None;
+    }
+    let option_type = match product.call_put.to_ascii_uppercase().as_str() {
+      "CALL" => OptionType::Call,
+      "PUT" => OptionType::Put,
+      _ => return None,
+    };
+    let expiry = NaiveDate::from_ymd_opt(
+      product.expiry_year,
+      product.expiry_month as u32,
+      product.expiry_day as u32,
+    )?;
+    let quantity = if position.position_type.eq_ignore_ascii_case("SHORT") {
+      -position.quantity.abs()
+    } else {
+      position.quantity
+    };
+
+    let mut leg = Leg {
+      position_id: position.position_id,
+      underlying: product.symbol.clone(),
+      option_type,
+      strike: product.strike_price,
+      expiry,
+      quantity,
+      multiplier: MULTIPLIER,
+      price_paid: position.price_paid,
+      price: position.price,
+      total_gain: position.total_gain,
+      delta: 0.0,
+      gamma: 0.0,
+      theta: 0.0,
+      vega: 0.0,
+      underlying_price: None,
+    };
+    match &position.view {
+      Some(PositionView::Complete(view)) => {
+        leg.delta = view.delta;
+        leg.gamma = view.gamma;
+        leg.theta = view.theta;
+        leg.vega = view.vega;
+        if view.option_multiplier > 0.0 {
+          leg.multiplier = view.option_multiplier;
+        }
+        leg.underlying_price = base_price(&view.base_symbol_and_price);
+      }
+      Some(PositionView::OptionsWatch(view)) => leg.underlying_price = base_price(&view.base_symbol_and_price),
+      _ => {}
+    }
+    Some(leg)
+  }
+
+  pub fn is_short(&self) -> bool {
+    self.quantity < 0.0
+  }
+
+  /// The value per share of exercising the option, `None` without the underlying price.
+  pub fn intrinsic_value(&self) -> Option<f64> {
+    let price = self.underlying_price?;
+    Some(match self.option_type {
+      OptionType::Call => (price - self.strike).max(0.0),
+      OptionType::Put => (self.strike - price).max(0.0),
+    })
+  }
+
+  pub fn days_to_expiration(&self, today: NaiveDate) -> i64 {
+    (self.expiry - today).num_days()
+  }
+
+  fn is_call(&self) -> bool {
+    matches!(self.option_type, OptionType::Call)
+  }
+
+  /// The shares the leg stands for, negative when short.
+  fn shares(&self) -> f64 {
+    self.quantity * self.multiplier
+  }
+
+  /// The part of the leg with `contracts` contracts, of the same side.
+  fn part(&self, contracts: f64) -> Leg {
+    let ratio = if self.quantity != 0.0 {
+      contracts / self.quantity.abs()
+    } else {
+      0.0
+    };
+    Leg {
+      quantity: contracts * self.quantity.signum(),
+      total_gain: self.total_gain * ratio,
+      ..self.clone()
+    }
+  }
+}
+
+/// The value per share after the base symbol in views such as `AAPL 150.25`.
+fn base_price(base_symbol_and_price: &str) -> Option<f64> {
+  base_symbol_and_price.split_whitespace().last()?.parse().ok()
+}
+
+/// Shares of an underlying that cover short calls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StockLeg {
+  pub symbol: String,
+  pub quantity: f64,
+  /// The cost per share.
+  pub cost: f64,
+  pub price: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyKind {
+  /// A long or short call or put.
+  Single,
+  /// Short calls covered by shares.
+  CoveredCall,
+  /// A long and a short option of the same type and expiry.
+  Vertical,
+  /// A call and a put of the same side, strike and expiry.
+  Straddle,
+  /// A call and a put of the same side and expiry, at different strikes.
+  Strangle,
+  /// A short put and call spread of the same expiry, with the calls above the puts.
+  IronCondor,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Strategy {
+  pub kind: StrategyKind,
+  pub underlying: String,
+  pub legs: Vec<Leg>,
+  /// The shares of a covered call.
+  pub stock: Option<StockLeg>,
+}
+
+impl Strategy {
+  /// The delta in shares.
+  pub fn net_delta(&self) -> f64 {
+    self.legs.iter().map(|l| l.delta * l.shares()).sum::<f64>() + self.stock.as_ref().map_or(0.0, |s| s.quantity)
+  }
+
+  pub fn net_gamma(&self) -> f64 {
+    self.legs.iter().map(|l| l.gamma * l.shares()).sum()
+  }
+
+  /// The dollars gained or lost per day.
+  pub fn net_theta(&self) -> f64 {
+    self.legs.iter().map(|l| l.theta * l.shares()).sum()
+  }
+
+  /// The dollars gained or lost per point of implied volatility.
+  pub fn net_vega(&self) -> f64 {
+    self.legs.iter().map(|l| l.vega * l.shares()).sum()
+  }
+
+  /// The profit or loss so far, stock included.
+  pub fn pnl(&self) -> f64 {
+    self.legs.iter().map(|l| l.total_gain).sum::<f64>()
+      + self.stock.as_ref().map_or(0.0, |s| (s.price - s.cost) * s.quantity)
+  }
+
+  /// The premium paid per share for one contract of every leg, negative for a credit.
+  pub fn net_debit(&self) -> f64 {
+    let contracts = self.contracts();
+    if contracts == 0.0 {
+      return 0.0;
+    }
+    self.legs.iter().map(|l| l.price_paid * l.quantity).sum::<f64>() / contracts
+  }
+
+  /// The maximum profit at expiration, `None` when it is unlimited.
+  pub fn max_profit(&self) -> Option<f64> {
+    self.bounds().0
+  }
+
+  /// The maximum loss at expiration as a positive amount, `None` when it is unlimited.
+  pub fn max_loss(&self) -> Option<f64> {
+    self.bounds().1
+  }
+
+  fn contracts(&self) -> f64 {
+    self
+      .legs
+      .iter()
+      .map(|l| l.quantity.abs())
+      .reduce(f64::min)
+      .unwrap_or_default()
+  }
+
+  fn bounds(&self) -> (Option<f64>, Option<f64>) {
+    let size = self.contracts() * self.legs.first().map_or(MULTIPLIER, |l| l.multiplier);
+    let debit = self.net_debit();
+    let strikes: Vec<f64> = self.legs.iter().map(|l| l.strike).collect();
+    let width = |a: f64, b: f64| (a - b).abs();
+    match self.kind {
+      StrategyKind::Single => {
+        let leg = &self.legs[0];
+        match (leg.is_short(), leg.is_call()) {
+          (false, true) => (None, Some(debit * size)),
+          (false, false) => (Some((leg.strike - debit) * size), Some(debit * size)),
+          (true, true) => (Some(-debit * size), None),
+          (true, false) => (Some(-debit * size), Some((leg.strike + debit) * size)),
+        }
+      }
+      StrategyKind::Vertical => {
+        let w = width(strikes[0], strikes[1]);
+        if debit > 0.0 {
+          (Some((w - debit) * size), Some(debit * size))
+        } else {
+          (Some(-debit * size), Some((w + debit) * size))
+        }
+      }
+      StrategyKind::IronCondor => {
+        let w = width(strikes[0], strikes[1]).max(width(strikes[2], strikes[3]));
+        (Some(-debit * size), Some((w + debit) * size))
+      }
+      StrategyKind::Straddle | StrategyKind::Strangle => {
+        if self.legs[0].is_short() {
+          (Some(-debit * size), None)
+        } else {
+          (None, Some(debit * size))
+        }
+      }
+      StrategyKind::CoveredCall => {
+        let (call, stock) = (&self.legs[0], self.stock.as_ref().unwrap());
+        let shares = call.shares().abs();
+        (
+          Some((call.strike - stock.cost - debit) * shares),
+          Some((stock.cost + debit) * shares),
+        )
+      }
+    }
+  }
+}
+
+/// A short option in the money close to expiration, which can be assigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssignmentRisk {
+  pub leg: Leg,
+  pub intrinsic_value: f64,
+  pub days_to_expiration: i64,
+}
+
+/// The option strategies of a portfolio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dashboard {
+  pub strategies: Vec<Strategy>,
+  pub assignment_risks: Vec<AssignmentRisk>,
+}
+
+impl Dashboard {
+  /// Groups the option positions, short options in the money that expire within `risk_days` of today are flagged.
+  pub fn new(positions: &[PortfolioPosition], today: NaiveDate, risk_days: i64) -> Self {
+    let legs: Vec<Leg> = positions.iter().filter_map(Leg::from_position).collect();
+    let mut stocks: BTreeMap<String, StockLeg> = BTreeMap::new();
+    for position in positions {
+      if matches!(position.product.security_type, Some(SecurityType::Optn)) || position.quantity <= 0.0 {
+        continue;
+      }
+      if position.position_type.eq_ignore_ascii_case("SHORT") {
+        continue;
+      }
+      stocks.insert(
+        position.product.symbol.clone(),
+        StockLeg {
+          symbol: position.product.symbol.clone(),
+          quantity: position.quantity,
+          cost: position.cost_per_share,
+          price: position.price,
+        },
+      );
+    }
+
+    let mut by_underlying: BTreeMap<String, Vec<Leg>> = BTreeMap::new();
+    for leg in &legs {
+      by_underlying
+        .entry(leg.underlying.clone())
+        .or_default()
+        .push(leg.clone());
+    }
+    let mut strategies = vec![];
+    for (underlying, legs) in by_underlying {
+      strategies.extend(group(&underlying, legs, stocks.get(&underlying).cloned()));
+    }
+
+    let assignment_risks = legs
+      .iter()
+      .filter(|l| l.is_short())
+      .filter_map(|leg| {
+        let days = leg.days_to_expiration(today);
+        let intrinsic_value = leg.intrinsic_value()?;
+        (intrinsic_value > 0.0 && (0..=risk_days).contains(&days)).then(|| AssignmentRisk {
+          leg: leg.clone(),
+          intrinsic_value,
+          days_to_expiration: days,
+        })
+      })
+      .collect();
+
+    Self {
+      strategies,
+      assignment_risks,
+    }
+  }
+
+  /// Lists the portfolio with the complete view, which has the Greeks, and groups it.
+  pub async fn load(
+    accounts: &dyn AccountsClient,
+    account_id_key: &str,
+    today: NaiveDate,
+    risk_days: i64,
+  ) -> Result<Self> {
+    let portfolio = accounts
+      .portfolio_all(
+        account_id_key,
+        PortfolioRequest {
+          view: Some(PortfolioView::Complete),
+          ..Default::default()
+        },
+      )
+      .await?;
+    let positions: Vec<PortfolioPosition> = portfolio
+      .account_portfolio
+      .into_iter()
+      .flat_map(|p| p.position)
+      .collect();
+    Ok(Self::new(&positions, today, risk_days))
+  }
+
+  /// The net delta, theta and vega of every underlying.
+  pub fn greeks_by_underlying(&self) -> BTreeMap<String, (f64, f64, f64)> {
+    let mut greeks: BTreeMap<String, (f64, f64, f64)> = BTreeMap::new();
+    for strategy in &self.strategies {
+      let entry = greeks.entry(strategy.underlying.clone()).or_default();
+      entry.0 += strategy.net_delta();
+      entry.1 += strategy.net_theta();
+      entry.2 += strategy.net_vega();
+    }
+    greeks
+  }
+}
+
+/// Takes `contracts` contracts of the leg at `index` out of the pool, dropping it once it is used up.
+fn take(pool: &mut Vec<Leg>, index: usize, contracts: f64) -> Leg {
+  let part = pool[index].part(contracts);
+  let rest = pool[index].quantity.abs() - contracts;
+  if rest <= 0.0 {
+    pool.remove(index);
+  } else {
+    pool[index] = pool[index].part(rest);
+  }
+  part
+}
+
+/// Takes the legs at the indexes out of the pool, as many contracts as the smallest of them has.
+fn take_all(pool: &mut Vec<Leg>, mut indexes: Vec<usize>) -> Vec<Leg> {
+  let contracts = indexes
+    .iter()
+    .map(|i| pool[*i].quantity.abs())
+    .fold(f64::INFINITY, f64::min);
+  let order = indexes.clone();
+  // remove from the back so the indexes stay valid
+  indexes.sort_unstable_by(|a, b| b.cmp(a));
+  let mut taken: BTreeMap<usize, Leg> = BTreeMap::new();
+  for index in indexes {
+    taken.insert(index, take(pool, index, contracts));
+  }
+  order.iter().map(|i| taken.remove(i).unwrap()).collect()
+}
+
+fn group(underlying: &str, mut pool: Vec<Leg>, mut stock: Option<StockLeg>) -> Vec<Strategy> {
+  let strategy = |kind, legs| Strategy {
+    kind,
+    underlying: underlying.to_string(),
+    legs,
+    stock: None,
+  };
+  let mut strategies = vec![];
+
+  // iron condors: long put < short put <= short call < long call
+  while let Some(indexes) = find_condor(&pool) {
+    strategies.push(strategy(
+      StrategyKind::IronCondor,
+      take_all(&mut pool, indexes.to_vec()),
+    ));
+  }
+  // verticals
+  while let Some((a, b)) = find_pair(&pool, |a, b| {
+    a.option_type == b.option_type && a.expiry == b.expiry && !a.is_short() && b.is_short() && a.strike != b.strike
+  }) {
+    strategies.push(strategy(StrategyKind::Vertical, take_all(&mut pool, vec![a, b])));
+  }
+  // straddles and strangles
+  while let Some((a, b)) = find_pair(&pool, |a, b| {
+    a.is_call() && !b.is_call() && a.expiry == b.expiry && a.is_short() == b.is_short()
+  }) {
+    let kind = if pool[a].strike == pool[b].strike {
+      StrategyKind::Straddle
+    } else {
+      StrategyKind::Strangle
+    };
+    strategies.push(strategy(kind, take_all(&mut pool, vec![a, b])));
+  }
+  // covered calls
+  while let Some(shares) = stock.as_mut().filter(|s| s.quantity >= MULTIPLIER) {
+    let Some(index) = pool.iter().position(|l| l.is_call() && l.is_short()) else {
+      break;
+    };
+    let contracts = (shares.quantity / pool[index].multiplier)
+      .floor()
+      .min(pool[index].quantity.abs());
+    if contracts <= 0.0 {
+      break;
+    }
+    let call = take(&mut pool, index, contracts);
+    let covered = StockLeg {
+      quantity: call.shares().abs(),
+      ..shares.clone()
+    };
+    shares.quantity -= covered.quantity;
+    strategies.push(Strategy {
+      stock: Some(covered),
+      ..strategy(StrategyKind::CoveredCall, vec![call])
+    });
+  }
+  strategies.extend(pool.into_iter().map(|leg| strategy(StrategyKind::Single, vec![leg])));
+  strategies
+}
+
+fn find_pair(pool: &[Leg], matches: impl Fn(&Leg, &Leg) -> bool) -> Option<(usize, usize)> {
+  for (i, a) in pool.iter().enumerate() {
+    for (j, b) in pool.iter().enumerate() {
+      if i != j && matches(a, b) {
+        return Some((i, j));
+      }
+    }
+  }
+  None
+}
+
+fn find_condor(pool: &[Leg]) -> Option<[usize; 4]> {
+  let (calls, puts): (Vec<usize>, Vec<usize>) = (0..pool.len()).partition(|i| pool[*i].is_call());
+  for &short_put in puts.iter().filter(|i| pool[**i].is_short()) {
+    let expiry = pool[short_put].expiry;
+    let same = |i: &&usize| pool[**i].expiry == expiry;
+    for &long_put in puts.iter().filter(same).filter(|i| !pool[**i].is_short()) {
+      if pool[long_put].strike >= pool[short_put].strike {
+        continue;
+      }
+      for &short_call in calls.iter().filter(same).filter(|i| pool[**i].is_short()) {
+        if pool[short_call].strike < pool[short_put].strike {
+          continue;
+        }
+        for &long_call in calls.iter().filter(same).filter(|i| !pool[**i].is_short()) {
+          if pool[long_call].strike > pool[short_call].strike {
+            return Some([long_put, short_put, short_call, long_call]);
+          }
+        }
+      }
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+
+  use super::{Dashboard, StrategyKind};
+  use crate::accounts::{AccountPortfolio, CompleteView, PortfolioPosition, PortfolioResponse, PositionView};
+  use crate::testing::MockAccounts;
+  use crate::{Product, SecurityType};
+
+  fn option(symbol: &str, call_put: &str, strike: f64, quantity: f64, price_paid: f64) -> PortfolioPosition {
+    PortfolioPosition {
+      product: Product {
+        symbol: symbol.to_string(),
+        security_type: Some(SecurityType::Optn),
+        call_put: call_put.to_string(),
+        strike_price: strike,
+        expiry_year: 2024,
+        expiry_month: 7,
+        expiry_day: 19,
+        ..Default::default()
+      },
+      quantity: quantity.abs(),
+      position_type: if quantity < 0.0 { "SHORT" } else { "LONG" }.to_string(),
+      price_paid,
+      ..Default::default()
+    }
+  }
+
+  fn with_greeks(mut position: PortfolioPosition, delta: f64, theta: f64, base: &str) -> PortfolioPosition {
+    position.view = Some(PositionView::Complete(Box::new(CompleteView {
+      delta,
+      theta,
+      base_symbol_and_price: base.to_string(),
+      ..Default::default()
+    })));
+    position
+  }
+
+  #[test]
+  fn groups_legs_into_strategies() {
+    let positions = vec![
+      PortfolioPosition {
+        product: Product {
+          symbol: "AAPL".to_string(),
+          security_type: Some(SecurityType::Eq),
+          ..Default::default()
+        },
+        quantity: 150.0,
+        position_type: "LONG".to_string(),
+        cost_per_share: 150.0,
+        price: 165.0,
+        ..Default::default()
+      },
+      with_greeks(option("AAPL", "CALL", 160.0, -1.0, 3.0), 0.7, 0.05, "AAPL 165.00"),
+      option("MSFT", "PUT", 280.0, 1.0, 1.0),
+      option("MSFT", "PUT", 290.0, -1.0, 2.5),
+      option("MSFT", "CALL", 330.0, -1.0, 2.5),
+      option("MSFT", "CALL", 340.0, 1.0, 1.0),
+      option("TSLA", "CALL", 200.0, 3.0, 10.0),
+      option("TSLA", "CALL", 210.0, -2.0, 6.0),
+      option("SPY", "CALL", 400.0, 2.0, 5.0),
+      option("SPY", "PUT", 400.0, 2.0, 5.0),
+    ];
+    let dashboard = Dashboard::new(&positions, NaiveDate::from_ymd_opt(2024, 7, 16).unwrap(), 5);
+    let kinds: Vec<_> = dashboard
+      .strategies
+      .iter()
+      .map(|s| (s.underlying.as_str(), s.kind))
+      .collect();
+    assert_eq!(
+      kinds,
+      vec![
+        ("AAPL", StrategyKind::CoveredCall),
+        ("MSFT", StrategyKind::IronCondor),
+        ("SPY", StrategyKind::Straddle),
+        ("TSLA", StrategyKind::Vertical),
+        ("TSLA", StrategyKind::Single),
+      ]
+    );
+    let bounds: Vec<_> = dashboard
+      .strategies
+      .iter()
+      .map(|s| (s.max_profit(), s.max_loss()))
+      .collect();
+    assert_eq!(
+      bounds,
+      vec![
+        (Some(1_300.0), Some(14_700.0)),
+        (Some(300.0), Some(700.0)),
+        (None, Some(2_000.0)),
+        (Some(1_200.0), Some(800.0)),
+        (None, Some(1_000.0)),
+      ]
+    );
+
+    // 100 covered shares against a short call of delta 0.7
+    let covered = &dashboard.strategies[0];
+    assert!((covered.net_delta() - 30.0).abs() < 1e-9);
+    assert!((covered.net_theta() + 5.0).abs() < 1e-9);
+    assert_eq!(dashboard.greeks_by_underlying()["AAPL"].0, covered.net_delta());
+
+    assert_eq!(dashboard.assignment_risks.len(), 1);
+    let risk = &dashboard.assignment_risks[0];
+    assert_eq!((risk.leg.strike, risk.days_to_expiration), (160.0, 3));
+    assert_eq!(risk.intrinsic_value, 5.0);
+  }
+
+  #[tokio::test]
+  async fn loads_the_complete_view() {
+    let accounts = MockAccounts::new().on_portfolio(Ok(PortfolioResponse {
+      account_portfolio: vec![AccountPortfolio {
+        position: vec![
+          with_greeks(option("AAPL", "PUT", 150.0, -2.0, 4.0), -0.3, 0.04, "AAPL 148.00"),
+          option("AAPL", "PUT", 140.0, 2.0, 1.5),
+        ],
+        ..Default::default()
+      }],
+      ..Default::default()
+    }));
+    let today = NaiveDate::from_ymd_opt(2024, 7, 1).unwrap();
+    let dashboard = Dashboard::load(&accounts, "key", today, 5).await.unwrap();
+    assert_eq!(accounts.calls()[0].args["params"]["view"], "COMPLETE");
+
+    let spread = &dashboard.strategies[0];
+    assert_eq!(spread.kind, StrategyKind::Vertical);
+    assert!((spread.net_debit() + 2.5).abs() < 1e-9);
+    assert_eq!((spread.max_profit(), spread.max_loss()), (Some(500.0), Some(1_500.0)));
+    assert!((spread.net_delta() - 60.0).abs() < 1e-9);
+    // in the money but 18 days out
+    assert!(dashboard.assignment_risks.is_empty());
+  }
+}
@@ -0,0 +1,82 @@
+//! Helpers to test code built on this crate, enabled with the `testing` feature.
+//!
+//! [`verify_store`] and [`verify_listing_store`] check that a [`Store`] implementation honours the semantics the
+//! session relies on, third-party stores can run them from their own tests. [`FakeServer`] serves the E*Trade api
+//! from [`Fixtures`] on a local port, to run end to end tests without the sandbox. The `Mock*` clients answer the
+//! traits of [`crate::clients`] with scripted responses, for unit tests without any http.
+
+use crate::Store;
+
+mod fake_server;
+mod mocks;
+
+pub use fake_server::{FakeServer, Fixtures};
+pub use mocks::{Call, MockAccounts, MockAlerts, MockMarket, MockOrders, MockTransactions};
+
+const NAMESPACE: &str = "etrade-conformance";
+const OTHER_NAMESPACE: &str = "etrade-conformance:other";
+
+/// Checks the basic contract of a store: `put` inserts or replaces, `get` returns `None` for missing keys and `del`
+/// succeeds whether the key exists or not.
+///
+/// The keys live in namespaces starting with `etrade-conformance`, they are removed again when the checks pass.
+pub async fn verify_store(store: impl Store) {
+  assert!(
+    store.get(NAMESPACE, "missing").await.unwrap().is_none(),
+    "get of a missing key returns None"
+  );
+
+  store.put(NAMESPACE, "api_key", "hello").await.unwrap();
+  assert_eq!(
+    store.get(NAMESPACE, "api_key").await.unwrap().unwrap().unsecure(),
+    "hello"
+  );
+
+  store.put(NAMESPACE, "api_key", "héllo wörld").await.unwrap();
+  assert_eq!(
+    store.get(NAMESPACE, "api_key").await.unwrap().unwrap().unsecure(),
+    "héllo wörld",
+    "put replaces an existing value"
+  );
+
+  store.put(OTHER_NAMESPACE, "api_key", "other").await.unwrap();
+  assert_eq!(
+    store.get(NAMESPACE, "api_key").await.unwrap().unwrap().unsecure(),
+    "héllo wörld",
+    "namespaces are isolated"
+  );
+  assert!(store.get(NAMESPACE, "api").await.unwrap().is_none());
+
+  store.del(NAMESPACE, "api_key").await.unwrap();
+  assert!(store.get(NAMESPACE, "api_key").await.unwrap().is_none());
+  assert_eq!(
+    store.get(OTHER_NAMESPACE, "api_key").await.unwrap().unwrap().unsecure(),
+    "other",
+    "del only removes the key of its namespace"
+  );
+  store
+    .del(NAMESPACE, "api_key")
+    .await
+    .expect("del of a missing key succeeds");
+
+  store.del(OTHER_NAMESPACE, "api_key").await.unwrap();
+}
+
+/// Checks `list` and `namespaces`, the store needs to be empty.
+pub async fn verify_listing_store(store: impl Store + Sync) {
+  assert!(store.namespaces().await.unwrap().is_empty());
+  store.put(NAMESPACE, "b", "1").await.unwrap();
+  store.put(NAMESPACE, "a", "2").await.unwrap();
+  store.put(NAMESPACE, "a", "3").await.unwrap();
+  store.put(OTHER_NAMESPACE, "c", "4").await.unwrap();
+  assert_eq!(store.list(NAMESPACE).await.unwrap(), vec!["a", "b"]);
+  assert_eq!(store.namespaces().await.unwrap(), vec![NAMESPACE, OTHER_NAMESPACE]);
+
+  store.del(OTHER_NAMESPACE, "c").await.unwrap();
+  assert_eq!(store.namespaces().await.unwrap(), vec![NAMESPACE]);
+  assert!(store.list("missing").await.unwrap().is_empty());
+
+  store.del(NAMESPACE, "a").await.unwrap();
+  store.del(NAMESPACE, "b").await.unwrap();
+  assert!(store.namespaces().await.unwrap().is_empty());
+}
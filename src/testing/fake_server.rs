@@ -0,0 +1,1305 @@
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  convert::Infallible,
+  net::{SocketAddr, TcpListener},
+  path::Path,
+  sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use http::{
+  header::{AUTHORIZATION, CONTENT_TYPE},
+  request::Parts,
+  Method, Request, Response, StatusCode,
+};
+use hyper::{
+  service::{make_service_fn, service_fn},
+  Body, Server,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::oneshot;
+
+use crate::accounts::{
+  Account, AccountMode, AccountPortfolio, AccountStatus, AccountType, BalanceResponse, ComputedBalance,
+  InstitutionType, PortfolioPosition, PortfolioResponse, PositionLot, PositionLotsResponse, RealTimeValues,
+};
+use crate::alerts::{Alert, AlertDetailsResponse, AlertsResponse, DeleteAlertsResponse, FailedAlerts, Status};
+use crate::options::{AllQuoteDetails, OptionChainPair, OptionChainResponse, OptionDetails, QuoteData, QuoteResponse};
+use crate::orders::{
+  CancelOrderRequest, CancelOrderResponse, Event, EventName, Order, OrderDetail, OrderId, OrderStatus,
+  PlaceOrderRequest, PlaceOrderResponse, PreviewId, PreviewOrderRequest, PreviewOrderResponse,
+};
+use crate::transactions::{TransactionDetailsResponse, TransactionListResponse};
+use crate::{CallbackProvider, Message, MessageType, Messages, Mode, Product, SecurityType, Session, Store};
+
+/// The data a [`FakeServer`] starts from, it changes as orders get placed and alerts read or deleted.
+///
+/// The maps are keyed by the `accountIdKey` of the account, the option chains by the symbol of the underlying.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Fixtures {
+  pub consumer_key: String,
+  pub consumer_secret: String,
+  /// What the authorize page hands out as verifier code.
+  pub verifier: String,
+  pub accounts: Vec<Account>,
+  pub balances: BTreeMap<String, BalanceResponse>,
+  pub portfolios: BTreeMap<String, PortfolioResponse>,
+  pub orders: BTreeMap<String, Vec<Order>>,
+  pub quotes: Vec<QuoteData>,
+  pub option_chains: BTreeMap<String, OptionChainResponse>,
+  pub alerts: Vec<AlertDetailsResponse>,
+  pub transactions: BTreeMap<String, Vec<TransactionDetailsResponse>>,
+}
+
+impl Default for Fixtures {
+  fn default() -> Self {
+    Self {
+      consumer_key: "fake-consumer-key".to_string(),
+      consumer_secret: "fake-consumer-secret".to_string(),
+      verifier: "fake-verifier".to_string(),
+      accounts: vec![],
+      balances: BTreeMap::default(),
+      portfolios: BTreeMap::default(),
+      orders: BTreeMap::default(),
+      quotes: vec![],
+      option_chains: BTreeMap::default(),
+      alerts: vec![],
+      transactions: BTreeMap::default(),
+    }
+  }
+}
+
+impl Fixtures {
+  /// Reads fixtures from a JSON file when the extension is `.json` and from YAML otherwise.
+  pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+    let path = path.as_ref();
+    let content = std::fs::read(path).with_context(|| format!("failed to read the fixtures {}", path.display()))?;
+    let fixtures = if path.extension().map(|ext| ext == "json").unwrap_or(false) {
+      serde_json::from_slice(&content)?
+    } else {
+      serde_yaml::from_slice(&content)?
+    };
+    Ok(fixtures)
+  }
+
+  /// A brokerage account holding 10 AAPL with $10,000 in cash, quotes for AAPL and MSFT, an AAPL option chain, two
+  /// alerts and a transaction.
+  pub fn sample() -> Self {
+    let account_id = "12345678".to_string();
+    let key = "fake-account-key".to_string();
+    let aapl = Product {
+      symbol: "AAPL".to_string(),
+      security_type: Some(SecurityType::Eq),
+      ..Default::default()
+    };
+    let now = Utc::now().timestamp();
+
+    Self {
+      accounts: vec![Account {
+        inst_no: Some(1),
+        account_id: account_id.clone(),
+        account_id_key: key.clone(),
+        account_mode: AccountMode::Cash,
+        account_desc: "Brokerage".to_string(),
+        account_name: "Fake brokerage".to_string(),
+        account_type: AccountType::Individual,
+        institution_type: InstitutionType::Brokerage,
+        account_status: AccountStatus::Active,
+        closed_date: None,
+      }],
+      balances: BTreeMap::from([(
+        key.clone(),
+        BalanceResponse {
+          account_id: account_id.clone(),
+          institution_type: Some("BROKERAGE".to_string()),
+          account_type: "INDIVIDUAL".to_string(),
+          account_description: "Brokerage".to_string(),
+          computed_balance: ComputedBalance {
+            cash_available_for_investment: 10_000.0,
+            cash_available_for_withdrawal: 10_000.0,
+            net_cash: 10_000.0,
+            cash_balance: 10_000.0,
+            settled_cash_for_investment: 10_000.0,
+            cash_buying_power: Some(10_000.0),
+            real_time_values: RealTimeValues {
+              total_account_value: 11_500.0,
+              net_mv: 1_500.0,
+              net_mv_long: 1_500.0,
+              ..Default::default()
+            },
+            ..Default::default()
+          },
+          ..Default::default()
+        },
+      )]),
+      portfolios: BTreeMap::from([(
+        key.clone(),
+        PortfolioResponse {
+          totals: None,
+          account_portfolio: vec![AccountPortfolio {
+            account_id: account_id.clone(),
+            total_no_of_pages: 1,
+            position: vec![PortfolioPosition {
+              position_id: 1,
+              account_id: account_id.clone(),
+              product: aapl.clone(),
+              symbol_description: "AAPL".to_string(),
+              date_acquired: Utc.timestamp_opt(now, 0).single(),
+              price_paid: 120.0,
+              price: 150.0,
+              quantity: 10.0,
+              position_type: "LONG".to_string(),
+              market_value: 1_500.0,
+              total_cost: 1_200.0,
+              total_gain: 300.0,
+              total_gain_pct: 25.0,
+              pct_of_portfolio: 100.0,
+              cost_per_share: 120.0,
+              position_lot: vec![PositionLot {
+                position_id: 1,
+                position_log_id: 1,
+                price: 120.0,
+                remaining_qty: 10.0,
+                original_qty: 10.0,
+                acquired_date: now * 1000,
+                market_value: 1_500.0,
+                total_cost: 1_200.0,
+                ..Default::default()
+              }],
+              ..Default::default()
+            }],
+            ..Default::default()
+          }],
+        },
+      )]),
+      quotes: vec![
+        quote(aapl.clone(), "APPLE INC COM", 150.0),
+        quote(
+          Product {
+            symbol: "MSFT".to_string(),
+            security_type: Some(SecurityType::Eq),
+            ..Default::default()
+          },
+          "MICROSOFT CORP COM",
+          300.0,
+        ),
+      ],
+      option_chains: BTreeMap::from([(
+        "AAPL".to_string(),
+        OptionChainResponse {
+          option_pairs: vec![OptionChainPair {
+            call: Some(option("AAPL", "CALL", 150.0, 5.0)),
+            put: Some(option("AAPL", "PUT", 150.0, 4.5)),
+            pair_type: None,
+          }],
+          time_stamp: now,
+          quote_type: "DELAYED".to_string(),
+          near_price: 150.0,
+          selected: None,
+        },
+      )]),
+      alerts: vec![
+        AlertDetailsResponse {
+          id: 1,
+          create_time: Utc.timestamp_opt(now, 0).single(),
+          subject: "AAPL crossed $150".to_string(),
+          msg_text: "<p>AAPL crossed $150</p>".to_string(),
+          symbol: Some("AAPL".to_string()),
+          ..Default::default()
+        },
+        AlertDetailsResponse {
+          id: 2,
+          create_time: Utc.timestamp_opt(now, 0).single(),
+          subject: "Your statement is available".to_string(),
+          msg_text: "<p>Your statement is available</p>".to_string(),
+          ..Default::default()
+        },
+      ],
+      transactions: BTreeMap::from([(
+        key,
+        vec![TransactionDetailsResponse {
+          transaction_id: 1,
+          account_id,
+          tranaction_date: now * 1000,
+          postdate: now * 1000,
+          amount: -1_200.0,
+          category: None,
+          brokerage: Some(crate::transactions::Brokerage {
+            transaction_type: "Bought".to_string(),
+            product: aapl,
+            quantity: 10.0,
+            price: 120.0,
+            settlement_currency: "USD".to_string(),
+            payment_currency: "USD".to_string(),
+            ..Default::default()
+          }),
+        }],
+      )]),
+      ..Default::default()
+    }
+  }
+}
+
+fn quote(product: Product, description: &str, last_trade: f64) -> QuoteData {
+  QuoteData {
+    all: Some(AllQuoteDetails {
+      symbol_description: description.to_string(),
+      company_name: description.to_string(),
+      last_trade,
+      bid: last_trade - 0.01,
+      ask: last_trade + 0.01,
+      previous_close: last_trade,
+      ..Default::default()
+    }),
+    date_time_utc: Utc::now().timestamp(),
+    product: Some(product),
+    ..Default::default()
+  }
+}
+
+fn option(symbol: &str, option_type: &str, strike_price: f64, last_price: f64) -> OptionDetails {
+  OptionDetails {
+    option_root_symbol: symbol.to_string(),
+    display_symbol: format!("{} {} {}", symbol, strike_price, option_type),
+    option_type: option_type.to_string(),
+    strike_price,
+    symbol: symbol.to_string(),
+    bid: last_price - 0.05,
+    ask: last_price + 0.05,
+    last_price,
+    ..Default::default()
+  }
+}
+
+/// An E*Trade api on a local port, for end to end tests that don't talk to the sandbox.
+///
+/// It implements the oauth flow, where the authorize page answers with the verifier code of the fixtures, and the
+/// accounts, orders, market, alerts and transactions endpoints on top of [`Fixtures`]. Signatures are not checked,
+/// only that the consumer key and tokens are the ones it handed out. The server stops when it is dropped.
+pub struct FakeServer {
+  addr: SocketAddr,
+  state: Arc<Mutex<State>>,
+  shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeServer {
+  /// Starts serving the fixtures on a random port of the loopback interface.
+  pub async fn start(fixtures: Fixtures) -> Result<Self> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let state = Arc::new(Mutex::new(State::new(fixtures)));
+
+    let shared = state.clone();
+    let make_service = make_service_fn(move |_| {
+      let state = shared.clone();
+      async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+    });
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let server = Server::from_tcp(listener)?
+      .tcp_nodelay(true)
+      .serve(make_service)
+      .with_graceful_shutdown(async {
+        stopped.await.ok();
+      });
+    tokio::spawn(async move {
+      if let Err(e) = server.await {
+        warn!("fake server failed: {}", e);
+      }
+    });
+
+    Ok(Self {
+      addr,
+      state,
+      shutdown: Some(shutdown),
+    })
+  }
+
+  /// The base url to pass to [`Session::with_base_url`].
+  pub fn url(&self) -> String {
+    format!("http://{}", self.addr)
+  }
+
+  /// Answers the authorization prompt of a session by following the authorize url.
+  pub fn authorizer(&self) -> impl CallbackProvider {
+    Authorizer
+  }
+
+  /// A session against this server, initialized with the consumer credentials of the fixtures.
+  pub async fn session<T: Store>(&self, store: T) -> Result<Session<T>> {
+    let (key, secret) = {
+      let state = self.state.lock().unwrap();
+      (
+        state.fixtures.consumer_key.clone(),
+        state.fixtures.consumer_secret.clone(),
+      )
+    };
+    let session = Session::new(Mode::Sandbox, store)
+      .with_base_url(self.url())
+      .with_callbacks(self.authorizer());
+    session.initialize(key, secret).await?;
+    Ok(session)
+  }
+
+  /// The current state of the data, with the orders placed so far.
+  pub fn snapshot(&self) -> Fixtures {
+    self.state.lock().unwrap().fixtures.clone()
+  }
+
+  /// Revokes every token handed out so far, sessions need to authorize again.
+  pub fn expire_tokens(&self) {
+    let mut state = self.state.lock().unwrap();
+    state.request_tokens.clear();
+    state.access_tokens.clear();
+  }
+
+  /// Fills an open order at the given price.
+  pub fn execute_order(&self, account_id_key: &str, order_id: i64, price: f64) -> Result<()> {
+    let mut state = self.state.lock().unwrap();
+    let order = state
+      .order_mut(account_id_key, order_id)
+      .map_err(|f| anyhow::anyhow!("{}", f.message))?;
+    let now = Utc::now().timestamp_millis();
+    for detail in order.order_detail.iter_mut() {
+      detail.status = Some(OrderStatus::Executed);
+      detail.executed_time = now;
+      for instrument in detail.instrument.iter_mut() {
+        instrument.filled_quantity = instrument.quantity;
+        instrument.average_execution_price = price;
+      }
+    }
+    order.events.event.push(event(EventName::OrderExecuted, now));
+    Ok(())
+  }
+}
+
+impl Drop for FakeServer {
+  fn drop(&mut self) {
+    if let Some(shutdown) = self.shutdown.take() {
+      let _ = shutdown.send(());
+    }
+  }
+}
+
+/// Gets the verifier code from the authorize page of the fake server.
+struct Authorizer;
+
+#[async_trait]
+impl CallbackProvider for Authorizer {
+  async fn verifier_code(&self, url: &str) -> Result<String> {
+    let resp = hyper::Client::new().get(url.parse()?).await?;
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body()).await?;
+    if !status.is_success() {
+      return Err(anyhow::anyhow!("authorization failed with {}", status));
+    }
+    Ok(String::from_utf8(body.to_vec())?)
+  }
+}
+
+struct RequestToken {
+  secret: String,
+  authorized: bool,
+  exchanged: bool,
+}
+
+struct Preview {
+  account_id_key: String,
+  request: PreviewOrderRequest,
+  /// The order a change replaces.
+  replaces: Option<i64>,
+}
+
+struct State {
+  fixtures: Fixtures,
+  last_id: i64,
+  request_tokens: HashMap<String, RequestToken>,
+  access_tokens: HashSet<String>,
+  previews: HashMap<i64, Preview>,
+}
+
+/// Why a request failed, sent back in the XML error format of E*Trade.
+struct Failure {
+  status: StatusCode,
+  code: isize,
+  message: String,
+}
+
+impl Failure {
+  fn new(status: StatusCode, message: impl Into<String>) -> Self {
+    Self {
+      status,
+      code: status.as_u16() as isize,
+      message: message.into(),
+    }
+  }
+
+  fn not_found(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::NOT_FOUND, message)
+  }
+
+  fn bad_request(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::BAD_REQUEST, message)
+  }
+
+  fn unauthorized(message: impl Into<String>) -> Self {
+    Self::new(StatusCode::UNAUTHORIZED, message)
+  }
+
+  fn into_response(self) -> Response<Body> {
+    let body = format!(
+      "<Error><code>{}</code><message>{}</message></Error>",
+      self.code,
+      self.message.replace('&', "&amp;").replace('<', "&lt;")
+    );
+    Response::builder()
+      .status(self.status)
+      .header(CONTENT_TYPE, "application/xml")
+      .body(body.into())
+      .unwrap()
+  }
+}
+
+type Handled = std::result::Result<Response<Body>, Failure>;
+
+async fn handle(state: Arc<Mutex<State>>, req: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+  let (parts, body) = req.into_parts();
+  let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+  debug!("fake server: {} {}", parts.method, parts.uri);
+  let mut state = state.lock().unwrap();
+  Ok(state.route(&parts, &body).unwrap_or_else(Failure::into_response))
+}
+
+fn json(name: &str, value: impl Serialize) -> Handled {
+  let body = serde_json::to_vec(&serde_json::json!({ name: value }))
+    .map_err(|e| Failure::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+  Ok(
+    Response::builder()
+      .header(CONTENT_TYPE, "application/json")
+      .body(body.into())
+      .unwrap(),
+  )
+}
+
+fn form(values: &[(&str, &str)]) -> Handled {
+  Ok(
+    Response::builder()
+      .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+      .body(serde_urlencoded::to_string(values).unwrap().into())
+      .unwrap(),
+  )
+}
+
+fn query(parts: &Parts) -> HashMap<String, String> {
+  parts
+    .uri
+    .query()
+    .and_then(|q| serde_urlencoded::from_str(q).ok())
+    .unwrap_or_default()
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> std::result::Result<T, Failure> {
+  serde_json::from_slice(body).map_err(|e| Failure::bad_request(format!("invalid request body: {}", e)))
+}
+
+/// The parameters of the `OAuth` authorization header.
+fn oauth_params(parts: &Parts) -> HashMap<String, String> {
+  let header = parts
+    .headers
+    .get(AUTHORIZATION)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or_default();
+  let pairs: Vec<String> = header
+    .trim_start_matches("OAuth ")
+    .split(',')
+    .filter_map(|p| p.trim().split_once('='))
+    .map(|(k, v)| format!("{}={}", k, v.trim_matches('"')))
+    .collect();
+  serde_urlencoded::from_str(&pairs.join("&")).unwrap_or_default()
+}
+
+fn now() -> i64 {
+  Utc::now().timestamp_millis()
+}
+
+fn event(name: EventName, date_time: i64) -> Event {
+  Event {
+    name,
+    date_time,
+    ..Default::default()
+  }
+}
+
+fn is_open(order: &Order) -> bool {
+  order
+    .order_detail
+    .iter()
+    .all(|d| matches!(d.status, Some(OrderStatus::Open)))
+}
+
+fn parse_id(id: &str) -> std::result::Result<i64, Failure> {
+  id.parse()
+    .map_err(|_| Failure::bad_request(format!("invalid id {}", id)))
+}
+
+impl State {
+  fn new(fixtures: Fixtures) -> Self {
+    let last_id = fixtures
+      .orders
+      .values()
+      .flatten()
+      .map(|o| o.order_id)
+      .max()
+      .unwrap_or(0);
+    Self {
+      fixtures,
+      last_id,
+      request_tokens: HashMap::default(),
+      access_tokens: HashSet::default(),
+      previews: HashMap::default(),
+    }
+  }
+
+  fn next_id(&mut self) -> i64 {
+    self.last_id += 1;
+    self.last_id
+  }
+
+  fn route(&mut self, parts: &Parts, body: &[u8]) -> Handled {
+    let path = parts.uri.path().trim_matches('/').to_string();
+    let segments: Vec<&str> = path.split('/').collect();
+    let method = &parts.method;
+
+    match segments.as_slice() {
+      ["oauth", "request_token"] => return self.request_token(parts),
+      ["oauth", "access_token"] => return self.access_token(parts),
+      ["oauth", "renew_access_token"] => return self.renew_access_token(parts),
+      ["e", "t", "etws", "authorize"] => return self.authorize(parts),
+      _ => self.authenticate(parts)?,
+    }
+
+    match (method, segments.as_slice()) {
+      (&Method::GET, ["v1", "accounts", "list"]) => json(
+        "AccountListResponse",
+        serde_json::json!({ "Accounts": { "Account": self.fixtures.accounts } }),
+      ),
+      (&Method::GET, ["v1", "accounts", key, "balance"]) => {
+        self.account(key)?;
+        let balance = self
+          .fixtures
+          .balances
+          .get(*key)
+          .ok_or_else(|| Failure::not_found("no balance for the account"))?;
+        json("BalanceResponse", balance)
+      }
+      (&Method::GET, ["v1", "accounts", key, "portfolio"]) => {
+        self.account(key)?;
+        json(
+          "PortfolioResponse",
+          self.fixtures.portfolios.get(*key).cloned().unwrap_or_default(),
+        )
+      }
+      (&Method::GET, ["v1", "accounts", key, "portfolio", position_id]) => self.position_lots(key, position_id),
+      (&Method::GET, ["v1", "accounts", key, "orders"]) => self.orders(key, &query(parts)),
+      (&Method::POST, ["v1", "accounts", key, "orders", "preview"]) => self.preview(key, parse_body(body)?, None),
+      (&Method::POST, ["v1", "accounts", key, "orders", "place"]) => self.place(key, parse_body(body)?, None),
+      (&Method::PUT, ["v1", "accounts", key, "orders", "cancel"]) => self.cancel(key, parse_body(body)?),
+      (&Method::PUT, ["v1", "accounts", key, "orders", order_id, "change", "preview"]) => {
+        let order_id = parse_id(order_id)?;
+        self.open_order(key, order_id)?;
+        self.preview(key, parse_body(body)?, Some(order_id))
+      }
+      (&Method::PUT, ["v1", "accounts", key, "orders", order_id, "change", "place"]) => {
+        let order_id = parse_id(order_id)?;
+        self.open_order(key, order_id)?;
+        self.place(key, parse_body(body)?, Some(order_id))
+      }
+      (&Method::GET, ["v1", "accounts", key, "transactions"]) => self.transactions(key, &query(parts)),
+      (&Method::GET, ["v1", "accounts", key, "transactions", id]) => {
+        self.account(key)?;
+        let id = parse_id(id)?;
+        let transaction = self
+          .fixtures
+          .transactions
+          .get(*key)
+          .and_then(|ts| ts.iter().find(|t| t.transaction_id == id))
+          .ok_or_else(|| Failure::not_found(format!("transaction {} not found", id)))?;
+        json("TransactionDetailsResponse", transaction)
+      }
+      (&Method::GET, ["v1", "market", "quote", "optionchains"]) => {
+        let symbol = query(parts).remove("symbol").unwrap_or_default();
+        let chain = self
+          .fixtures
+          .option_chains
+          .get(&symbol.to_uppercase())
+          .ok_or_else(|| Failure::bad_request(format!("no option chain for {}", symbol)))?;
+        json("OptionChainResponse", chain)
+      }
+      (&Method::GET, ["v1", "market", "quote", symbols]) => self.quotes(symbols),
+      (&Method::GET, ["v1", "users", "alerts"]) => self.alerts(&query(parts)),
+      (&Method::GET, ["v1", "users", "alerts", id]) => {
+        let id = parse_id(id)?;
+        let live: Vec<i64> = self
+          .fixtures
+          .alerts
+          .iter()
+          .filter(|a| a.delete_time.is_none())
+          .map(|a| a.id)
+          .collect();
+        let pos = live
+          .iter()
+          .position(|a| *a == id)
+          .ok_or_else(|| Failure::not_found(format!("alert {} not found", id)))?;
+        let link = |id: Option<&i64>| id.map(|id| format!("/v1/users/alerts/{}", id)).unwrap_or_default();
+        let (prev, next) = (
+          link(pos.checked_sub(1).and_then(|p| live.get(p))),
+          link(live.get(pos + 1)),
+        );
+        let alert = self.fixtures.alerts.iter_mut().find(|a| a.id == id).unwrap();
+        alert.read_time.get_or_insert_with(Utc::now);
+        let mut alert = alert.clone();
+        alert.prev = prev;
+        alert.next = next;
+        json("AlertDetailsResponse", alert)
+      }
+      (&Method::DELETE, ["v1", "users", "alerts", ids]) => self.delete_alerts(ids),
+      _ => Err(Failure::not_found(format!("no route for {} /{}", method, path))),
+    }
+  }
+
+  fn check_consumer(&self, params: &HashMap<String, String>) -> std::result::Result<(), Failure> {
+    match params.get("oauth_consumer_key") {
+      Some(key) if *key == self.fixtures.consumer_key => Ok(()),
+      _ => Err(Failure::unauthorized("consumer key rejected")),
+    }
+  }
+
+  fn authenticate(&self, parts: &Parts) -> std::result::Result<(), Failure> {
+    let params = oauth_params(parts);
+    self.check_consumer(&params)?;
+    match params.get("oauth_token") {
+      Some(token) if self.access_tokens.contains(token) => Ok(()),
+      _ => Err(Failure::unauthorized("token rejected")),
+    }
+  }
+
+  fn request_token(&mut self, parts: &Parts) -> Handled {
+    self.check_consumer(&oauth_params(parts))?;
+    let id = self.next_id();
+    let (token, secret) = (format!("request-token-{}", id), format!("request-secret-{}", id));
+    self.request_tokens.insert(
+      token.clone(),
+      RequestToken {
+        secret: secret.clone(),
+        authorized: false,
+        exchanged: false,
+      },
+    );
+    form(&[
+      ("oauth_token", &token),
+      ("oauth_token_secret", &secret),
+      ("oauth_callback_confirmed", "true"),
+    ])
+  }
+
+  fn authorize(&mut self, parts: &Parts) -> Handled {
+    let query = query(parts);
+    if query.get("key") != Some(&self.fixtures.consumer_key) {
+      return Err(Failure::unauthorized("consumer key rejected"));
+    }
+    let token = query
+      .get("token")
+      .and_then(|t| self.request_tokens.get_mut(t))
+      .ok_or_else(|| Failure::unauthorized("request token rejected"))?;
+    token.authorized = true;
+    Ok(
+      Response::builder()
+        .header(CONTENT_TYPE, "text/plain")
+        .body(self.fixtures.verifier.clone().into())
+        .unwrap(),
+    )
+  }
+
+  fn issue_access_token(&mut self) -> Handled {
+    let id = self.next_id();
+    let (token, secret) = (format!("access-token-{}", id), format!("access-secret-{}", id));
+    self.access_tokens.insert(token.clone());
+    form(&[("oauth_token", &token), ("oauth_token_secret", &secret)])
+  }
+
+  fn access_token(&mut self, parts: &Parts) -> Handled {
+    let params = oauth_params(parts);
+    self.check_consumer(&params)?;
+    if params.get("oauth_verifier") != Some(&self.fixtures.verifier) {
+      return Err(Failure::unauthorized("verifier rejected"));
+    }
+    match params.get("oauth_token").and_then(|t| self.request_tokens.get_mut(t)) {
+      Some(token) if token.authorized && !token.exchanged && !token.secret.is_empty() => token.exchanged = true,
+      _ => return Err(Failure::unauthorized("request token rejected")),
+    }
+    self.issue_access_token()
+  }
+
+  /// Accepts an access token, or a request token that was already exchanged for one.
+  fn renew_access_token(&mut self, parts: &Parts) -> Handled {
+    let params = oauth_params(parts);
+    self.check_consumer(&params)?;
+    let token = params.get("oauth_token").map(String::as_str).unwrap_or_default();
+    let renewable =
+      self.access_tokens.contains(token) || self.request_tokens.get(token).map(|t| t.exchanged).unwrap_or(false);
+    if !renewable {
+      return Err(Failure::unauthorized("token rejected"));
+    }
+    self.issue_access_token()
+  }
+
+  fn account(&self, key: &str) -> std::result::Result<&Account, Failure> {
+    self
+      .fixtures
+      .accounts
+      .iter()
+      .find(|a| a.account_id_key == key)
+      .ok_or_else(|| Failure::not_found(format!("account {} not found", key)))
+  }
+
+  fn position_lots(&self, key: &str, position_id: &str) -> Handled {
+    self.account(key)?;
+    let position_id = parse_id(position_id)?;
+    let position = self
+      .fixtures
+      .portfolios
+      .get(key)
+      .into_iter()
+      .flat_map(|p| p.account_portfolio.iter())
+      .flat_map(|p| p.position.iter())
+      .find(|p| p.position_id == position_id)
+      .ok_or_else(|| Failure::not_found(format!("position {} not found", position_id)))?;
+    json(
+      "PositionLotsResponse",
+      PositionLotsResponse {
+        position_lot: position.position_lot.clone(),
+      },
+    )
+  }
+
+  fn order_mut(&mut self, key: &str, order_id: i64) -> std::result::Result<&mut Order, Failure> {
+    self
+      .fixtures
+      .orders
+      .get_mut(key)
+      .and_then(|orders| orders.iter_mut().find(|o| o.order_id == order_id))
+      .ok_or_else(|| Failure::not_found(format!("order {} not found", order_id)))
+  }
+
+  fn open_order(&mut self, key: &str, order_id: i64) -> std::result::Result<&mut Order, Failure> {
+    self.account(key)?;
+    let order = self.order_mut(key, order_id)?;
+    if !is_open(order) {
+      return Err(Failure::bad_request(format!("order {} is not open", order_id)));
+    }
+    Ok(order)
+  }
+
+  fn orders(&self, key: &str, query: &HashMap<String, String>) -> Handled {
+    self.account(key)?;
+    let status = query.get("status");
+    let mut orders: Vec<&Order> = self
+      .fixtures
+      .orders
+      .get(key)
+      .into_iter()
+      .flatten()
+      .filter(|o| {
+        status.is_none()
+          || o.order_detail.iter().any(|d| {
+            serde_json::to_value(d.status).ok().as_ref().and_then(|s| s.as_str()) == status.map(String::as_str)
+          })
+      })
+      .collect();
+    if let Some(count) = query.get("count").and_then(|c| c.parse().ok()) {
+      orders.truncate(count);
+    }
+    json(
+      "OrdersResponse",
+      serde_json::json!({ "marker": "", "next": "", "Order": orders }),
+    )
+  }
+
+  fn last_trade(&self, symbol: &str) -> f64 {
+    self
+      .fixtures
+      .quotes
+      .iter()
+      .find(|q| q.product.as_ref().map(|p| p.symbol.as_str()) == Some(symbol))
+      .and_then(|q| q.all.as_ref())
+      .map(|all| all.last_trade)
+      .unwrap_or_default()
+  }
+
+  /// The limit price, or the last trade of the quotes for market orders.
+  fn order_value(&self, detail: &OrderDetail) -> f64 {
+    detail
+      .instrument
+      .iter()
+      .map(|i| {
+        let price = if detail.limit_price > 0.0 {
+          detail.limit_price
+        } else {
+          self.last_trade(&i.product.symbol)
+        };
+        i.quantity * price
+      })
+      .sum()
+  }
+
+  fn preview(&mut self, key: &str, request: PreviewOrderRequest, replaces: Option<i64>) -> Handled {
+    let account_id = self.account(key)?.account_id.clone();
+    if request.order.is_empty() {
+      return Err(Failure::bad_request("the order has no details"));
+    }
+    let preview_id = self.next_id();
+    let preview_time = now();
+    let order: Vec<OrderDetail> = request
+      .order
+      .iter()
+      .cloned()
+      .map(|mut d| {
+        d.order_value = self.order_value(&d);
+        d.preview_time = preview_time;
+        d.preview_id = preview_id;
+        d.estimated_total_amount = d.order_value;
+        d
+      })
+      .collect();
+    let response = PreviewOrderResponse {
+      total_order_value: order.iter().map(|d| d.order_value).sum(),
+      order,
+      preview_ids: vec![PreviewId {
+        preview_id,
+        cash_margin: "CASH".to_string(),
+      }],
+      preview_time,
+      account_id,
+      client_order_id: request.client_order_id.clone(),
+      ..Default::default()
+    };
+    self.previews.insert(
+      preview_id,
+      Preview {
+        account_id_key: key.to_string(),
+        request,
+        replaces,
+      },
+    );
+    json("PreviewOrderResponse", response)
+  }
+
+  fn place(&mut self, key: &str, request: PlaceOrderRequest, replaces: Option<i64>) -> Handled {
+    let account_id = self.account(key)?.account_id.clone();
+    let preview_id = request
+      .preview_ids
+      .first()
+      .map(|p| p.preview_id)
+      .ok_or_else(|| Failure::bad_request("missing preview id"))?;
+    match self.previews.get(&preview_id) {
+      Some(p) if p.account_id_key == key && p.replaces == replaces => {}
+      _ => return Err(Failure::bad_request(format!("unknown preview id {}", preview_id))),
+    }
+    let preview = self.previews.remove(&preview_id).unwrap();
+
+    let order_id = self.next_id();
+    let placed_time = now();
+    if let Some(replaced) = replaces {
+      let order = self.open_order(key, replaced)?;
+      for detail in order.order_detail.iter_mut() {
+        detail.status = Some(OrderStatus::Cancelled);
+        detail.replaced_by_order_id = order_id as isize;
+      }
+      order.events.event.push(event(EventName::OrderModified, placed_time));
+    }
+
+    let details = if request.order.is_empty() {
+      preview.request.order
+    } else {
+      request.order
+    };
+    let details: Vec<OrderDetail> = details
+      .into_iter()
+      .map(|mut d| {
+        d.order_value = self.order_value(&d);
+        d.account_id = account_id.clone();
+        d.preview_id = preview_id;
+        d.placed_time = placed_time;
+        d.status = Some(OrderStatus::Open);
+        d.replaces_order_id = replaces.unwrap_or_default() as isize;
+        d
+      })
+      .collect();
+    let order_type = request.order_type.or(preview.request.order_type);
+    let total_order_value = details.iter().map(|d| d.order_value).sum();
+
+    self.fixtures.orders.entry(key.to_string()).or_default().push(Order {
+      order_id,
+      order_type: order_type
+        .and_then(|t| serde_json::to_value(t).ok())
+        .and_then(|t| t.as_str().map(str::to_string))
+        .unwrap_or_default(),
+      total_order_value,
+      order_detail: details.clone(),
+      events: crate::orders::Events {
+        event: vec![event(EventName::OrderPlaced, placed_time)],
+      },
+      ..Default::default()
+    });
+
+    json(
+      "PlaceOrderResponse",
+      PlaceOrderResponse {
+        order_type,
+        total_order_value,
+        order_id,
+        order: details,
+        account_id,
+        order_ids: vec![OrderId {
+          order_id,
+          cash_margin: "CASH".to_string(),
+        }],
+        placed_time,
+        client_order_id: request.client_order_id,
+        ..Default::default()
+      },
+    )
+  }
+
+  fn cancel(&mut self, key: &str, request: CancelOrderRequest) -> Handled {
+    let account_id = self.account(key)?.account_id.clone();
+    let cancel_time = now();
+    let order = self.open_order(key, request.order_id)?;
+    for detail in order.order_detail.iter_mut() {
+      detail.status = Some(OrderStatus::Cancelled);
+    }
+    order.events.event.push(event(EventName::OrderCancelled, cancel_time));
+    json(
+      "CancelOrderResponse",
+      CancelOrderResponse {
+        account_id,
+        order_id: request.order_id,
+        cancel_time,
+        messages: Messages {
+          message: vec![Message {
+            description: "Your request to cancel your order is being processed.".to_string(),
+            code: 5011,
+            tpe: MessageType::Warning,
+          }],
+        },
+      },
+    )
+  }
+
+  fn quotes(&self, symbols: &str) -> Handled {
+    let mut response = QuoteResponse::default();
+    for symbol in symbols.split(',').filter(|s| !s.is_empty()) {
+      let quote = self.fixtures.quotes.iter().find(|q| {
+        q.product
+          .as_ref()
+          .map(|p| p.symbol.eq_ignore_ascii_case(symbol))
+          .unwrap_or(false)
+      });
+      match quote {
+        Some(q) => response.quote_data.push(q.clone()),
+        None => response.message_list.message.push(Message {
+          description: format!("{} is not a valid symbol.", symbol),
+          code: 10033,
+          tpe: MessageType::Warning,
+        }),
+      }
+    }
+    json("QuoteResponse", response)
+  }
+
+  fn alerts(&self, query: &HashMap<String, String>) -> Handled {
+    let status: Option<Status> = query
+      .get("status")
+      .and_then(|s| serde_json::from_value(serde_json::Value::String(s.clone())).ok());
+    let mut alerts: Vec<Alert> = self
+      .fixtures
+      .alerts
+      .iter()
+      .map(|a| Alert {
+        id: a.id,
+        create_time: a.create_time,
+        subject: a.subject.clone(),
+        status: Some(match (a.delete_time, a.read_time) {
+          (Some(_), _) => Status::Deleted,
+          (None, Some(_)) => Status::Read,
+          (None, None) => Status::Unread,
+        }),
+      })
+      .filter(|a| match (status, a.status) {
+        (None, Some(Status::Deleted)) => false,
+        (None, _) => true,
+        (Some(wanted), Some(status)) => std::mem::discriminant(&wanted) == std::mem::discriminant(&status),
+        (Some(_), None) => false,
+      })
+      .collect();
+    let total_alerts = alerts.len() as i64;
+    let count = query.get("count").and_then(|c| c.parse().ok()).unwrap_or(25);
+    alerts.truncate(count);
+    json("AlertsResponse", AlertsResponse { total_alerts, alerts })
+  }
+
+  fn delete_alerts(&mut self, ids: &str) -> Handled {
+    let now = Utc::now();
+    let mut failed = vec![];
+    for id in ids.split(',') {
+      let alert = id.parse::<i64>().ok().and_then(|id| {
+        self
+          .fixtures
+          .alerts
+          .iter_mut()
+          .find(|a| a.id == id && a.delete_time.is_none())
+      });
+      match alert {
+        Some(alert) => alert.delete_time = Some(now),
+        None => failed.extend(id.parse::<i64>().ok()),
+      }
+    }
+    json(
+      "AlertsResponse",
+      DeleteAlertsResponse {
+        result: if failed.is_empty() { "SUCCESS" } else { "FAILURE" }.to_string(),
+        failed_alerts: FailedAlerts { alert_id: failed },
+      },
+    )
+  }
+
+  fn transactions(&self, key: &str, query: &HashMap<String, String>) -> Handled {
+    self.account(key)?;
+    let all = self.fixtures.transactions.get(key).cloned().unwrap_or_default();
+    let total_count = all.len();
+    let mut transaction = all;
+    if let Some(count) = query.get("count").and_then(|c| c.parse().ok()) {
+      transaction.truncate(count);
+    }
+    json(
+      "TransactionListResponse",
+      TransactionListResponse {
+        page_marker: String::new(),
+        more_transactions: transaction.len() < total_count,
+        transaction_count: transaction.len(),
+        total_count,
+        transaction,
+      },
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::{FakeServer, Fixtures};
+  use crate::accounts::{self, BalanceRequest, PortfolioRequest};
+  use crate::alerts::{self, ListAlertsRequest};
+  use crate::options::{self, GetOptionChainsRequest, GetQuotesRequest};
+  use crate::orders::{
+    self, CancelOrderRequest, Instrument, ListOrdersRequest, OrderAction, OrderDetail, OrderStatus, OrderType,
+    PlaceOrderRequest, PreviewOrderRequest, PriceType,
+  };
+  use crate::transactions::{self, ListTransactionsRequest};
+  use crate::{Memstore, Product};
+
+  const ACCOUNT: &str = "fake-account-key";
+
+  fn buy(symbol: &str, quantity: f64, limit_price: f64) -> PreviewOrderRequest {
+    PreviewOrderRequest {
+      order_type: Some(OrderType::Eq),
+      client_order_id: "client-1".to_string(),
+      order: vec![OrderDetail {
+        price_type: Some(PriceType::Limit),
+        limit_price,
+        instrument: vec![Instrument {
+          product: Product {
+            symbol: symbol.to_string(),
+            ..Default::default()
+          },
+          order_action: Some(OrderAction::Buy),
+          quantity,
+          ..Default::default()
+        }],
+        ..Default::default()
+      }],
+    }
+  }
+
+  fn place(preview: &orders::PreviewOrderResponse) -> PlaceOrderRequest {
+    PlaceOrderRequest {
+      order_type: Some(OrderType::Eq),
+      client_order_id: preview.client_order_id.clone(),
+      order: preview.order.clone(),
+      preview_ids: preview.preview_ids.clone(),
+    }
+  }
+
+  #[tokio::test]
+  async fn serves_accounts_and_market_data() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let session = Arc::new(server.session(Memstore::new()).await.unwrap());
+
+    let accounts = accounts::Api::new(session.clone());
+    assert_eq!(accounts.list().await.unwrap()[0].account_id_key, ACCOUNT);
+    let balance = accounts.balance(ACCOUNT, BalanceRequest::default()).await.unwrap();
+    assert_eq!(balance.computed_balance.cash_balance, 10_000.0);
+    let portfolio = accounts.portfolio(ACCOUNT, PortfolioRequest::default()).await.unwrap();
+    assert_eq!(portfolio.account_portfolio[0].position[0].quantity, 10.0);
+    assert_eq!(
+      accounts.position_lots(ACCOUNT, "1").await.unwrap().position_lot.len(),
+      1
+    );
+    assert!(accounts.balance("missing", BalanceRequest::default()).await.is_err());
+
+    let market = options::Api::new(session.clone());
+    let quotes = market.quotes("AAPL,NOPE", GetQuotesRequest::default()).await.unwrap();
+    assert_eq!(quotes.quote_data.len(), 1);
+    assert_eq!(quotes.message_list.message.len(), 1);
+    let chains = market
+      .chains(&GetOptionChainsRequest {
+        symbol: "AAPL",
+        ..Default::default()
+      })
+      .await
+      .unwrap();
+    assert_eq!(chains.option_pairs.len(), 1);
+
+    let transactions = transactions::Api::new(session);
+    let list = transactions
+      .list(ACCOUNT, ListTransactionsRequest::default())
+      .await
+      .unwrap();
+    assert_eq!(list.total_count, 1);
+    assert_eq!(transactions.details(ACCOUNT, "1", "").await.unwrap().amount, -1_200.0);
+  }
+
+  #[tokio::test]
+  async fn keeps_track_of_orders() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let orders = orders::Api::new(Arc::new(server.session(Memstore::new()).await.unwrap()));
+
+    let preview = orders.preview(ACCOUNT, buy("MSFT", 2.0, 0.0)).await.unwrap();
+    assert_eq!(preview.total_order_value, 600.0);
+    let placed = orders.place(ACCOUNT, place(&preview)).await.unwrap();
+    assert!(placed.order_id > 0);
+    assert!(
+      orders.place(ACCOUNT, place(&preview)).await.is_err(),
+      "previews are used once"
+    );
+
+    let change = orders
+      .change_preview(ACCOUNT, &placed.order_id.to_string(), buy("MSFT", 3.0, 290.0))
+      .await
+      .unwrap();
+    let changed = orders
+      .change_order(ACCOUNT, &placed.order_id.to_string(), place(&change))
+      .await
+      .unwrap();
+    assert_eq!(changed.total_order_value, 870.0);
+
+    let open = orders
+      .list(
+        ACCOUNT,
+        ListOrdersRequest {
+          status: Some(OrderStatus::Open),
+          ..Default::default()
+        },
+      )
+      .await
+      .unwrap();
+    assert_eq!(open.order.len(), 1);
+    assert_eq!(open.order[0].order_id, changed.order_id);
+    assert_eq!(
+      open.order[0].order_detail[0].replaces_order_id,
+      placed.order_id as isize
+    );
+
+    let cancel = CancelOrderRequest {
+      order_id: changed.order_id,
+    };
+    orders.cancel(ACCOUNT, cancel.clone()).await.unwrap();
+    assert!(
+      orders.cancel(ACCOUNT, cancel).await.is_err(),
+      "cancelled orders are not open"
+    );
+    let all = orders.list(ACCOUNT, ListOrdersRequest::default()).await.unwrap();
+    assert_eq!(all.order.len(), 2);
+    assert_eq!(server.snapshot().orders[ACCOUNT].len(), 2);
+  }
+
+  #[tokio::test]
+  async fn reads_and_deletes_alerts() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let alerts = alerts::Api::new(Arc::new(server.session(Memstore::new()).await.unwrap()));
+
+    assert_eq!(alerts.list(ListAlertsRequest::default()).await.unwrap().total_alerts, 2);
+    let details = alerts.details("1", false).await.unwrap();
+    assert!(details.read_time.is_some());
+    let read = alerts
+      .list(ListAlertsRequest {
+        status: Some(alerts::Status::Read),
+        ..Default::default()
+      })
+      .await
+      .unwrap();
+    assert_eq!(read.alerts[0].id, 1);
+
+    assert_eq!(alerts.delete("1,3").await.unwrap().failed_alerts.alert_id, vec![3]);
+    assert_eq!(alerts.list(ListAlertsRequest::default()).await.unwrap().total_alerts, 1);
+  }
+
+  #[tokio::test]
+  async fn pages_and_navigates_alerts() {
+    let server = FakeServer::start(Fixtures::sample()).await.unwrap();
+    let alerts = alerts::Api::new(Arc::new(server.session(Memstore::new()).await.unwrap()));
+
+    let all = alerts
+      .list_all(ListAlertsRequest {
+        count: Some(1),
+        ..Default::default()
+      })
+      .await
+      .unwrap();
+    assert_eq!(all.alerts.iter().map(|a| a.id).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(all.alerts[0].create_time.is_some());
+
+    let first = alerts.details("1", false).await.unwrap();
+    assert_eq!(first.text(), "AAPL crossed $150");
+    let second = alerts.next_alert(&first, false).await.unwrap().unwrap();
+    assert_eq!(second.id, 2);
+    assert!(alerts.next_alert(&second, false).await.unwrap().is_none());
+    assert_eq!(alerts.prev_alert(&second, false).await.unwrap().unwrap().id, 1);
+
+    alerts.mark_read(&[2]).await.unwrap();
+    assert!(server.snapshot().alerts[1].read_time.is_some());
+    let deleted = alerts.delete_many(&[1, 2, 7]).await.unwrap();
+    assert_eq!(deleted.failed_alerts.alert_id, vec![7]);
+    assert_eq!(
+      alerts
+        .list_all(ListAlertsRequest::default())
+        .await
+        .unwrap()
+        .total_alerts,
+      0
+    );
+  }
+
+  #[test]
+  fn loads_fixtures() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fixtures.yaml");
+    std::fs::write(&path, serde_yaml::to_string(&Fixtures::sample()).unwrap()).unwrap();
+    let fixtures = Fixtures::load(&path).unwrap();
+    assert_eq!(fixtures.accounts[0].account_id_key, ACCOUNT);
+    assert_eq!(fixtures.consumer_key, "fake-consumer-key");
+  }
+}
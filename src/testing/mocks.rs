@@ -0,0 +1,415 @@
+use std::{
+  any::Any,
+  collections::{HashMap, VecDeque},
+  sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::accounts::{
+  Account, BalanceRequest, BalanceResponse, PortfolioRequest, PortfolioResponse, PositionLotsResponse,
+};
+use crate::alerts::{AlertDetailsResponse, AlertsResponse, DeleteAlertsResponse, ListAlertsRequest};
+use crate::clients::{AccountsClient, AlertsClient, MarketClient, OrdersClient, TransactionsClient};
+use crate::options::{
+  GetOptionChainsRequest, GetOptionExpireDatesRequest, GetQuotesRequest, LookupResponse, OptionChainResponse,
+  OptionExpireDateResponse, QuoteResponse,
+};
+use crate::orders::{
+  CancelOrderRequest, CancelOrderResponse, ListOrdersRequest, OrdersResponse, PlaceOrderRequest, PlaceOrderResponse,
+  PreviewOrderRequest, PreviewOrderResponse,
+};
+use crate::transactions::{ListTransactionsRequest, TransactionDetailsResponse, TransactionListResponse};
+
+/// A call received by a mock, with its arguments as JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+  pub method: &'static str,
+  pub args: Value,
+}
+
+/// The responses queued per method and the calls received so far.
+#[derive(Default)]
+struct Script {
+  responses: Mutex<HashMap<&'static str, VecDeque<Box<dyn Any + Send>>>>,
+  calls: Mutex<Vec<Call>>,
+}
+
+impl Script {
+  fn push<R: Send + 'static>(&self, method: &'static str, response: Result<R>) {
+    self
+      .responses
+      .lock()
+      .unwrap()
+      .entry(method)
+      .or_default()
+      .push_back(Box::new(response));
+  }
+
+  fn call<R: 'static>(&self, mock: &str, method: &'static str, args: Value) -> Result<R> {
+    self.calls.lock().unwrap().push(Call { method, args });
+    let response = self
+      .responses
+      .lock()
+      .unwrap()
+      .get_mut(method)
+      .and_then(VecDeque::pop_front)
+      .ok_or_else(|| anyhow!("unexpected call to {}::{}", mock, method))?;
+    *response
+      .downcast::<Result<R>>()
+      .map_err(|_| anyhow!("{}::{} was scripted with the wrong response type", mock, method))?
+  }
+
+  fn calls(&self) -> Vec<Call> {
+    self.calls.lock().unwrap().clone()
+  }
+}
+
+fn args(value: impl serde::Serialize) -> Value {
+  serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// An [`AccountsClient`] answering with scripted responses.
+///
+/// Every `on_*` method queues a response for the method of the same name, the responses are handed out in order and a
+/// call without a queued response fails. [`MockAccounts::calls`] returns the calls received so far.
+#[derive(Default)]
+pub struct MockAccounts {
+  script: Script,
+}
+
+impl MockAccounts {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn on_list(self, response: Result<Vec<Account>>) -> Self {
+    self.script.push("list", response);
+    self
+  }
+
+  pub fn on_balance(self, response: Result<BalanceResponse>) -> Self {
+    self.script.push("balance", response);
+    self
+  }
+
+  pub fn on_portfolio(self, response: Result<PortfolioResponse>) -> Self {
+    self.script.push("portfolio", response);
+    self
+  }
+
+  pub fn on_position_lots(self, response: Result<PositionLotsResponse>) -> Self {
+    self.script.push("position_lots", response);
+    self
+  }
+
+  pub fn calls(&self) -> Vec<Call> {
+    self.script.calls()
+  }
+}
+
+#[async_trait]
+impl AccountsClient for MockAccounts {
+  async fn list(&self) -> Result<Vec<Account>> {
+    self.script.call("MockAccounts", "list", json!({}))
+  }
+
+  async fn balance(&self, account_id_key: &str, params: BalanceRequest<'_>) -> Result<BalanceResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockAccounts", "balance", args)
+  }
+
+  async fn portfolio(&self, account_id_key: &str, params: PortfolioRequest) -> Result<PortfolioResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockAccounts", "portfolio", args)
+  }
+
+  async fn position_lots(&self, account_id_key: &str, position_id: &str) -> Result<PositionLotsResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "positionId": position_id });
+    self.script.call("MockAccounts", "position_lots", args)
+  }
+}
+
+/// An [`OrdersClient`] answering with scripted responses, see [`MockAccounts`].
+#[derive(Default)]
+pub struct MockOrders {
+  script: Script,
+}
+
+impl MockOrders {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn on_list(self, response: Result<OrdersResponse>) -> Self {
+    self.script.push("list", response);
+    self
+  }
+
+  pub fn on_preview(self, response: Result<PreviewOrderResponse>) -> Self {
+    self.script.push("preview", response);
+    self
+  }
+
+  pub fn on_place(self, response: Result<PlaceOrderResponse>) -> Self {
+    self.script.push("place", response);
+    self
+  }
+
+  pub fn on_cancel(self, response: Result<CancelOrderResponse>) -> Self {
+    self.script.push("cancel", response);
+    self
+  }
+
+  pub fn on_change_preview(self, response: Result<PreviewOrderResponse>) -> Self {
+    self.script.push("change_preview", response);
+    self
+  }
+
+  pub fn on_change_order(self, response: Result<PlaceOrderResponse>) -> Self {
+    self.script.push("change_order", response);
+    self
+  }
+
+  pub fn calls(&self) -> Vec<Call> {
+    self.script.calls()
+  }
+}
+
+#[async_trait]
+impl OrdersClient for MockOrders {
+  async fn list(&self, account_id_key: &str, params: ListOrdersRequest) -> Result<OrdersResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockOrders", "list", args)
+  }
+
+  async fn preview(&self, account_id_key: &str, params: PreviewOrderRequest) -> Result<PreviewOrderResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockOrders", "preview", args)
+  }
+
+  async fn place(&self, account_id_key: &str, params: PlaceOrderRequest) -> Result<PlaceOrderResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockOrders", "place", args)
+  }
+
+  async fn cancel(&self, account_id_key: &str, params: CancelOrderRequest) -> Result<CancelOrderResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockOrders", "cancel", args)
+  }
+
+  async fn change_preview(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PreviewOrderRequest,
+  ) -> Result<PreviewOrderResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "orderId": order_id, "params": args(&params) });
+    self.script.call("MockOrders", "change_preview", args)
+  }
+
+  async fn change_order(
+    &self,
+    account_id_key: &str,
+    order_id: &str,
+    params: PlaceOrderRequest,
+  ) -> Result<PlaceOrderResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "orderId": order_id, "params": args(&params) });
+    self.script.call("MockOrders", "change_order", args)
+  }
+}
+
+/// A [`MarketClient`] answering with scripted responses, see [`MockAccounts`].
+#[derive(Default)]
+pub struct MockMarket {
+  script: Script,
+}
+
+impl MockMarket {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn on_quotes(self, response: Result<QuoteResponse>) -> Self {
+    self.script.push("quotes", response);
+    self
+  }
+
+  pub fn on_product(self, response: Result<LookupResponse>) -> Self {
+    self.script.push("product", response);
+    self
+  }
+
+  pub fn on_chains(self, response: Result<OptionChainResponse>) -> Self {
+    self.script.push("chains", response);
+    self
+  }
+
+  pub fn on_expire_dates(self, response: Result<OptionExpireDateResponse>) -> Self {
+    self.script.push("expire_dates", response);
+    self
+  }
+
+  pub fn calls(&self) -> Vec<Call> {
+    self.script.calls()
+  }
+}
+
+#[async_trait]
+impl MarketClient for MockMarket {
+  async fn quotes(&self, symbols: &str, params: GetQuotesRequest) -> Result<QuoteResponse> {
+    let args = json!({ "symbols": symbols, "params": args(&params) });
+    self.script.call("MockMarket", "quotes", args)
+  }
+
+  async fn product(&self, search: &str) -> Result<LookupResponse> {
+    self.script.call("MockMarket", "product", json!({ "search": search }))
+  }
+
+  async fn chains(&self, params: &GetOptionChainsRequest<'_>) -> Result<OptionChainResponse> {
+    self
+      .script
+      .call("MockMarket", "chains", json!({ "params": args(params) }))
+  }
+
+  async fn expire_dates(&self, params: &GetOptionExpireDatesRequest<'_>) -> Result<OptionExpireDateResponse> {
+    self
+      .script
+      .call("MockMarket", "expire_dates", json!({ "params": args(params) }))
+  }
+}
+
+/// An [`AlertsClient`] answering with scripted responses, see [`MockAccounts`].
+#[derive(Default)]
+pub struct MockAlerts {
+  script: Script,
+}
+
+impl MockAlerts {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn on_list(self, response: Result<AlertsResponse>) -> Self {
+    self.script.push("list", response);
+    self
+  }
+
+  pub fn on_details(self, response: Result<AlertDetailsResponse>) -> Self {
+    self.script.push("details", response);
+    self
+  }
+
+  pub fn on_delete(self, response: Result<DeleteAlertsResponse>) -> Self {
+    self.script.push("delete", response);
+    self
+  }
+
+  pub fn calls(&self) -> Vec<Call> {
+    self.script.calls()
+  }
+}
+
+#[async_trait]
+impl AlertsClient for MockAlerts {
+  async fn list(&self, params: ListAlertsRequest) -> Result<AlertsResponse> {
+    self
+      .script
+      .call("MockAlerts", "list", json!({ "params": args(&params) }))
+  }
+
+  async fn details(&self, alert_id: &str, html: bool) -> Result<AlertDetailsResponse> {
+    let args = json!({ "alertId": alert_id, "html": html });
+    self.script.call("MockAlerts", "details", args)
+  }
+
+  async fn delete(&self, alert_id: &str) -> Result<DeleteAlertsResponse> {
+    self.script.call("MockAlerts", "delete", json!({ "alertId": alert_id }))
+  }
+}
+
+/// A [`TransactionsClient`] answering with scripted responses, see [`MockAccounts`].
+#[derive(Default)]
+pub struct MockTransactions {
+  script: Script,
+}
+
+impl MockTransactions {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn on_list(self, response: Result<TransactionListResponse>) -> Self {
+    self.script.push("list", response);
+    self
+  }
+
+  pub fn on_details(self, response: Result<TransactionDetailsResponse>) -> Self {
+    self.script.push("details", response);
+    self
+  }
+
+  pub fn calls(&self) -> Vec<Call> {
+    self.script.calls()
+  }
+}
+
+#[async_trait]
+impl TransactionsClient for MockTransactions {
+  async fn list(&self, account_id_key: &str, params: ListTransactionsRequest<'_>) -> Result<TransactionListResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "params": args(&params) });
+    self.script.call("MockTransactions", "list", args)
+  }
+
+  async fn details(&self, account_id_key: &str, tranid: &str, store_id: &str) -> Result<TransactionDetailsResponse> {
+    let args = json!({ "accountIdKey": account_id_key, "tranid": tranid, "storeId": store_id });
+    self.script.call("MockTransactions", "details", args)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::anyhow;
+  use serde_json::json;
+
+  use super::{Call, MockAccounts, MockOrders};
+  use crate::accounts::{Account, BalanceRequest};
+  use crate::clients::{AccountsClient, OrdersClient};
+  use crate::orders::{CancelOrderRequest, CancelOrderResponse};
+
+  #[tokio::test]
+  async fn replays_the_scripted_responses_in_order() {
+    let accounts = MockAccounts::new()
+      .on_list(Ok(vec![Account {
+        account_id_key: "key".to_string(),
+        ..Default::default()
+      }]))
+      .on_list(Err(anyhow!("unavailable")));
+
+    assert_eq!(accounts.list().await.unwrap()[0].account_id_key, "key");
+    assert_eq!(accounts.list().await.unwrap_err().to_string(), "unavailable");
+    assert!(accounts.list().await.is_err(), "fails once the script runs out");
+    assert!(accounts.balance("key", BalanceRequest::default()).await.is_err());
+    assert_eq!(accounts.calls().len(), 4);
+  }
+
+  #[tokio::test]
+  async fn records_the_calls() {
+    let orders = MockOrders::new().on_cancel(Ok(CancelOrderResponse {
+      order_id: 7,
+      ..Default::default()
+    }));
+    let client: &dyn OrdersClient = &orders;
+    let resp = client.cancel("key", CancelOrderRequest { order_id: 7 }).await.unwrap();
+    assert_eq!(resp.order_id, 7);
+    assert_eq!(
+      orders.calls(),
+      vec![Call {
+        method: "cancel",
+        args: json!({ "accountIdKey": "key", "params": { "orderId": 7 } }),
+      }]
+    );
+  }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use http::Method;
 use std::sync::Arc;
 
-use crate::{qs_params, session::CallbackProvider, Product, Session, SortOrder, Store};
+use crate::{qs_params, Product, Session, SortOrder, Store};
 
 pub struct Api<T: Store> {
   session: Arc<Session<T>>,
@@ -20,7 +20,6 @@ where
     &self,
     account_id_key: &'a str,
     params: ListTransactionsRequest<'a>,
-    callbacks: impl CallbackProvider,
   ) -> Result<TransactionListResponse> {
     let orders: serde_json::Value = self
       .session
@@ -28,7 +27,6 @@ where
         Method::GET,
         format!("/v1/accounts/{}/transactions", account_id_key),
         qs_params(&params)?,
-        callbacks,
       )
       .await?;
     debug!("orders json: {}", serde_json::to_string_pretty(&orders)?);
@@ -42,7 +40,6 @@ where
     account_id_key: &'a str,
     tranid: &'a str,
     store_id: &'a str,
-    callbacks: impl CallbackProvider,
   ) -> Result<TransactionDetailsResponse> {
     let orders: serde_json::Value = self
       .session
@@ -54,7 +51,6 @@ where
         } else {
           Some(vec![("storeId", store_id)])
         },
-        callbacks,
       )
       .await?;
     debug!("orders json: {}", serde_json::to_string_pretty(&orders)?);
@@ -90,6 +86,7 @@ pub struct TransactionListResponse {
 pub struct TransactionDetailsResponse {
   pub transaction_id: i64,
   pub account_id: String,
+  #[serde(alias = "transactionDate")]
   pub tranaction_date: i64,
   pub postdate: i64,
   pub amount: f64,
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use http::{Request, Response};
+use hyper::{
+  client::{connect::dns::GaiResolver, HttpConnector},
+  Body, Client,
+};
+use hyper_tls::HttpsConnector;
+
+pub(crate) type HttpClient = Client<HttpsConnector<HttpConnector<GaiResolver>>, Body>;
+
+/// Sends the requests of a [`Session`](crate::Session), after the middlewares ran.
+///
+/// The session uses a hyper client by default, [`Session::with_transport`](crate::Session::with_transport) swaps it
+/// for example for the cassettes in [`crate::cassette`].
+#[async_trait]
+pub trait Transport: Send + Sync {
+  async fn send(&self, req: Request<Body>) -> Result<Response<Body>>;
+}
+
+#[async_trait]
+impl Transport for HttpClient {
+  async fn send(&self, req: Request<Body>) -> Result<Response<Body>> {
+    Ok(self.request(req).await?)
+  }
+}
+
+#[async_trait]
+impl<T> Transport for Arc<T>
+where
+  T: Transport + ?Sized,
+{
+  async fn send(&self, req: Request<Body>) -> Result<Response<Body>> {
+    self.as_ref().send(req).await
+  }
+}
+
+pub(crate) fn https_client() -> HttpClient {
+  Client::builder().build(HttpsConnector::new())
+}
@@ -1,4 +1,5 @@
 use anyhow::{anyhow, Result};
+use async_trait::async_trait;
 use byteorder::{ByteOrder, LittleEndian};
 use secstr::SecUtf8;
 use std::ffi::OsStr;
@@ -8,6 +9,8 @@ use std::os::windows::ffi::OsStrExt;
 use std::slice;
 use std::str;
 use winapi::shared::minwindef::FILETIME;
+use winapi::shared::winerror::ERROR_NOT_FOUND;
+use winapi::um::errhandlingapi::GetLastError;
 use winapi::um::wincred::{
   CredDeleteW, CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_ENTERPRISE, CRED_TYPE_GENERIC, PCREDENTIALW,
   PCREDENTIAL_ATTRIBUTEW,
@@ -15,136 +18,167 @@ use winapi::um::wincred::{
 
 use crate::Store;
 
-#[derive(Debug)]
+/// A store backed by the Windows Credential Manager, the target of a credential is `key.namespace`.
+#[derive(Debug, Clone, Default)]
 pub struct KeychainStore;
 
+impl KeychainStore {
+  pub async fn new() -> Result<Self> {
+    Ok(Self)
+  }
+}
+
+#[async_trait]
 impl Store for KeychainStore {
-  fn put(
+  async fn put(
     &self,
     namespace: impl Into<String> + Send,
     key: impl Into<String> + Send,
     value: impl Into<SecUtf8> + Send,
   ) -> Result<()> {
-    let ns: String = namespace.into();
-    let k: String = key.into();
-
-    // Setting values of credential
-
-    let flags = 0;
-    let cred_type = CRED_TYPE_GENERIC;
-    let target_name: String = [k.as_str(), ns.as_str()].join(".");
-    let mut target_name = to_wstr(&target_name);
-
-    // empty string for comments, and target alias,
-    // I don't use here
-    let label = format!("secret for etradectl {}@{}", &k, &ns);
-    let mut empty_str = to_wstr(&label);
-
-    // Ignored by CredWriteW
-    let last_written = FILETIME {
-      dwLowDateTime: 0,
-      dwHighDateTime: 0,
-    };
-
-    // In order to allow editing of the password
-    // from within Windows, the password must be
-    // transformed into utf16. (but because it's a
-    // blob, it then needs to be passed to windows
-    // as an array of bytes).
-    let blob_u16 = to_wstr_no_null(value.into().unsecure());
-    let mut blob = vec![0; blob_u16.len() * 2];
-    LittleEndian::write_u16_into(&blob_u16, &mut blob);
-
-    let blob_len = blob.len() as u32;
-    let persist = CRED_PERSIST_ENTERPRISE;
-    let attribute_count = 0;
-    let attributes: PCREDENTIAL_ATTRIBUTEW = std::ptr::null_mut();
-    let mut username = to_wstr(&k);
-
-    let mut credential = CREDENTIALW {
-      Flags: flags,
-      Type: cred_type,
-      TargetName: target_name.as_mut_ptr(),
-      Comment: empty_str.as_mut_ptr(),
-      LastWritten: last_written,
-      CredentialBlobSize: blob_len,
-      CredentialBlob: blob.as_mut_ptr(),
-      Persist: persist,
-      AttributeCount: attribute_count,
-      Attributes: attributes,
-      TargetAlias: empty_str.as_mut_ptr(),
-      UserName: username.as_mut_ptr(),
-    };
-    // raw pointer to credential, is coerced from &mut
-    let pcredential: PCREDENTIALW = &mut credential;
-
-    // Call windows API
-    match unsafe { CredWriteW(pcredential, 0) } {
-      0 => Err(anyhow!("windows vault error")),
-      _ => Ok(()),
-    }
+    let (ns, k, value) = (namespace.into(), key.into(), value.into());
+    tokio::task::spawn_blocking(move || write(&ns, &k, &value)).await?
+  }
+
+  async fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
+    let target_name = target(namespace.as_ref(), key.as_ref());
+    tokio::task::spawn_blocking(move || delete(&target_name)).await?
   }
 
-  fn del(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<()> {
-    let target_name: String = [key.as_ref(), namespace.as_ref()].join(".");
+  async fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
+    let target_name = target(namespace.as_ref(), key.as_ref());
+    tokio::task::spawn_blocking(move || read(&target_name)).await?
+  }
+}
 
-    let cred_type = CRED_TYPE_GENERIC;
-    let target_name = to_wstr(&target_name);
+fn target(namespace: &str, key: &str) -> String {
+  [key, namespace].join(".")
+}
 
-    match unsafe { CredDeleteW(target_name.as_ptr(), cred_type, 0) } {
-      0 => Err(anyhow!("windows vault error")),
-      _ => Ok(()),
-    }
+/// Creates the credential or replaces the existing one.
+fn write(ns: &str, k: &str, value: &SecUtf8) -> Result<()> {
+  // Setting values of credential
+
+  let flags = 0;
+  let cred_type = CRED_TYPE_GENERIC;
+  let target_name = target(ns, k);
+  let mut target_name = to_wstr(&target_name);
+
+  // empty string for comments, and target alias,
+  // I don't use here
+  let label = format!("secret for etradectl {}@{}", k, ns);
+  let mut empty_str = to_wstr(&label);
+
+  // Ignored by CredWriteW
+  let last_written = FILETIME {
+    dwLowDateTime: 0,
+    dwHighDateTime: 0,
+  };
+
+  // In order to allow editing of the password
+  // from within Windows, the password must be
+  // transformed into utf16. (but because it's a
+  // blob, it then needs to be passed to windows
+  // as an array of bytes).
+  let blob_u16 = to_wstr_no_null(value.unsecure());
+  let mut blob = vec![0; blob_u16.len() * 2];
+  LittleEndian::write_u16_into(&blob_u16, &mut blob);
+
+  let blob_len = blob.len() as u32;
+  let persist = CRED_PERSIST_ENTERPRISE;
+  let attribute_count = 0;
+  let attributes: PCREDENTIAL_ATTRIBUTEW = std::ptr::null_mut();
+  let mut username = to_wstr(k);
+
+  let mut credential = CREDENTIALW {
+    Flags: flags,
+    Type: cred_type,
+    TargetName: target_name.as_mut_ptr(),
+    Comment: empty_str.as_mut_ptr(),
+    LastWritten: last_written,
+    CredentialBlobSize: blob_len,
+    CredentialBlob: blob.as_mut_ptr(),
+    Persist: persist,
+    AttributeCount: attribute_count,
+    Attributes: attributes,
+    TargetAlias: empty_str.as_mut_ptr(),
+    UserName: username.as_mut_ptr(),
+  };
+  // raw pointer to credential, is coerced from &mut
+  let pcredential: PCREDENTIALW = &mut credential;
+
+  // Call windows API
+  match unsafe { CredWriteW(pcredential, 0) } {
+    0 => Err(vault_error()),
+    _ => Ok(()),
   }
+}
+
+/// Deletes the credential, a missing credential is not an error.
+fn delete(target_name: &str) -> Result<()> {
+  let cred_type = CRED_TYPE_GENERIC;
+  let target_name = to_wstr(target_name);
 
-  fn get(&self, namespace: impl AsRef<str> + Send, key: impl AsRef<str> + Send) -> Result<Option<SecUtf8>> {
-    // passing uninitialized pcredential.
-    // Should be ok; it's freed by a windows api
-    // call CredFree.
-    let mut pcredential = MaybeUninit::uninit();
-
-    let target_name: String = [key.as_ref(), namespace.as_ref()].join(".");
-    let target_name = to_wstr(&target_name);
-
-    let cred_type = CRED_TYPE_GENERIC;
-
-    // Windows api call
-    match unsafe { CredReadW(target_name.as_ptr(), cred_type, 0, pcredential.as_mut_ptr()) } {
-      0 => Err(anyhow!("windows vault error")),
-      _ => {
-        let pcredential = unsafe { pcredential.assume_init() };
-        // Dereferencing pointer to credential
-        let credential: CREDENTIALW = unsafe { *pcredential };
-
-        // get blob by creating an array from the pointer
-        // and the length reported back from the credential
-        let blob_pointer: *const u8 = credential.CredentialBlob;
-        let blob_len: usize = credential.CredentialBlobSize as usize;
-
-        // blob needs to be transformed from bytes to an
-        // array of u16, which will then be transformed into
-        // a utf8 string. As noted above, this is to allow
-        // editing of the password from within the vault order
-        // or other windows programs, which operate in utf16
-        let blob: &[u8] = unsafe { slice::from_raw_parts(blob_pointer, blob_len) };
-        let mut blob_u16 = vec![0; blob_len / 2];
-        LittleEndian::read_u16_into(&blob, &mut blob_u16);
-
-        // Now can get utf8 string from the array
-        let password = String::from_utf16(&blob_u16)
-          .map(|pass| Some(pass.to_string().into()))
-          .map_err(|_| anyhow!("windows vault error"));
-
-        // Free the credential
-        unsafe {
-          CredFree(pcredential as *mut _);
-        }
-
-        password
+  match unsafe { CredDeleteW(target_name.as_ptr(), cred_type, 0) } {
+    0 if unsafe { GetLastError() } == ERROR_NOT_FOUND => Ok(()),
+    0 => Err(vault_error()),
+    _ => Ok(()),
+  }
+}
+
+/// Reads the credential, `None` when it doesn't exist.
+fn read(target_name: &str) -> Result<Option<SecUtf8>> {
+  // passing uninitialized pcredential.
+  // Should be ok; it's freed by a windows api
+  // call CredFree.
+  let mut pcredential = MaybeUninit::uninit();
+
+  let target_name = to_wstr(target_name);
+
+  let cred_type = CRED_TYPE_GENERIC;
+
+  // Windows api call
+  match unsafe { CredReadW(target_name.as_ptr(), cred_type, 0, pcredential.as_mut_ptr()) } {
+    0 if unsafe { GetLastError() } == ERROR_NOT_FOUND => Ok(None),
+    0 => Err(vault_error()),
+    _ => {
+      let pcredential = unsafe { pcredential.assume_init() };
+      // Dereferencing pointer to credential
+      let credential: CREDENTIALW = unsafe { *pcredential };
+
+      // get blob by creating an array from the pointer
+      // and the length reported back from the credential
+      let blob_pointer: *const u8 = credential.CredentialBlob;
+      let blob_len: usize = credential.CredentialBlobSize as usize;
+
+      // blob needs to be transformed from bytes to an
+      // array of u16, which will then be transformed into
+      // a utf8 string. As noted above, this is to allow
+      // editing of the password from within the vault order
+      // or other windows programs, which operate in utf16
+      let blob: &[u8] = unsafe { slice::from_raw_parts(blob_pointer, blob_len) };
+      let mut blob_u16 = vec![0; blob_len / 2];
+      LittleEndian::read_u16_into(blob, &mut blob_u16);
+
+      // Now can get utf8 string from the array
+      let password = String::from_utf16(&blob_u16)
+        .map(|pass| Some(pass.into()))
+        .map_err(|_| anyhow!("windows vault error: the credential is not valid utf-16"));
+
+      // Free the credential
+      unsafe {
+        CredFree(pcredential as *mut _);
       }
+
+      password
     }
   }
 }
+
+fn vault_error() -> anyhow::Error {
+  anyhow!("windows vault error: {}", unsafe { GetLastError() })
+}
+
 // helper function for turning utf8 strings to windows
 // utf16
 fn to_wstr(s: &str) -> Vec<u16> {
@@ -154,3 +188,13 @@ fn to_wstr(s: &str) -> Vec<u16> {
 fn to_wstr_no_null(s: &str) -> Vec<u16> {
   OsStr::new(s).encode_wide().collect()
 }
+
+#[cfg(test)]
+mod tests {
+  use super::KeychainStore;
+
+  #[tokio::test]
+  async fn test_credential_store() {
+    crate::testing::verify_store(KeychainStore::new().await.unwrap()).await
+  }
+}